@@ -0,0 +1,37 @@
+//! ch4 的系统调用错误码。
+//!
+//! 编号与 Linux 一致，系统调用失败时返回错误码的相反数，写作 `-Errno::EINVAL`。
+//! 本章引入地址空间，错误主要来自 `mmap`、`munmap`、`sbrk` 的参数检查和访问用户内存：
+//! 指针不可访问时返回 `EFAULT`，映射范围冲突时返回 `EEXIST`，物理页不够时返回 `ENOMEM`。
+//! 每章都是独立的 crate，各自只列出本章用到的错误码。
+
+use core::ops::Neg;
+
+/// 系统调用错误码，变体名沿用 Linux 的宏名。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 进程不存在
+    ESRCH = 3,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 地址无效
+    EFAULT = 14,
+    /// 已经存在
+    EEXIST = 17,
+    /// 参数无效
+    EINVAL = 22,
+}
+
+impl Neg for Errno {
+    type Output = isize;
+
+    /// 作为系统调用返回值的负数错误码。
+    #[inline]
+    fn neg(self) -> isize {
+        -(self as isize)
+    }
+}
@@ -6,7 +6,9 @@
 #![cfg_attr(target_arch = "riscv64", deny(warnings, missing_docs))]
 #![cfg_attr(not(target_arch = "riscv64"), allow(dead_code, unused_imports))]
 
+mod errno;
 mod process;
+mod user_buffer;
 
 #[macro_use]
 extern crate tg_console;
@@ -101,17 +103,6 @@ extern "C" fn rust_main() -> ! {
     // 建立内核地址空间
     let mut ks = kernel_space(layout, MEMORY, portal_ptr as _);
     let portal_idx = PROTAL_TRANSIT.index_in(Sv39::MAX_LEVEL);
-    // 加载应用程序
-    for (i, elf) in tg_linker::AppMeta::locate().iter().enumerate() {
-        let base = elf.as_ptr() as usize;
-        log::info!("detect app[{i}]: {base:#x}..{:#x}", base + elf.len());
-        if let Some(process) = Process::new(ElfFile::new(elf).unwrap()) {
-            // 映射异界传送门
-            process.address_space.root()[portal_idx] = ks.root()[portal_idx];
-            unsafe { PROCESSES.get_mut().push(process) };
-        }
-    }
-
     // 建立调度栈
     const PAGE: Layout =
         unsafe { Layout::from_size_align_unchecked(2 << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS) };
@@ -122,15 +113,29 @@ extern "C" fn rust_main() -> ! {
         PPN::new(stack as usize >> Sv39::PAGE_BITS),
         build_flags("_WRV"),
     );
+    // 内核地址空间占用的物理页，所有应用程序退出后应该只剩这些
+    let kernel_frames = Sv39Manager::frames();
+    // 加载应用程序
+    for (i, elf) in tg_linker::AppMeta::locate().iter().enumerate() {
+        let base = elf.as_ptr() as usize;
+        log::info!("detect app[{i}]: {base:#x}..{:#x}", base + elf.len());
+        if let Some(process) = Process::new(ElfFile::new(elf).unwrap()) {
+            // 映射异界传送门
+            process.address_space.root()[portal_idx] = Sv39Manager::share(ks.root()[portal_idx]);
+            unsafe { PROCESSES.get_mut().push(process) };
+        }
+    }
+
     // 建立调度线程，目的是划分异常域。调度线程上发生内核异常时会回到这个控制流处理
     let mut scheduling = LocalContext::thread(schedule as *const () as _, false);
     *scheduling.sp_mut() = 1 << 38;
+    *scheduling.a_mut(0) = kernel_frames;
     unsafe { scheduling.execute() };
     log::error!("stval = {:#x}", stval::read());
     panic!("trap from scheduling thread: {:?}", scause::read().cause());
 }
 
-extern "C" fn schedule() -> ! {
+extern "C" fn schedule(kernel_frames: usize) -> ! {
     // 初始化异界传送门
     let portal = unsafe { MultislotPortal::init_transit(PROTAL_TRANSIT.base().val(), 1) };
     // 初始化 syscall
@@ -191,9 +196,21 @@ extern "C" fn schedule() -> ! {
             }
         }
     }
+    check_frames(kernel_frames);
     tg_sbi::shutdown(false)
 }
 
+/// 所有应用程序退出后检查物理页是否全部回收，有页泄漏时以异常方式关机。
+fn check_frames(kernel_frames: usize) {
+    let frames = Sv39Manager::frames();
+    if frames == kernel_frames {
+        log::info!("all user frames reclaimed, {frames} frames in use");
+    } else {
+        log::error!("{frames} frames in use, expected {kernel_frames}");
+        tg_sbi::shutdown(true)
+    }
+}
+
 /// Rust 异常处理函数，以异常方式关机。
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -247,16 +264,41 @@ fn kernel_space(
 
 /// 各种接口库的实现。
 mod impls {
-    use crate::{build_flags, Sv39, PROCESSES};
-    use alloc::alloc::alloc_zeroed;
-    use core::{alloc::Layout, ptr::NonNull};
+    use crate::{
+        errno::Errno,
+        user_buffer::{UserBuffer, UserPtr},
+        Sv39, PROCESSES,
+    };
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc},
+        collections::BTreeMap,
+        string::String,
+        vec::Vec,
+    };
+    use core::{
+        alloc::Layout,
+        cell::UnsafeCell,
+        ops::Range,
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
     use tg_console::log;
     use tg_kernel_vm::{
-        page_table::{MmuMeta, Pte, VAddr, VmFlags, PPN, VPN},
-        PageManager,
+        page_table::{MmuMeta, Pte, VAddr, VmFlags, VmMeta, PPN, VPN},
+        AddressSpace, PageManager,
     };
     use tg_syscall::*;
 
+    /// 所有页管理器持有的物理页数，包括页表和带 `OWNED` 标记的数据页。
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    /// 一次分配了多个页的块：起始物理页号 → (块的页数, 其中还没有释放的页数)。只有一页的不登记。
+    struct FrameBlocks(UnsafeCell<BTreeMap<usize, (usize, usize)>>);
+
+    unsafe impl Sync for FrameBlocks {}
+
+    static BLOCKS: FrameBlocks = FrameBlocks(UnsafeCell::new(BTreeMap::new()));
+
     #[repr(transparent)]
     pub struct Sv39Manager(NonNull<Pte<Sv39>>);
 
@@ -265,13 +307,87 @@ mod impls {
 
         #[inline]
         fn page_alloc<T>(count: usize) -> *mut T {
+            FRAMES.fetch_add(count, Ordering::Relaxed);
+            let ptr = unsafe { alloc_zeroed(Self::block_layout(count)) };
+            if count > 1 {
+                let ppn = ptr as usize >> Sv39::PAGE_BITS;
+                unsafe { &mut *BLOCKS.0.get() }.insert(ppn, (count, count));
+            }
+            ptr.cast()
+        }
+
+        /// 释放从 `ptr` 开始的 `count` 个页。
+        ///
+        /// 这些页可能只是 [`page_alloc`](Self::page_alloc) 一次分配的块中的一部分，
+        /// 块中的页全部释放后才按分配时的大小把整块还给堆。
+        fn page_free<T>(ptr: *mut T, count: usize) {
+            FRAMES.fetch_sub(count, Ordering::Relaxed);
+            let ppn = ptr as usize >> Sv39::PAGE_BITS;
+            let blocks = unsafe { &mut *BLOCKS.0.get() };
+            let (ptr, count) = match blocks.range_mut(..=ppn).next_back() {
+                Some((&start, (pages, live))) if ppn < start + *pages => {
+                    *live -= count;
+                    if *live > 0 {
+                        return;
+                    }
+                    let pages = *pages;
+                    blocks.remove(&start);
+                    ((start << Sv39::PAGE_BITS) as *mut u8, pages)
+                }
+                _ => (ptr.cast(), count),
+            };
+            unsafe { dealloc(ptr, Self::block_layout(count)) }
+        }
+
+        #[inline]
+        fn block_layout(count: usize) -> Layout {
             unsafe {
-                alloc_zeroed(Layout::from_size_align_unchecked(
-                    count << Sv39::PAGE_BITS,
-                    1 << Sv39::PAGE_BITS,
-                ))
+                Layout::from_size_align_unchecked(count << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS)
+            }
+        }
+
+        /// 当前所有页管理器持有的物理页数。
+        pub fn frames() -> usize {
+            FRAMES.load(Ordering::Relaxed)
+        }
+
+        /// 去掉页表项的 `OWNED` 标记，用于把其他地址空间的页表项复制过来：回收页表时不会释放它指向的页。
+        pub fn share(pte: Pte<Sv39>) -> Pte<Sv39> {
+            let flags = unsafe { VmFlags::from_raw(pte.flags().val() & !Self::OWNED.val()) };
+            flags.build_pte(pte.ppn())
+        }
+
+        /// 取消 `range` 的映射，并释放其中带 `OWNED` 标记的页。
+        pub fn unmap(space: &mut AddressSpace<Sv39, Self>, range: Range<VPN<Sv39>>) {
+            let mut frames = Vec::new();
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(ptr) = space.translate::<u8>(vpn.base(), Self::OWNED) {
+                    frames.push(ptr);
+                }
+                vpn += 1;
+            }
+            space.unmap(range);
+            for ptr in frames {
+                Self::page_free(ptr.as_ptr(), 1);
+            }
+        }
+
+        /// 回收页表 `table` 指向的子树：`level` 层的页表项中，带 `OWNED` 标记的中间页表递归回收，
+        /// 带 `OWNED` 标记的叶子页直接释放，最后释放页表本身。
+        unsafe fn drop_table(&mut self, table: NonNull<Pte<Sv39>>, level: usize) {
+            for i in 0..1 << Sv39::LEVEL_BITS[level] {
+                let pte = *table.as_ptr().add(i);
+                if !pte.is_valid() || !self.check_owned(pte) {
+                    continue;
+                }
+                if level > 0 && !Sv39::is_leaf(pte.flags().val()) {
+                    self.drop_table(self.p_to_v(pte.ppn()), level - 1);
+                } else {
+                    self.deallocate(pte, 1);
+                }
             }
-            .cast()
+            Self::page_free(table.as_ptr(), 1);
         }
     }
 
@@ -312,12 +428,26 @@ mod impls {
             NonNull::new(Self::page_alloc(len)).unwrap()
         }
 
-        fn deallocate(&mut self, _pte: Pte<Sv39>, _len: usize) -> usize {
-            todo!()
+        fn deallocate(&mut self, pte: Pte<Sv39>, len: usize) -> usize {
+            if !self.check_owned(pte) {
+                return 0;
+            }
+            Self::page_free(self.p_to_v::<u8>(pte.ppn()).as_ptr(), len);
+            len
         }
 
         fn drop_root(&mut self) {
-            todo!()
+            if self.0 == NonNull::dangling() {
+                return;
+            }
+            unsafe { self.drop_table(self.0, Sv39::MAX_LEVEL) };
+            self.0 = NonNull::dangling();
+        }
+    }
+
+    impl Drop for Sv39Manager {
+        fn drop(&mut self) {
+            self.drop_root();
         }
     }
 
@@ -336,28 +466,20 @@ mod impls {
         fn write(&self, caller: Caller, fd: usize, buf: usize, count: usize) -> isize {
             match fd {
                 STDOUT | STDDEBUG => {
-                    const READABLE: VmFlags<Sv39> = build_flags("RV");
-                    if let Some(ptr) = unsafe { PROCESSES.get_mut() }
+                    let process = unsafe { PROCESSES.get_mut() }
                         .get_mut(caller.entity)
-                        .unwrap()
-                        .address_space
-                        .translate::<u8>(VAddr::new(buf), READABLE)
-                    {
-                        print!("{}", unsafe {
-                            core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-                                ptr.as_ptr(),
-                                count,
-                            ))
-                        });
+                        .unwrap();
+                    if let Some(data) = UserBuffer::new(buf, count).read(&process.address_space) {
+                        print!("{}", String::from_utf8_lossy(&data));
                         count as _
                     } else {
                         log::error!("ptr not readable");
-                        -1
+                        -Errno::EFAULT
                     }
                 }
                 _ => {
                     log::error!("unsupported fd: {fd}");
-                    -1
+                    -Errno::EBADF
                 }
             }
         }
@@ -374,10 +496,10 @@ mod impls {
                 if let Some(old_brk) = process.change_program_brk(size as isize) {
                     old_brk as isize
                 } else {
-                    -1
+                    -Errno::ENOMEM
                 }
             } else {
-                -1
+                -Errno::ESRCH
             }
         }
     }
@@ -392,59 +514,48 @@ mod impls {
     impl Clock for SyscallContext {
         #[inline]
         fn clock_gettime(&self, caller: Caller, clock_id: ClockId, tp: usize) -> isize {
-            const WRITABLE: VmFlags<Sv39> = build_flags("W_V");
             match clock_id {
                 ClockId::CLOCK_MONOTONIC => {
-                    if let Some(mut ptr) = unsafe { PROCESSES.get_mut() }
+                    let process = unsafe { PROCESSES.get_mut() }
                         .get_mut(caller.entity)
-                        .unwrap()
-                        .address_space
-                        .translate::<TimeSpec>(VAddr::new(tp), WRITABLE)
-                    {
-                        let time = riscv::register::time::read() * 10000 / 125;
-                        *unsafe { ptr.as_mut() } = TimeSpec {
-                            tv_sec: time / 1_000_000_000,
-                            tv_nsec: time % 1_000_000_000,
-                        };
-                        0
-                    } else {
-                        log::error!("ptr not readable");
-                        -1
+                        .unwrap();
+                    let time = riscv::register::time::read() * 10000 / 125;
+                    let time = TimeSpec {
+                        tv_sec: time / 1_000_000_000,
+                        tv_nsec: time % 1_000_000_000,
+                    };
+                    match UserPtr::new(tp).write(&process.address_space, time) {
+                        Some(()) => 0,
+                        None => {
+                            log::error!("ptr not writable");
+                            -Errno::EFAULT
+                        }
                     }
                 }
-                _ => -1,
+                _ => -Errno::EINVAL,
             }
         }
     }
 
     impl Trace for SyscallContext {
         fn trace(&self, caller: Caller, trace_request: usize, id: usize, data: usize) -> isize {
-            const READABLE: VmFlags<Sv39> = build_flags("U__RV");
-            const WRITABLE: VmFlags<Sv39> = build_flags("U_W_V");
             let process = unsafe { PROCESSES.get_mut() }
                 .get_mut(caller.entity)
                 .unwrap();
             match trace_request {
                 // request=0: 读取用户地址 id 处的 1 字节
-                0 => {
-                    if let Some(ptr) = process.address_space.translate::<u8>(VAddr::new(id), READABLE) {
-                        (unsafe { *ptr.as_ptr() }) as isize
-                    } else {
-                        -1
-                    }
-                }
+                0 => match UserPtr::<u8>::new(id).read(&process.address_space) {
+                    Some(byte) => byte as isize,
+                    None => -Errno::EFAULT,
+                },
                 // request=1: 向用户地址 id 处写入 data 的低 8 位
-                1 => {
-                    if let Some(mut ptr) = process.address_space.translate::<u8>(VAddr::new(id), WRITABLE) {
-                        unsafe { *ptr.as_mut() = data as u8 };
-                        0
-                    } else {
-                        -1
-                    }
-                }
+                1 => match UserPtr::new(id).write(&process.address_space, data as u8) {
+                    Some(()) => 0,
+                    None => -Errno::EFAULT,
+                },
                 // request=2: 返回目标 syscall 的调用次数（由调度器通过 caller.flow 传入）
                 2 => caller.flow as isize,
-                _ => -1,
+                _ => -Errno::EINVAL,
             }
         }
     }
@@ -464,15 +575,15 @@ mod impls {
 
             // addr 必须页对齐
             if addr % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
             // prot 低 3 位不能全为 0
             if prot & 0x7 == 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
             // prot 高位必须为 0
             if prot & !0x7 != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
 
             // len 向上取整到页
@@ -491,7 +602,7 @@ mod impls {
             // 检查是否与已有映射重叠
             for area in &process.address_space.areas {
                 if start_vpn < area.end && end_vpn > area.start {
-                    return -1;
+                    return -Errno::EEXIST;
                 }
             }
 
@@ -519,7 +630,7 @@ mod impls {
 
             // addr 必须页对齐
             if addr % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
 
             let len_aligned = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
@@ -541,12 +652,12 @@ mod impls {
                     vpn >= area.start && vpn < area.end
                 });
                 if !covered {
-                    return -1;
+                    return -Errno::EINVAL;
                 }
                 vpn = vpn + 1;
             }
 
-            process.address_space.unmap(start_vpn..end_vpn);
+            Sv39Manager::unmap(&mut process.address_space, start_vpn..end_vpn);
             0
         }
     }
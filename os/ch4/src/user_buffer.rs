@@ -0,0 +1,108 @@
+//! 访问用户内存。
+//!
+//! 用户传入的缓冲区在虚拟地址上连续，但可能跨越多个物理上不连续的页。
+//! 这里的类型逐页翻译用户地址并检查每一页的权限，只要有一页不能按要求访问，整个操作就失败，
+//! 不会读写缓冲区以外的内核内存。
+
+use crate::{build_flags, Sv39, Sv39Manager};
+use alloc::vec::Vec;
+use core::{marker::PhantomData, ptr::NonNull};
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VAddr, VmFlags},
+    AddressSpace,
+};
+
+/// 用户可读。
+const READABLE: VmFlags<Sv39> = build_flags("U__RV");
+/// 用户可写。
+const WRITABLE: VmFlags<Sv39> = build_flags("U_W_V");
+
+/// 用户地址空间中的一段缓冲区。
+#[derive(Clone, Copy)]
+pub struct UserBuffer {
+    addr: usize,
+    len: usize,
+}
+
+impl UserBuffer {
+    /// 起始于用户地址 `addr`，长度为 `len` 字节的缓冲区。
+    #[inline]
+    pub const fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+
+    /// 逐页翻译缓冲区，按顺序返回覆盖整个缓冲区的内核地址和长度。
+    ///
+    /// 缓冲区地址溢出，或者其中任何一页不能以 `flags` 访问时返回 `None`。
+    fn translate(
+        &self,
+        space: &AddressSpace<Sv39, Sv39Manager>,
+        flags: VmFlags<Sv39>,
+    ) -> Option<Vec<(NonNull<u8>, usize)>> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        let end = self.addr.checked_add(self.len)?;
+        let mut slices = Vec::new();
+        let mut addr = self.addr;
+        while addr < end {
+            let len = (PAGE_SIZE - addr % PAGE_SIZE).min(end - addr);
+            slices.push((space.translate(VAddr::new(addr), flags)?, len));
+            addr += len;
+        }
+        Some(slices)
+    }
+
+    /// 读出缓冲区的全部内容，缓冲区不可读时返回 `None`。
+    pub fn read(&self, space: &AddressSpace<Sv39, Sv39Manager>) -> Option<Vec<u8>> {
+        let slices = self.translate(space, READABLE)?;
+        let mut data = Vec::with_capacity(slices.iter().map(|(_, len)| len).sum());
+        for (ptr, len) in slices {
+            data.extend_from_slice(unsafe { core::slice::from_raw_parts(ptr.as_ptr(), len) });
+        }
+        Some(data)
+    }
+
+    /// 从缓冲区开头写入 `data`，超出缓冲区的部分被丢弃。
+    ///
+    /// 整个缓冲区都可写才会写入，否则返回 `None`，用户内存保持不变。
+    pub fn write(&self, space: &AddressSpace<Sv39, Sv39Manager>, data: &[u8]) -> Option<()> {
+        let mut data = data;
+        for (ptr, len) in self.translate(space, WRITABLE)? {
+            let len = len.min(data.len());
+            unsafe { ptr.as_ptr().copy_from_nonoverlapping(data.as_ptr(), len) };
+            data = &data[len..];
+        }
+        Some(())
+    }
+}
+
+/// 用户地址空间中的一个 `T`，可以不对齐，也可以跨页。
+///
+/// `T` 的值按字节复制，任意字节组合都应当是合法的 `T`。
+pub struct UserPtr<T> {
+    buffer: UserBuffer,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    /// 指向用户地址 `addr` 的指针。
+    #[inline]
+    pub const fn new(addr: usize) -> Self {
+        Self {
+            buffer: UserBuffer::new(addr, size_of::<T>()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// 读出用户内存中的值。
+    pub fn read(&self, space: &AddressSpace<Sv39, Sv39Manager>) -> Option<T> {
+        let data = self.buffer.read(space)?;
+        Some(unsafe { core::ptr::read_unaligned(data.as_ptr().cast()) })
+    }
+
+    /// 把 `value` 写入用户内存。
+    pub fn write(&self, space: &AddressSpace<Sv39, Sv39Manager>, value: T) -> Option<()> {
+        let data =
+            unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        self.buffer.write(space, data)
+    }
+}
@@ -0,0 +1,47 @@
+//! ch5 的系统调用错误码。
+//!
+//! 编号与 Linux 一致，系统调用失败时返回错误码的相反数，写作 `-Errno::EINVAL`。
+//! 在 ch4 的基础上，进程管理带来了新的失败原因：`exec` 找不到应用时返回 `ENOENT`，
+//! ELF 无法加载时返回 `ENOEXEC`，参数过长时返回 `E2BIG`，`waitpid` 没有可等的子进程时返回 `ECHILD`，
+//! 抬高栈的硬限制时返回 `EPERM`。
+
+use core::ops::Neg;
+
+/// 系统调用错误码，变体名沿用 Linux 的宏名。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 操作不允许
+    EPERM = 1,
+    /// 文件或目录不存在
+    ENOENT = 2,
+    /// 进程不存在
+    ESRCH = 3,
+    /// 参数列表过长
+    E2BIG = 7,
+    /// 可执行文件格式错误
+    ENOEXEC = 8,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 没有子进程
+    ECHILD = 10,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 地址无效
+    EFAULT = 14,
+    /// 已经存在
+    EEXIST = 17,
+    /// 参数无效
+    EINVAL = 22,
+}
+
+impl Neg for Errno {
+    type Output = isize;
+
+    /// 作为系统调用返回值的负数错误码。
+    #[inline]
+    fn neg(self) -> isize {
+        -(self as isize)
+    }
+}
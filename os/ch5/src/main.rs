@@ -6,8 +6,14 @@
 #![cfg_attr(target_arch = "riscv64", deny(warnings, missing_docs))]
 #![cfg_attr(not(target_arch = "riscv64"), allow(dead_code, unused_imports))]
 
+mod args;
+mod elf;
+mod errno;
 mod process;
 mod processor;
+mod sched;
+mod stack;
+mod user_buffer;
 
 #[macro_use]
 extern crate tg_console;
@@ -15,9 +21,11 @@ extern crate tg_console;
 extern crate alloc;
 
 use crate::{
+    args::InitStack,
     impls::{Console, Sv39Manager, SyscallContext},
     process::Process,
-    processor::{ProcManager, PROCESSOR},
+    processor::{PManager, ProcManager, PROCESSOR},
+    stack::STACK_TOP,
 };
 use alloc::{alloc::alloc, collections::BTreeMap};
 use core::{alloc::Layout, cell::UnsafeCell, ffi::CStr, mem::MaybeUninit};
@@ -35,7 +43,7 @@ use tg_kernel_vm::{
 };
 use tg_sbi;
 use tg_syscall::Caller;
-use tg_task_manage::{PManager, ProcId};
+use tg_task_manage::ProcId;
 use xmas_elf::ElfFile;
 
 /// 构建 VmFlags。
@@ -53,6 +61,30 @@ fn parse_flags(s: &str) -> Result<VmFlags<Sv39>, ()> {
 #[cfg(not(target_arch = "riscv64"))]
 use stub::{build_flags, parse_flags};
 
+/// 时间片长度（`time` 计数），默认 12500，可以在构建时用环境变量 `TIME_SLICE` 修改。
+const TIME_SLICE: u64 = match option_env!("TIME_SLICE") {
+    Some(s) => parse_u64(s),
+    None => 12500,
+};
+
+/// 在编译期解析十进制整数。
+const fn parse_u64(s: &str) -> u64 {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "TIME_SLICE must not be empty");
+    let mut value = 0u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "TIME_SLICE must be a decimal number"
+        );
+        value = value * 10 + (bytes[i] - b'0') as u64;
+        i += 1;
+    }
+    assert!(value > 0, "TIME_SLICE must be positive");
+    value
+}
+
 // 应用程序内联进来。
 #[cfg(target_arch = "riscv64")]
 core::arch::global_asm!(include_str!(env!("APP_ASM")));
@@ -127,6 +159,8 @@ extern "C" fn rust_main() -> ! {
     assert!(portal_layout.size() < 1 << Sv39::PAGE_BITS);
     // 建立内核地址空间
     kernel_space(layout, MEMORY, portal_ptr as _);
+    // 内核地址空间占用的物理页，所有进程退出后应该只剩这些
+    let kernel_frames = Sv39Manager::frames();
     // 初始化异界传送门
     let portal = unsafe { MultislotPortal::init_transit(PROTAL_TRANSIT.base().val(), 1) };
     // 初始化 syscall
@@ -137,41 +171,82 @@ extern "C" fn rust_main() -> ! {
     tg_syscall::init_memory(&SyscallContext);
     // 加载初始进程
     let initproc_data = APPS.get("initproc").unwrap();
-    if let Some(process) = Process::from_elf(ElfFile::new(initproc_data).unwrap()) {
+    if let Ok(mut process) = Process::from_elf(ElfFile::new(initproc_data).unwrap()) {
+        process.push_args(&InitStack::new(STACK_TOP, &["initproc"], &[]).unwrap());
         PROCESSOR.get_mut().set_manager(ProcManager::new());
         PROCESSOR
             .get_mut()
             .add(process.pid, process, ProcId::from_usize(usize::MAX));
     }
+    // 打开时钟中断
+    unsafe { sie::set_stimer() };
     loop {
         let processor: *mut PManager<Process, ProcManager> = PROCESSOR.get_mut() as *mut _;
         if let Some(task) = unsafe { (*processor).find_next() } {
+            let pid = task.pid;
+            let start = time::read64();
+            tg_sbi::set_timer(start + unsafe { (*processor).manager().time_slice(pid) });
             unsafe { task.context.execute(portal, ()) };
-            match scause::read().cause() {
+            let elapsed = time::read64() - start;
+            let cause = scause::read().cause();
+            let preempted = matches!(
+                cause,
+                scause::Trap::Interrupt(scause::Interrupt::SupervisorTimer)
+            );
+            unsafe { (*processor).manager().charge(pid, elapsed, preempted) };
+            match cause {
+                scause::Trap::Interrupt(scause::Interrupt::SupervisorTimer) => {
+                    tg_sbi::set_timer(u64::MAX);
+                    unsafe { (*processor).make_current_suspend() };
+                }
                 scause::Trap::Exception(scause::Exception::UserEnvCall) => {
                     use tg_syscall::{SyscallId as Id, SyscallResult as Ret};
                     let ctx = &mut task.context.context;
                     ctx.move_next();
-                    let id: Id = ctx.a(7).into();
+                    let nr = ctx.a(7);
+                    let id: Id = nr.into();
                     let args = [ctx.a(0), ctx.a(1), ctx.a(2), ctx.a(3), ctx.a(4), ctx.a(5)];
                     match tg_syscall::handle(Caller { entity: 0, flow: 0 }, id, args) {
                         Ret::Done(ret) => match id {
-                            Id::EXIT => unsafe { (*processor).make_current_exited(ret) },
+                            Id::EXIT => unsafe { exit_current(&mut *processor, ret) },
                             _ => {
                                 let ctx = &mut task.context.context;
                                 *ctx.a_mut(0) = ret as _;
                                 unsafe { (*processor).make_current_suspend() };
                             }
                         },
-                        Ret::Unsupported(_) => {
-                            log::info!("id = {id:?}");
-                            unsafe { (*processor).make_current_exited(-2) };
-                        }
+                        Ret::Unsupported(_) => match impls::handle_extra(task, nr, args) {
+                            Some(ret) => {
+                                *task.context.context.a_mut(0) = ret as _;
+                                unsafe { (*processor).make_current_suspend() };
+                            }
+                            None => {
+                                log::info!("id = {id:?}");
+                                unsafe { exit_current(&mut *processor, -2) };
+                            }
+                        },
+                    }
+                }
+                scause::Trap::Exception(
+                    e @ (scause::Exception::StorePageFault
+                    | scause::Exception::LoadPageFault
+                    | scause::Exception::InstructionPageFault),
+                ) => {
+                    let addr = stval::read();
+                    let store = matches!(e, scause::Exception::StorePageFault);
+                    if task.handle_page_fault(VAddr::<Sv39>::new(addr).floor(), store) {
+                        unsafe { (*processor).make_current_suspend() };
+                    } else {
+                        log::error!(
+                            "unsupported trap: {e:?}, stval = {addr:#x}, sepc = {:#x}",
+                            task.context.context.pc()
+                        );
+                        unsafe { exit_current(&mut *processor, -3) };
                     }
                 }
                 e => {
                     log::error!("unsupported trap: {e:?}");
-                    unsafe { (*processor).make_current_exited(-3) };
+                    unsafe { exit_current(&mut *processor, -3) };
                 }
             }
         } else {
@@ -179,9 +254,33 @@ extern "C" fn rust_main() -> ! {
             break;
         }
     }
+    check_frames(kernel_frames);
     tg_sbi::shutdown(false)
 }
 
+/// 当前进程以 `exit_code` 退出，记录它占用处理器的时间。
+fn exit_current(processor: &mut PManager<Process, ProcManager>, exit_code: isize) {
+    let task = processor.current().unwrap();
+    log::info!(
+        "process {} exited with code {exit_code}, {} ticks, {} cycles",
+        task.pid.get_usize(),
+        task.ticks,
+        task.run_time,
+    );
+    processor.make_current_exited(exit_code);
+}
+
+/// 所有进程退出后检查物理页是否全部回收，有页泄漏时以异常方式关机。
+fn check_frames(kernel_frames: usize) {
+    let frames = Sv39Manager::frames();
+    if frames == kernel_frames {
+        log::info!("all user frames reclaimed, {frames} frames in use");
+    } else {
+        log::error!("{frames} frames in use, expected {kernel_frames}");
+        tg_sbi::shutdown(true)
+    }
+}
+
 /// Rust 异常处理函数，以异常方式关机。
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -225,43 +324,319 @@ fn kernel_space(layout: tg_linker::KernelLayout, memory: usize, portal: usize) {
     unsafe { KERNEL_SPACE.write(space) };
 }
 
-/// 映射异界传送门。
+/// 映射异界传送门。传送门所在的页表属于内核地址空间，用户地址空间回收时不释放。
 fn map_portal(space: &AddressSpace<Sv39, Sv39Manager>) {
     let portal_idx = PROTAL_TRANSIT.index_in(Sv39::MAX_LEVEL);
-    space.root()[portal_idx] = unsafe { KERNEL_SPACE.assume_init_ref() }.root()[portal_idx];
+    let pte = unsafe { KERNEL_SPACE.assume_init_ref() }.root()[portal_idx];
+    space.root()[portal_idx] = Sv39Manager::share(pte);
 }
 
 /// 各种接口库的实现。
 mod impls {
     use crate::{
-        build_flags, process::Process as ProcStruct, processor::ProcManager, Sv39, APPS, PROCESSOR,
+        args::{InitStack, ARG_MAX},
+        build_flags,
+        errno::Errno,
+        process::Process as ProcStruct,
+        processor::{PManager, ProcManager},
+        sched::SchedInfo,
+        stack::{RLimit, RLIMIT_STACK, STACKS_BOTTOM, STACK_TOP},
+        user_buffer::{UserBuffer, UserPtr},
+        Sv39, APPS, PROCESSOR,
+    };
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc},
+        collections::BTreeMap,
+        string::String,
+        vec,
+        vec::Vec,
+    };
+    use core::{
+        alloc::Layout,
+        ops::Range,
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering},
     };
-    use alloc::alloc::alloc_zeroed;
-    use core::{alloc::Layout, ptr::NonNull};
     use tg_console::log;
     use tg_kernel_vm::{
-        page_table::{MmuMeta, Pte, VAddr, VmFlags, PPN, VPN},
-        PageManager,
+        page_table::{MmuMeta, Pte, VAddr, VmFlags, VmMeta, PPN, VPN},
+        AddressSpace, PageManager,
     };
     use tg_syscall::*;
-    use tg_task_manage::{PManager, ProcId};
+    use tg_task_manage::ProcId;
     use xmas_elf::ElfFile;
 
+    /// 所有页管理器持有的物理页数，包括页表和带 `OWNED` 标记的数据页。
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+    /// 被多个地址空间共享的页（物理页号）和映射它的页表项数，只记录数量大于 1 的页。
+    static SHARED: spin::Mutex<BTreeMap<usize, usize>> = spin::Mutex::new(BTreeMap::new());
+
+    /// 一次分配了多个页的块：起始物理页号 → (块的页数, 其中还没有释放的页数)。只有一页的不登记。
+    static BLOCKS: spin::Mutex<BTreeMap<usize, (usize, usize)>> = spin::Mutex::new(BTreeMap::new());
+
     #[repr(transparent)]
     pub struct Sv39Manager(NonNull<Pte<Sv39>>);
 
     impl Sv39Manager {
         const OWNED: VmFlags<Sv39> = unsafe { VmFlags::from_raw(1 << 8) };
+        /// 写时复制的页，页表项去掉了写权限
+        const COW: VmFlags<Sv39> = unsafe { VmFlags::from_raw(1 << 9) };
+        const WRITABLE: VmFlags<Sv39> = unsafe { VmFlags::from_raw(1 << 2) };
 
         #[inline]
         fn page_alloc<T>(count: usize) -> *mut T {
+            FRAMES.fetch_add(count, Ordering::Relaxed);
+            let ptr = unsafe { alloc_zeroed(Self::block_layout(count)) };
+            if count > 1 {
+                let ppn = ptr as usize >> Sv39::PAGE_BITS;
+                BLOCKS.lock().insert(ppn, (count, count));
+            }
+            ptr.cast()
+        }
+
+        /// 释放从 `ptr` 开始的 `count` 个页。
+        ///
+        /// 这些页可能只是 [`page_alloc`](Self::page_alloc) 一次分配的块中的一部分，
+        /// 块中的页全部释放后才按分配时的大小把整块还给堆。
+        fn page_free<T>(ptr: *mut T, count: usize) {
+            FRAMES.fetch_sub(count, Ordering::Relaxed);
+            let ppn = ptr as usize >> Sv39::PAGE_BITS;
+            let blocks = &mut BLOCKS.lock();
+            let (ptr, count) = match blocks.range_mut(..=ppn).next_back() {
+                Some((&start, (pages, live))) if ppn < start + *pages => {
+                    *live -= count;
+                    if *live > 0 {
+                        return;
+                    }
+                    let pages = *pages;
+                    blocks.remove(&start);
+                    ((start << Sv39::PAGE_BITS) as *mut u8, pages)
+                }
+                _ => (ptr.cast(), count),
+            };
+            unsafe { dealloc(ptr, Self::block_layout(count)) }
+        }
+
+        #[inline]
+        fn block_layout(count: usize) -> Layout {
             unsafe {
-                alloc_zeroed(Layout::from_size_align_unchecked(
-                    count << Sv39::PAGE_BITS,
-                    1 << Sv39::PAGE_BITS,
-                ))
+                Layout::from_size_align_unchecked(count << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS)
+            }
+        }
+
+        #[inline]
+        fn frame_ptr<T>(ppn: PPN<Sv39>) -> *mut T {
+            unsafe { VPN::<Sv39>::new(ppn.val()).base().as_mut_ptr() }
+        }
+
+        #[inline]
+        fn frame_ppn<T>(ptr: *mut T) -> PPN<Sv39> {
+            PPN::new(ptr as usize >> Sv39::PAGE_BITS)
+        }
+
+        /// 取消一个共享页的映射，返回这个页是否被共享。
+        fn unshare(ppn: PPN<Sv39>) -> bool {
+            let mut shared = SHARED.lock();
+            let Some(count) = shared.get_mut(&ppn.val()) else {
+                return false;
+            };
+            *count -= 1;
+            if *count == 1 {
+                shared.remove(&ppn.val());
+            }
+            true
+        }
+
+        /// 释放一个数据页。共享页只减少映射数，最后一个映射取消时才释放。
+        fn free_frame(ppn: PPN<Sv39>) {
+            if !Self::unshare(ppn) {
+                Self::page_free(Self::frame_ptr::<u8>(ppn), 1);
+            }
+        }
+
+        /// 找到 `vpn` 的叶子页表项，中间页表不存在时返回 `None`。
+        fn leaf(space: &mut AddressSpace<Sv39, Self>, vpn: VPN<Sv39>) -> Option<&mut Pte<Sv39>> {
+            let mut table = Self::frame_ptr::<Pte<Sv39>>(space.root_ppn());
+            for level in (1..=Sv39::MAX_LEVEL).rev() {
+                let pte = unsafe { *table.add(vpn.index_in(level)) };
+                if !pte.is_valid() || Sv39::is_leaf(pte.flags().val()) {
+                    return None;
+                }
+                table = Self::frame_ptr(pte.ppn());
+            }
+            Some(unsafe { &mut *table.add(vpn.index_in(0)) })
+        }
+
+        /// 为 `fork` 复制地址空间：`parent` 拥有的页与 `child` 共享，可写的页在双方都改为写时复制；
+        /// 不属于 `parent` 的页（用 `map_extern` 映射的）不能共享，直接复制一份。
+        pub fn fork(parent: &mut AddressSpace<Sv39, Self>, child: &mut AddressSpace<Sv39, Self>) {
+            const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+            let areas = parent.areas.clone();
+            for area in &areas {
+                let mut vpn = area.start;
+                while vpn < area.end {
+                    if let Some(pte) = Self::leaf(parent, vpn).filter(|pte| pte.is_valid()) {
+                        let ppn = pte.ppn();
+                        let mut flags = pte.flags();
+                        if flags.contains(Self::OWNED) {
+                            if flags.contains(Self::WRITABLE) {
+                                flags = unsafe {
+                                    VmFlags::from_raw(
+                                        flags.val() & !Self::WRITABLE.val() | Self::COW.val(),
+                                    )
+                                };
+                                *pte = flags.build_pte(ppn);
+                            }
+                            *SHARED.lock().entry(ppn.val()).or_insert(1) += 1;
+                            child.map_extern(vpn..vpn + 1, ppn, flags);
+                        } else {
+                            let data = unsafe {
+                                core::slice::from_raw_parts(Self::frame_ptr::<u8>(ppn), PAGE_SIZE)
+                            };
+                            child.map(vpn..vpn + 1, data, 0, flags);
+                        }
+                    }
+                    vpn += 1;
+                }
+            }
+            child.areas = areas;
+            // 父进程的页表项被修改过，丢弃可能缓存的旧映射
+            unsafe { riscv::asm::sfence_vma_all() };
+        }
+
+        /// 处理对 `vpn` 的写入：写时复制的页还被其他地址空间共享时复制一份，否则直接恢复写权限。
+        ///
+        /// 返回 `vpn` 是不是写时复制的页。
+        pub fn copy_on_write(space: &mut AddressSpace<Sv39, Self>, vpn: VPN<Sv39>) -> bool {
+            let Some(pte) = Self::leaf(space, vpn) else {
+                return false;
+            };
+            let flags = pte.flags();
+            if !pte.is_valid() || !flags.contains(Self::COW) {
+                return false;
+            }
+            let flags =
+                unsafe { VmFlags::from_raw(flags.val() & !Self::COW.val() | Self::WRITABLE.val()) };
+            let mut ppn = pte.ppn();
+            if Self::unshare(ppn) {
+                let page = Self::page_alloc::<u8>(1);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        Self::frame_ptr::<u8>(ppn),
+                        page,
+                        1 << Sv39::PAGE_BITS,
+                    )
+                };
+                ppn = Self::frame_ppn(page);
+            }
+            *pte = flags.build_pte(ppn);
+            unsafe { riscv::asm::sfence_vma_all() };
+            true
+        }
+
+        /// 当前所有页管理器持有的物理页数。
+        pub fn frames() -> usize {
+            FRAMES.load(Ordering::Relaxed)
+        }
+
+        /// 去掉页表项的 `OWNED` 标记，用于把其他地址空间的页表项复制过来：回收页表时不会释放它指向的页。
+        pub fn share(pte: Pte<Sv39>) -> Pte<Sv39> {
+            let flags = unsafe { VmFlags::from_raw(pte.flags().val() & !Self::OWNED.val()) };
+            flags.build_pte(pte.ppn())
+        }
+
+        /// 在 `vpn` 处映射一个新分配的页，缺少的中间页表一并分配。`vpn` 已经映射时返回 `false`。
+        pub fn map_page(
+            space: &mut AddressSpace<Sv39, Self>,
+            vpn: VPN<Sv39>,
+            flags: VmFlags<Sv39>,
+        ) -> bool {
+            let mut table = Self::frame_ptr::<Pte<Sv39>>(space.root_ppn());
+            for level in (1..=Sv39::MAX_LEVEL).rev() {
+                let pte = unsafe { &mut *table.add(vpn.index_in(level)) };
+                if !pte.is_valid() {
+                    let page = Self::page_alloc::<u8>(1);
+                    *pte = (build_flags("V") | Self::OWNED).build_pte(Self::frame_ppn(page));
+                }
+                table = Self::frame_ptr(pte.ppn());
+            }
+            let pte = unsafe { &mut *table.add(vpn.index_in(0)) };
+            if pte.is_valid() {
+                return false;
+            }
+            let page = Self::page_alloc::<u8>(1);
+            *pte = (flags | Self::OWNED).build_pte(Self::frame_ppn(page));
+            true
+        }
+
+        /// 取消 `range` 中已经映射的页，释放其中带 `OWNED` 标记的页，`areas` 保持不变。
+        pub fn discard(space: &mut AddressSpace<Sv39, Self>, range: Range<VPN<Sv39>>) {
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(pte) = Self::leaf(space, vpn).filter(|pte| pte.is_valid()) {
+                    if pte.flags().contains(Self::OWNED) {
+                        Self::free_frame(pte.ppn());
+                    }
+                    *pte = unsafe { VmFlags::from_raw(0) }.build_pte(PPN::new(0));
+                }
+                vpn += 1;
+            }
+            unsafe { riscv::asm::sfence_vma_all() };
+        }
+
+        /// 把 `range` 中已经映射的页的权限改为 `flags`。
+        ///
+        /// 还被其他地址空间共享的页不能直接写：要可写时去掉写权限、打上 `COW` 标记，写入时再复制。
+        pub fn protect(
+            space: &mut AddressSpace<Sv39, Self>,
+            range: Range<VPN<Sv39>>,
+            flags: VmFlags<Sv39>,
+        ) {
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(pte) = Self::leaf(space, vpn).filter(|pte| pte.is_valid()) {
+                    let ppn = pte.ppn();
+                    let mut raw = flags.val() | pte.flags().val() & Self::OWNED.val();
+                    if flags.contains(Self::WRITABLE) && SHARED.lock().contains_key(&ppn.val()) {
+                        raw = raw & !Self::WRITABLE.val() | Self::COW.val();
+                    }
+                    *pte = unsafe { VmFlags::from_raw(raw) }.build_pte(ppn);
+                }
+                vpn += 1;
             }
-            .cast()
+            unsafe { riscv::asm::sfence_vma_all() };
+        }
+
+        /// 取消 `range` 的映射，释放其中带 `OWNED` 标记的页，并从 `areas` 中截掉这一段。
+        pub fn unmap(space: &mut AddressSpace<Sv39, Self>, range: Range<VPN<Sv39>>) {
+            Self::discard(space, range.clone());
+            let mut areas = Vec::new();
+            for area in space.areas.drain(..) {
+                if area.start < range.start {
+                    areas.push(area.start..area.end.min(range.start));
+                }
+                if area.end > range.end {
+                    areas.push(area.start.max(range.end)..area.end);
+                }
+            }
+            space.areas = areas;
+        }
+
+        /// 回收页表 `table` 指向的子树：`level` 层的页表项中，带 `OWNED` 标记的中间页表递归回收，
+        /// 带 `OWNED` 标记的叶子页通过 [`deallocate`](PageManager::deallocate) 释放，最后释放页表本身。
+        unsafe fn drop_table(&mut self, table: NonNull<Pte<Sv39>>, level: usize) {
+            for i in 0..1 << Sv39::LEVEL_BITS[level] {
+                let pte = *table.as_ptr().add(i);
+                if !pte.is_valid() || !self.check_owned(pte) {
+                    continue;
+                }
+                if level > 0 && !Sv39::is_leaf(pte.flags().val()) {
+                    self.drop_table(self.p_to_v(pte.ppn()), level - 1);
+                } else {
+                    self.deallocate(pte, 1);
+                }
+            }
+            Self::page_free(table.as_ptr(), 1);
         }
     }
 
@@ -302,12 +677,28 @@ mod impls {
             NonNull::new(Self::page_alloc(len)).unwrap()
         }
 
-        fn deallocate(&mut self, _pte: Pte<Sv39>, _len: usize) -> usize {
-            todo!()
+        fn deallocate(&mut self, pte: Pte<Sv39>, len: usize) -> usize {
+            if !self.check_owned(pte) {
+                return 0;
+            }
+            for i in 0..len {
+                Self::free_frame(pte.ppn() + i);
+            }
+            len
         }
 
         fn drop_root(&mut self) {
-            todo!()
+            if self.0 == NonNull::dangling() {
+                return;
+            }
+            unsafe { self.drop_table(self.0, Sv39::MAX_LEVEL) };
+            self.0 = NonNull::dangling();
+        }
+    }
+
+    impl Drop for Sv39Manager {
+        fn drop(&mut self) {
+            self.drop_root();
         }
     }
 
@@ -322,33 +713,318 @@ mod impls {
 
     pub struct SyscallContext;
 
+    /// 内核访问用户内存 `[addr, addr + len)` 之前，先替用户处理其中的缺页：
+    /// 分配按需分配的页、扩展用户栈，`write` 时还要解除写时复制。
+    ///
+    /// 地址溢出，或者遇到既没有映射、缺页处理也映射不了的页时返回 `EFAULT`，不再处理后面的页，
+    /// 所以用户给出再大的 `len`，处理的页数也不会超过按需分配的区域和栈能容纳的页数。
+    fn fault_in(
+        process: &mut ProcStruct,
+        addr: usize,
+        len: usize,
+        write: bool,
+    ) -> Result<(), Errno> {
+        const VALID: VmFlags<Sv39> = build_flags("V");
+        let end = addr.checked_add(len).ok_or(Errno::EFAULT)?;
+        let mut vpn = VAddr::<Sv39>::new(addr).floor();
+        let end = VAddr::<Sv39>::new(end).ceil();
+        while vpn < end {
+            let mapped = |process: &ProcStruct| {
+                process
+                    .address_space
+                    .translate::<u8>(vpn.base(), VALID)
+                    .is_some()
+            };
+            if write || !mapped(process) {
+                process.handle_page_fault(vpn, write);
+            }
+            if !mapped(process) {
+                return Err(Errno::EFAULT);
+            }
+            vpn += 1;
+        }
+        Ok(())
+    }
+
+    /// 找到名为 `name` 的应用并解析它的 ELF 文件。
+    fn find_app(name: &[u8]) -> Result<ElfFile<'static>, Errno> {
+        let input = core::str::from_utf8(name)
+            .ok()
+            .and_then(|name| APPS.get(name))
+            .ok_or(Errno::ENOENT)?;
+        ElfFile::new(input).map_err(|_| Errno::ENOEXEC)
+    }
+
+    /// 读出用户地址 `addr` 处以 0 结尾的字符串，不含结尾的 0。
+    fn read_cstr(process: &mut ProcStruct, mut addr: usize) -> Result<Vec<u8>, Errno> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        let mut s = Vec::new();
+        loop {
+            let len = PAGE_SIZE - addr % PAGE_SIZE;
+            fault_in(process, addr, len, false)?;
+            let chunk = UserBuffer::new(addr, len)
+                .read(&process.address_space)
+                .ok_or(Errno::EFAULT)?;
+            if let Some(end) = chunk.iter().position(|&b| b == 0) {
+                s.extend_from_slice(&chunk[..end]);
+                return Ok(s);
+            }
+            s.extend_from_slice(&chunk);
+            if s.len() > ARG_MAX {
+                return Err(Errno::E2BIG);
+            }
+            addr += len;
+        }
+    }
+
+    /// 读出用户地址 `addr` 处以 0 结尾的指针数组指向的全部字符串，`addr` 为 0 时视为空数组。
+    fn read_cstr_array(process: &mut ProcStruct, addr: usize) -> Result<Vec<Vec<u8>>, Errno> {
+        const WORD: usize = size_of::<usize>();
+        let mut strings = Vec::new();
+        let mut total = 0;
+        if addr == 0 {
+            return Ok(strings);
+        }
+        loop {
+            let ptr_addr = addr
+                .checked_add(strings.len() * WORD)
+                .ok_or(Errno::EFAULT)?;
+            fault_in(process, ptr_addr, WORD, false)?;
+            let ptr = UserPtr::<usize>::new(ptr_addr)
+                .read(&process.address_space)
+                .ok_or(Errno::EFAULT)?;
+            if ptr == 0 {
+                return Ok(strings);
+            }
+            let s = read_cstr(process, ptr)?;
+            total += s.len() + 1 + WORD;
+            if total > ARG_MAX {
+                return Err(Errno::E2BIG);
+            }
+            strings.push(s);
+        }
+    }
+
+    /// 读出用户地址 `path` 处长度为 `count` 的应用名，找到对应的 ELF 文件，排布新程序的初始用户栈。
+    ///
+    /// `args` 是 `argv` 和 `envp` 数组的用户地址，为 `None` 时参数只有应用名。
+    fn load_app(
+        process: &mut ProcStruct,
+        path: usize,
+        count: usize,
+        args: Option<(usize, usize)>,
+    ) -> Result<(ElfFile<'static>, InitStack), Errno> {
+        fault_in(process, path, count, false)?;
+        let name = UserBuffer::new(path, count)
+            .read(&process.address_space)
+            .ok_or(Errno::EFAULT)?;
+        let (argv, envp) = match args {
+            Some((argv, envp)) => (
+                read_cstr_array(process, argv)?,
+                read_cstr_array(process, envp)?,
+            ),
+            None => (vec![name.clone()], Vec::new()),
+        };
+        let stack = InitStack::new(STACK_TOP, &argv, &envp)?;
+        Ok((find_app(&name)?, stack))
+    }
+
+    /// 当前进程执行用户地址 `path` 处长度为 `count` 的应用，`args` 同 [`load_app`]。
+    ///
+    /// 成功时返回新程序的 `argc`：调度循环把返回值写进 `a0`，它正好是 `_start` 的第一个参数。
+    fn exec_app(path: usize, count: usize, args: Option<(usize, usize)>) -> isize {
+        let current = PROCESSOR.get_mut().current().unwrap();
+        let result = load_app(current, path, count, args).and_then(|(elf, stack)| {
+            current.exec(elf, &stack)?;
+            Ok(stack.argc)
+        });
+        match result {
+            Ok(argc) => argc as isize,
+            Err(errno) => {
+                if errno == Errno::ENOENT {
+                    log::error!("unknown app, select one in the list: ");
+                    APPS.keys().for_each(|app| println!("{app}"));
+                    println!();
+                }
+                -errno
+            }
+        }
+    }
+
+    /// 以用户地址 `path` 处长度为 `count` 的应用创建子进程，`args` 同 [`load_app`]。
+    fn spawn_app(path: usize, count: usize, args: Option<(usize, usize)>) -> isize {
+        let processor: *mut PManager<ProcStruct, ProcManager> = PROCESSOR.get_mut() as *mut _;
+        let current = unsafe { (*processor).current().unwrap() };
+        let parent_pid = current.pid;
+        let result = load_app(current, path, count, args).and_then(|(elf, stack)| {
+            let mut child = ProcStruct::from_elf(elf)?;
+            child.push_args(&stack);
+            Ok(child)
+        });
+        match result {
+            Ok(child) => {
+                let pid = child.pid;
+                unsafe { (*processor).add(pid, child, parent_pid) };
+                pid.get_usize() as isize
+            }
+            Err(errno) => -errno,
+        }
+    }
+
+    /// tg-syscall 分发表之外、由内核直接处理的系统调用，不认识的返回 `None`。
+    pub fn handle_extra(process: &mut ProcStruct, id: usize, args: [usize; 6]) -> Option<isize> {
+        const SCHED_GETINFO: usize = 420;
+        const EXECVE_ARGS: usize = 421;
+        const SPAWN_ARGS: usize = 422;
+        const GETRLIMIT: usize = 163;
+        const SETRLIMIT: usize = 164;
+        const MPROTECT: usize = 226;
+        const MADVISE: usize = 233;
+        Some(match id {
+            SCHED_GETINFO => sched_getinfo(process, args[0]),
+            EXECVE_ARGS => exec_app(args[0], args[1], Some((args[2], args[3]))),
+            SPAWN_ARGS => spawn_app(args[0], args[1], Some((args[2], args[3]))),
+            GETRLIMIT => getrlimit(process, args[0], args[1]),
+            SETRLIMIT => setrlimit(process, args[0], args[1]),
+            MPROTECT => mprotect(process, args[0], args[1], args[2]),
+            MADVISE => madvise(process, args[0], args[1], args[2]),
+            _ => return None,
+        })
+    }
+
+    /// `mmap` 的 `flags`：私有映射、固定地址、匿名映射。本章没有文件，所有映射都是匿名的私有映射。
+    const MAP_PRIVATE: i32 = 0x02;
+    const MAP_FIXED: i32 = 0x10;
+    const MAP_ANONYMOUS: i32 = 0x20;
+
+    /// `[addr, addr + len)` 覆盖的页，`addr` 没有页对齐或者范围溢出时返回 `None`。
+    fn page_range(addr: usize, len: usize) -> Option<Range<VPN<Sv39>>> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        if addr % PAGE_SIZE != 0 {
+            return None;
+        }
+        let end = addr.checked_add(len)?.checked_add(PAGE_SIZE - 1)?;
+        Some(VPN::new(addr >> Sv39::PAGE_BITS)..VPN::new(end >> Sv39::PAGE_BITS))
+    }
+
+    /// 把 `mmap`/`mprotect` 的 `prot`（读 1、写 2、执行 4）转换为页表项权限。
+    ///
+    /// `prot` 为 0 或有其他位时返回 `None`。硬件不支持只写的页，可写的页同时可读。
+    fn prot_flags(prot: usize) -> Option<VmFlags<Sv39>> {
+        if prot & 0x7 == 0 || prot & !0x7 != 0 {
+            return None;
+        }
+        let mut flags: [u8; 5] = *b"U___V";
+        if prot & 0x4 != 0 {
+            flags[1] = b'X';
+        }
+        if prot & 0x2 != 0 {
+            flags[2] = b'W';
+        }
+        if prot & 0x3 != 0 {
+            flags[3] = b'R';
+        }
+        crate::parse_flags(unsafe { core::str::from_utf8_unchecked(&flags) }).ok()
+    }
+
+    /// 把 `[addr, addr + len)` 的权限改为 `prot`，只能修改 `mmap` 和 `sbrk` 得到的区域。
+    fn mprotect(process: &mut ProcStruct, addr: usize, len: usize, prot: usize) -> isize {
+        let (Some(range), Some(flags)) = (page_range(addr, len), prot_flags(prot)) else {
+            return -Errno::EINVAL;
+        };
+        if process.protect(range, flags) {
+            0
+        } else {
+            -Errno::ENOMEM
+        }
+    }
+
+    /// 对 `[addr, addr + len)` 的使用建议。只有 `MADV_DONTNEED` 有效果：释放已经分配的页，
+    /// 再次访问时读到 0；其他建议只是提示，直接忽略。
+    fn madvise(process: &mut ProcStruct, addr: usize, len: usize, advice: usize) -> isize {
+        const MADV_WILLNEED: usize = 3;
+        const MADV_DONTNEED: usize = 4;
+        let Some(range) = page_range(addr, len) else {
+            return -Errno::EINVAL;
+        };
+        match advice {
+            0..=MADV_WILLNEED => 0,
+            MADV_DONTNEED if process.discard(range) => 0,
+            MADV_DONTNEED => -Errno::ENOMEM,
+            _ => -Errno::EINVAL,
+        }
+    }
+
+    /// 把资源 `resource` 的限制写到用户地址 `rlim`，目前只支持 `RLIMIT_STACK`。
+    fn getrlimit(process: &mut ProcStruct, resource: usize, rlim: usize) -> isize {
+        if resource != RLIMIT_STACK {
+            return -Errno::EINVAL;
+        }
+        if let Err(e) = fault_in(process, rlim, size_of::<RLimit>(), true) {
+            return -e;
+        }
+        match UserPtr::new(rlim).write(&process.address_space, process.stack_rlimit) {
+            Some(()) => 0,
+            None => -Errno::EFAULT,
+        }
+    }
+
+    /// 把资源 `resource` 的限制设为用户地址 `rlim` 处的值：软限制不能超过硬限制，硬限制只能调低。
+    ///
+    /// 调低栈的软限制不会回收已经映射的栈页，只是栈不能再扩展。
+    fn setrlimit(process: &mut ProcStruct, resource: usize, rlim: usize) -> isize {
+        if resource != RLIMIT_STACK {
+            return -Errno::EINVAL;
+        }
+        if let Err(e) = fault_in(process, rlim, size_of::<RLimit>(), false) {
+            return -e;
+        }
+        let Some(limit) = UserPtr::<RLimit>::new(rlim).read(&process.address_space) else {
+            return -Errno::EFAULT;
+        };
+        if limit.cur > limit.max {
+            -Errno::EINVAL
+        } else if limit.max > process.stack_rlimit.max {
+            -Errno::EPERM
+        } else {
+            process.stack_rlimit = limit;
+            0
+        }
+    }
+
+    /// 把进程的调度策略和调度计数写到用户地址 `info`。
+    fn sched_getinfo(process: &mut ProcStruct, info: usize) -> isize {
+        let Some(sched_info) = PROCESSOR.get_mut().manager().info(process.pid) else {
+            return -Errno::ESRCH;
+        };
+        if let Err(e) = fault_in(process, info, size_of::<SchedInfo>(), true) {
+            return -e;
+        }
+        match UserPtr::new(info).write(&process.address_space, sched_info) {
+            Some(()) => 0,
+            None => -Errno::EFAULT,
+        }
+    }
+
     impl IO for SyscallContext {
         fn write(&self, _caller: Caller, fd: usize, buf: usize, count: usize) -> isize {
             match fd {
                 STDOUT | STDDEBUG => {
-                    const READABLE: VmFlags<Sv39> = build_flags("RV");
-                    if let Some(ptr) = PROCESSOR
-                        .get_mut()
-                        .current()
-                        .unwrap()
-                        .address_space
-                        .translate::<u8>(VAddr::new(buf), READABLE)
-                    {
-                        print!("{}", unsafe {
-                            core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-                                ptr.as_ptr(),
-                                count,
-                            ))
-                        });
+                    let current = PROCESSOR.get_mut().current().unwrap();
+                    if let Err(e) = fault_in(current, buf, count, false) {
+                        return -e;
+                    }
+                    if let Some(data) = UserBuffer::new(buf, count).read(&current.address_space) {
+                        print!("{}", String::from_utf8_lossy(&data));
                         count as _
                     } else {
                         log::error!("ptr not readable");
-                        -1
+                        -Errno::EFAULT
                     }
                 }
                 _ => {
                     log::error!("unsupported fd: {fd}");
-                    -1
+                    -Errno::EBADF
                 }
             }
         }
@@ -356,30 +1032,22 @@ mod impls {
         #[inline]
         fn read(&self, _caller: Caller, fd: usize, buf: usize, count: usize) -> isize {
             if fd == STDIN {
-                const WRITEABLE: VmFlags<Sv39> = build_flags("W_V");
-                if let Some(mut ptr) = PROCESSOR
-                    .get_mut()
-                    .current()
-                    .unwrap()
-                    .address_space
-                    .translate::<u8>(VAddr::new(buf), WRITEABLE)
+                let current = PROCESSOR.get_mut().current().unwrap();
+                if let Err(e) = fault_in(current, buf, count, true) {
+                    return -e;
+                }
+                if UserBuffer::new(buf, count)
+                    .fill(&current.address_space, || tg_sbi::console_getchar() as u8)
+                    .is_some()
                 {
-                    let mut ptr = unsafe { ptr.as_mut() } as *mut u8;
-                    for _ in 0..count {
-                        let c = tg_sbi::console_getchar() as u8;
-                        unsafe {
-                            *ptr = c;
-                            ptr = ptr.add(1);
-                        }
-                    }
                     count as _
                 } else {
                     log::error!("ptr not writeable");
-                    -1
+                    -Errno::EFAULT
                 }
             } else {
                 log::error!("unsupported fd: {fd}");
-                -1
+                -Errno::EBADF
             }
         }
     }
@@ -403,47 +1071,22 @@ mod impls {
         }
 
         fn exec(&self, _caller: Caller, path: usize, count: usize) -> isize {
-            const READABLE: VmFlags<Sv39> = build_flags("RV");
-            let current = PROCESSOR.get_mut().current().unwrap();
-            current
-                .address_space
-                .translate::<u8>(VAddr::new(path), READABLE)
-                .map(|ptr| unsafe {
-                    core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr.as_ptr(), count))
-                })
-                .and_then(|name| APPS.get(name))
-                .and_then(|input| ElfFile::new(input).ok())
-                .map_or_else(
-                    || {
-                        log::error!("unknown app, select one in the list: ");
-                        APPS.keys().for_each(|app| println!("{app}"));
-                        println!();
-                        -1
-                    },
-                    |data| {
-                        current.exec(data);
-                        0
-                    },
-                )
+            exec_app(path, count, None)
         }
 
         fn wait(&self, _caller: Caller, pid: isize, exit_code_ptr: usize) -> isize {
             let processor: *mut PManager<ProcStruct, ProcManager> = PROCESSOR.get_mut() as *mut _;
             let current = unsafe { (*processor).current().unwrap() };
-            const WRITABLE: VmFlags<Sv39> = build_flags("W_V");
             if let Some((dead_pid, exit_code)) =
                 unsafe { (*processor).wait(ProcId::from_usize(pid as usize)) }
             {
-                if let Some(mut ptr) = current
-                    .address_space
-                    .translate::<i32>(VAddr::new(exit_code_ptr), WRITABLE)
-                {
-                    unsafe { *ptr.as_mut() = exit_code as i32 };
+                if fault_in(current, exit_code_ptr, size_of::<i32>(), true).is_ok() {
+                    UserPtr::new(exit_code_ptr).write(&current.address_space, exit_code as i32);
                 }
                 return dead_pid.get_usize() as isize;
             } else {
                 // 等待的子进程不存在
-                return -1;
+                return -Errno::ECHILD;
             }
         }
 
@@ -454,27 +1097,7 @@ mod impls {
 
         // 实现 spawn 系统调用
         fn spawn(&self, _caller: Caller, path: usize, count: usize) -> isize {
-            const READABLE: VmFlags<Sv39> = build_flags("RV");
-            let processor: *mut PManager<ProcStruct, ProcManager> = PROCESSOR.get_mut() as *mut _;
-            let current = unsafe { (*processor).current().unwrap() };
-            let parent_pid = current.pid;
-            let result = current
-                .address_space
-                .translate::<u8>(VAddr::new(path), READABLE)
-                .map(|ptr| unsafe {
-                    core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr.as_ptr(), count))
-                })
-                .and_then(|name| APPS.get(name))
-                .and_then(|input| ElfFile::new(input).ok())
-                .and_then(|elf| ProcStruct::from_elf(elf));
-            match result {
-                Some(child) => {
-                    let pid = child.pid;
-                    unsafe { (*processor).add(pid, child, parent_pid) };
-                    pid.get_usize() as isize
-                }
-                None => -1,
-            }
+            spawn_app(path, count, None)
         }
 
         fn sbrk(&self, _caller: Caller, size: i32) -> isize {
@@ -482,7 +1105,7 @@ mod impls {
             if let Some(old_brk) = current.change_program_brk(size as isize) {
                 old_brk as isize
             } else {
-                -1
+                -Errno::ENOMEM
             }
         }
     }
@@ -496,10 +1119,11 @@ mod impls {
         // 实现 set_priority 系统调用
         fn set_priority(&self, _caller: Caller, prio: isize) -> isize {
             if prio < 2 {
-                return -1;
+                return -Errno::EINVAL;
             }
-            let current = PROCESSOR.get_mut().current().unwrap();
-            current.priority = prio as usize;
+            let processor = PROCESSOR.get_mut();
+            let pid = processor.current().unwrap().pid;
+            processor.manager().set_priority(pid, prio as usize);
             prio
         }
     }
@@ -507,28 +1131,26 @@ mod impls {
     impl Clock for SyscallContext {
         #[inline]
         fn clock_gettime(&self, _caller: Caller, clock_id: ClockId, tp: usize) -> isize {
-            const WRITABLE: VmFlags<Sv39> = build_flags("W_V");
             match clock_id {
                 ClockId::CLOCK_MONOTONIC => {
-                    if let Some(mut ptr) = PROCESSOR
-                        .get_mut()
-                        .current()
-                        .unwrap()
-                        .address_space
-                        .translate::<TimeSpec>(VAddr::new(tp), WRITABLE)
-                    {
-                        let time = riscv::register::time::read() * 10000 / 125;
-                        *unsafe { ptr.as_mut() } = TimeSpec {
-                            tv_sec: time / 1_000_000_000,
-                            tv_nsec: time % 1_000_000_000,
-                        };
-                        0
-                    } else {
-                        log::error!("ptr not readable");
-                        -1
+                    let current = PROCESSOR.get_mut().current().unwrap();
+                    if let Err(e) = fault_in(current, tp, size_of::<TimeSpec>(), true) {
+                        return -e;
+                    }
+                    let time = riscv::register::time::read() * 10000 / 125;
+                    let time = TimeSpec {
+                        tv_sec: time / 1_000_000_000,
+                        tv_nsec: time % 1_000_000_000,
+                    };
+                    match UserPtr::new(tp).write(&current.address_space, time) {
+                        Some(()) => 0,
+                        None => {
+                            log::error!("ptr not writable");
+                            -Errno::EFAULT
+                        }
                     }
                 }
-                _ => -1,
+                _ => -Errno::EINVAL,
             }
         }
     }
@@ -540,85 +1162,84 @@ mod impls {
             addr: usize,
             len: usize,
             prot: i32,
-            _flags: i32,
+            flags: i32,
             _fd: i32,
             _offset: usize,
         ) -> isize {
-            const PAGE_SIZE: usize = 1 << <Sv39 as MmuMeta>::PAGE_BITS;
-
-            if addr % PAGE_SIZE != 0 {
-                return -1;
-            }
-            if prot & 0x7 == 0 {
-                return -1;
+            let (Some(range), Some(vm_flags)) = (page_range(addr, len), prot_flags(prot as usize))
+            else {
+                return -Errno::EINVAL;
+            };
+            if flags & !(MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+                return -Errno::EINVAL;
             }
-            if prot & !0x7 != 0 {
-                return -1;
+            let fixed = flags & MAP_FIXED != 0;
+            // 不能映射第 0 页，空指针总是非法
+            if addr == 0 && fixed {
+                return -Errno::EPERM;
             }
-
-            let len_aligned = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            if len_aligned == 0 {
+            if range.is_empty() {
                 return 0;
             }
 
-            let start_vpn = VPN::<Sv39>::new(addr >> <Sv39 as MmuMeta>::PAGE_BITS);
-            let end_vpn = VPN::<Sv39>::new((addr + len_aligned) >> <Sv39 as MmuMeta>::PAGE_BITS);
-
             let current = PROCESSOR.get_mut().current().unwrap();
-
-            for area in &current.address_space.areas {
-                if start_vpn < area.end && end_vpn > area.start {
-                    return -1;
+            let range = if addr == 0 && !fixed {
+                // 由内核挑选地址
+                let pages = range.end.val() - range.start.val();
+                match current.find_free(pages) {
+                    Some(start) => start..start + pages,
+                    None => return -Errno::ENOMEM,
                 }
+            } else {
+                range
+            };
+            // 映射不能碰到栈槽
+            if range.end.base().val() > STACKS_BOTTOM {
+                return -Errno::ENOMEM;
             }
 
-            let mut flags_str: [u8; 5] = *b"U___V";
-            if prot & 0x4 != 0 {
-                flags_str[1] = b'X';
-            }
-            if prot & 0x2 != 0 {
-                flags_str[2] = b'W';
+            if fixed {
+                // 固定地址的映射替换掉原有的映射，但不能拿走堆
+                let heap = current.heap();
+                if !heap.is_empty() && range.start < heap.end && heap.start < range.end {
+                    return -Errno::EEXIST;
+                }
+                current.unmap(range.clone());
+            } else if current.overlaps(&range) {
+                return -Errno::EEXIST;
             }
-            if prot & 0x1 != 0 {
-                flags_str[3] = b'R';
+            let start = range.start.base().val();
+            current.map_lazy(range, vm_flags);
+            // 指定地址时沿用前几章的约定返回 0，否则返回内核挑选的地址
+            if addr == 0 {
+                start as isize
+            } else {
+                0
             }
-            let flags = crate::parse_flags(
-                unsafe { core::str::from_utf8_unchecked(&flags_str) }
-            ).unwrap();
-
-            current.address_space.map(start_vpn..end_vpn, &[], 0, flags);
-            0
         }
 
         fn munmap(&self, _caller: Caller, addr: usize, len: usize) -> isize {
-            const PAGE_SIZE: usize = 1 << <Sv39 as MmuMeta>::PAGE_BITS;
-
-            if addr % PAGE_SIZE != 0 {
-                return -1;
-            }
-
-            let len_aligned = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            if len_aligned == 0 {
+            let Some(range) = page_range(addr, len) else {
+                return -Errno::EINVAL;
+            };
+            if range.is_empty() {
                 return 0;
             }
 
-            let start_vpn = VPN::<Sv39>::new(addr >> <Sv39 as MmuMeta>::PAGE_BITS);
-            let end_vpn = VPN::<Sv39>::new((addr + len_aligned) >> <Sv39 as MmuMeta>::PAGE_BITS);
-
             let current = PROCESSOR.get_mut().current().unwrap();
 
-            let mut vpn = start_vpn;
-            while vpn < end_vpn {
+            let mut vpn = range.start;
+            while vpn < range.end {
                 let covered = current.address_space.areas.iter().any(|area| {
                     vpn >= area.start && vpn < area.end
                 });
                 if !covered {
-                    return -1;
+                    return -Errno::EINVAL;
                 }
                 vpn = vpn + 1;
             }
 
-            current.address_space.unmap(start_vpn..end_vpn);
+            current.unmap(range);
             0
         }
     }
@@ -1,16 +1,21 @@
-use crate::{build_flags, map_portal, parse_flags, Sv39, Sv39Manager};
-use alloc::alloc::alloc_zeroed;
-use core::alloc::Layout;
+use crate::{
+    args::InitStack,
+    build_flags,
+    elf::{self, LoadError},
+    map_portal,
+    stack::{RLimit, Stack, STACKS_BOTTOM},
+    user_buffer::UserBuffer,
+    Sv39, Sv39Manager,
+};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
 use tg_kernel_context::{foreign::ForeignContext, LocalContext};
 use tg_kernel_vm::{
-    page_table::{MmuMeta, VAddr, PPN, VPN},
+    page_table::{MmuMeta, VAddr, VmFlags, VPN},
     AddressSpace,
 };
 use tg_task_manage::ProcId;
-use xmas_elf::{
-    header::{self, HeaderPt2, Machine},
-    program, ElfFile,
-};
+use xmas_elf::ElfFile;
 
 /// 进程。
 pub struct Process {
@@ -23,28 +28,54 @@ pub struct Process {
     pub heap_bottom: usize,
     /// 当前程序 break 位置
     pub program_brk: usize,
-    /// stride 调度：当前步进值
-    pub stride: usize,
-    /// stride 调度：优先级（>= 2）
+    /// 优先级（>= 2），stride、彩票和 CFS 调度使用
     pub priority: usize,
+    /// 被调度运行的次数
+    pub dispatches: usize,
+    /// 用完时间片被时钟中断打断的次数
+    pub ticks: usize,
+    /// 在用户态运行的总时间（`time` 计数）
+    pub run_time: u64,
+    /// 按需分配的区域及其权限：`mmap` 和 `sbrk` 只登记虚拟地址范围，第一次访问时才分配物理页
+    pub lazy_areas: Vec<(Range<VPN<Sv39>>, VmFlags<Sv39>)>,
+    /// 用户栈，下标是栈槽编号
+    pub stacks: Vec<Stack>,
+    /// 栈大小限制，`fork` 和 `exec` 时保留
+    pub stack_rlimit: RLimit,
 }
 
 impl Process {
-    pub fn exec(&mut self, elf: ElfFile) {
-        let proc = Process::from_elf(elf).unwrap();
+    /// 用 `elf` 替换进程的地址空间，初始用户栈为 `stack`。`elf` 无法加载时进程保持不变。
+    pub fn exec(&mut self, elf: ElfFile, stack: &InitStack) -> Result<(), LoadError> {
+        let proc = Process::from_elf(elf)?;
         self.address_space = proc.address_space;
         self.context = proc.context;
         self.heap_bottom = proc.heap_bottom;
         self.program_brk = proc.program_brk;
+        self.lazy_areas = proc.lazy_areas;
+        self.stacks = proc.stacks;
+        self.push_args(stack);
+        Ok(())
+    }
+
+    /// 把排布好的参数写到用户栈上，设置栈指针和 `_start` 的参数 `a0`、`a1`、`a2`。
+    pub fn push_args(&mut self, stack: &InitStack) {
+        UserBuffer::new(stack.sp, stack.data.len())
+            .write(&self.address_space, &stack.data)
+            .unwrap();
+        let ctx = &mut self.context.context;
+        *ctx.sp_mut() = stack.sp;
+        *ctx.a_mut(0) = stack.argc;
+        *ctx.a_mut(1) = stack.argv;
+        *ctx.a_mut(2) = stack.envp;
     }
 
     pub fn fork(&mut self) -> Option<Process> {
         // 子进程 pid
         let pid = ProcId::new();
-        // 复制父进程地址空间
-        let parent_addr_space = &self.address_space;
+        // 写时复制地共享父进程地址空间
         let mut address_space: AddressSpace<Sv39, Sv39Manager> = AddressSpace::new();
-        parent_addr_space.cloneself(&mut address_space);
+        Sv39Manager::fork(&mut self.address_space, &mut address_space);
         map_portal(&address_space);
         // 复制父进程上下文
         let context = self.context.context.clone();
@@ -56,97 +87,56 @@ impl Process {
             address_space,
             heap_bottom: self.heap_bottom,
             program_brk: self.program_brk,
-            stride: 0,
             priority: self.priority,
+            dispatches: 0,
+            ticks: 0,
+            run_time: 0,
+            lazy_areas: self.lazy_areas.clone(),
+            stacks: self.stacks.clone(),
+            stack_rlimit: self.stack_rlimit,
         })
     }
 
-    pub fn from_elf(elf: ElfFile) -> Option<Self> {
-        let entry = match elf.header.pt2 {
-            HeaderPt2::Header64(pt2)
-                if pt2.type_.as_type() == header::Type::Executable
-                    && pt2.machine.as_machine() == Machine::RISC_V =>
-            {
-                pt2.entry_point as usize
-            }
-            _ => None?,
-        };
-
-        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
-        const PAGE_MASK: usize = PAGE_SIZE - 1;
-
+    /// 加载 `elf` 创建进程，ELF 格式不对时返回 [`LoadError`]。
+    pub fn from_elf(elf: ElfFile) -> Result<Self, LoadError> {
         let mut address_space = AddressSpace::new();
-        let mut max_end_va: usize = 0;
-        for program in elf.program_iter() {
-            if !matches!(program.get_type(), Ok(program::Type::Load)) {
-                continue;
-            }
-
-            let off_file = program.offset() as usize;
-            let len_file = program.file_size() as usize;
-            let off_mem = program.virtual_addr() as usize;
-            let end_mem = off_mem + program.mem_size() as usize;
-            assert_eq!(off_file & PAGE_MASK, off_mem & PAGE_MASK);
-
-            if end_mem > max_end_va {
-                max_end_va = end_mem;
-            }
-
-            let mut flags: [u8; 5] = *b"U___V";
-            if program.flags().is_execute() {
-                flags[1] = b'X';
-            }
-            if program.flags().is_write() {
-                flags[2] = b'W';
-            }
-            if program.flags().is_read() {
-                flags[3] = b'R';
-            }
-            address_space.map(
-                VAddr::new(off_mem).floor()..VAddr::new(end_mem).ceil(),
-                &elf.input[off_file..][..len_file],
-                off_mem & PAGE_MASK,
-                parse_flags(unsafe { core::str::from_utf8_unchecked(&flags) }).unwrap(),
-            );
-        }
+        // 程序的段不能碰到栈槽
+        let image = elf::load(&elf, &mut address_space, STACKS_BOTTOM)?;
 
         // 堆底从 ELF 加载的最高地址的下一页开始
-        let heap_bottom = VAddr::<Sv39>::new(max_end_va).ceil().base().val();
-
-        // 映射用户栈
-        let stack = unsafe {
-            alloc_zeroed(Layout::from_size_align_unchecked(
-                2 << Sv39::PAGE_BITS,
-                1 << Sv39::PAGE_BITS,
-            ))
-        };
-        address_space.map_extern(
-            VPN::new((1 << 26) - 2)..VPN::new(1 << 26),
-            PPN::new(stack as usize >> Sv39::PAGE_BITS),
-            build_flags("U_WRV"),
-        );
+        let heap_bottom = VAddr::<Sv39>::new(image.end).ceil().base().val();
+
+        // 在第 0 个栈槽映射主线程的用户栈
+        let stack = Stack::new(&mut address_space, 0, image.stack_flags);
         // 映射异界传送门
         map_portal(&address_space);
 
-        let mut context = LocalContext::user(entry);
+        let mut context = LocalContext::user(image.entry);
         let satp = (8 << 60) | address_space.root_ppn().val();
-        *context.sp_mut() = 1 << 38;
-        Some(Self {
+        *context.sp_mut() = stack.top();
+        Ok(Self {
             pid: ProcId::new(),
             context: ForeignContext { context, satp },
             address_space,
             heap_bottom,
             program_brk: heap_bottom,
-            stride: 0,
             priority: 16,
+            dispatches: 0,
+            ticks: 0,
+            run_time: 0,
+            lazy_areas: Vec::new(),
+            stacks: vec![stack],
+            stack_rlimit: RLimit::STACK,
         })
     }
 
     /// 修改程序 break 位置，返回旧的 break 地址，失败返回 None
+    ///
+    /// 堆最多长到栈槽的下边界 `STACKS_BOTTOM`。
     pub fn change_program_brk(&mut self, size: isize) -> Option<usize> {
         let old_brk = self.program_brk;
         let new_brk = self.program_brk as isize + size;
-        if new_brk < self.heap_bottom as isize {
+        if new_brk < self.heap_bottom as isize || new_brk as usize > STACKS_BOTTOM {
             return None;
         }
         let new_brk = new_brk as usize;
@@ -157,19 +147,149 @@ impl Process {
         if size > 0 {
             // 扩展堆
             if new_brk_ceil.val() > old_brk_ceil.val() {
-                // 需要映射新页面
-                self.address_space
-                    .map(old_brk_ceil..new_brk_ceil, &[], 0, build_flags("U_WRV"));
+                // 堆不能长进 mmap 的区域
+                let range = old_brk_ceil..new_brk_ceil;
+                if self.overlaps(&range) {
+                    return None;
+                }
+                // 只登记新页面，访问时再分配
+                self.map_lazy(old_brk_ceil..new_brk_ceil, build_flags("U_WRV"));
             }
         } else if size < 0 {
             // 收缩堆
             if old_brk_ceil.val() > new_brk_ceil.val() {
                 // 需要取消映射页面
-                self.address_space.unmap(new_brk_ceil..old_brk_ceil);
+                self.unmap(new_brk_ceil..old_brk_ceil);
             }
         }
 
         self.program_brk = new_brk;
         Some(old_brk)
     }
+
+    /// 登记按需分配的区域 `range`，第一次访问其中的页时以 `flags` 映射。
+    pub fn map_lazy(&mut self, range: Range<VPN<Sv39>>, flags: VmFlags<Sv39>) {
+        self.address_space.areas.push(range.clone());
+        self.lazy_areas.push((range, flags));
+    }
+
+    /// 取消 `range` 的映射，已经分配的页被释放，按需分配的区域也一并截掉。
+    pub fn unmap(&mut self, range: Range<VPN<Sv39>>) {
+        Sv39Manager::unmap(&mut self.address_space, range.clone());
+        self.cut_lazy(&range);
+    }
+
+    /// 把 `range` 的权限改为 `flags`，区域按需要拆开。已经分配的页立即改权限，还被共享的页仍然写时复制。
+    ///
+    /// `range` 必须整个落在按需分配的区域中，否则返回 `false`，进程保持不变。
+    pub fn protect(&mut self, range: Range<VPN<Sv39>>, flags: VmFlags<Sv39>) -> bool {
+        if !self.is_lazy(&range) {
+            return false;
+        }
+        let cut = self.cut_lazy(&range);
+        self.lazy_areas
+            .extend(cut.into_iter().map(|(area, _)| (area, flags)));
+        Sv39Manager::protect(&mut self.address_space, range, flags);
+        true
+    }
+
+    /// 释放 `range` 中已经分配的页，区域保留，再次访问时得到全零的新页。
+    ///
+    /// `range` 必须整个落在按需分配的区域中，否则返回 `false`。
+    pub fn discard(&mut self, range: Range<VPN<Sv39>>) -> bool {
+        if !self.is_lazy(&range) {
+            return false;
+        }
+        Sv39Manager::discard(&mut self.address_space, range);
+        true
+    }
+
+    /// 堆占用的页，从 `heap_bottom` 到当前 break 所在的页。
+    pub fn heap(&self) -> Range<VPN<Sv39>> {
+        VAddr::<Sv39>::new(self.heap_bottom).floor()..VAddr::<Sv39>::new(self.program_brk).ceil()
+    }
+
+    /// 在堆顶和栈槽之间从高往低找 `pages` 页连续的空闲虚拟地址，返回起始页号。
+    pub fn find_free(&self, pages: usize) -> Option<VPN<Sv39>> {
+        let floor = VAddr::<Sv39>::new(self.program_brk).ceil();
+        let mut end = VPN::<Sv39>::new(STACKS_BOTTOM >> Sv39::PAGE_BITS);
+        loop {
+            let start = VPN::new(end.val().checked_sub(pages)?);
+            if start < floor {
+                return None;
+            }
+            // 与已有区域重叠时，从重叠区域中最低的起点往下接着找
+            let lowest = self
+                .address_space
+                .areas
+                .iter()
+                .filter(|area| area.start < end && start < area.end)
+                .map(|area| area.start)
+                .min();
+            match lowest {
+                Some(lowest) => end = lowest,
+                None => return Some(start),
+            }
+        }
+    }
+
+    /// `range` 是否与已有的映射重叠。
+    pub fn overlaps(&self, range: &Range<VPN<Sv39>>) -> bool {
+        self.address_space
+            .areas
+            .iter()
+            .any(|area| area.start < range.end && range.start < area.end)
+    }
+
+    /// `range` 中的每一页是否都在按需分配的区域中。
+    fn is_lazy(&self, range: &Range<VPN<Sv39>>) -> bool {
+        (range.start.val()..range.end.val()).all(|vpn| {
+            let vpn = VPN::new(vpn);
+            self.lazy_areas
+                .iter()
+                .any(|(area, _)| area.start <= vpn && vpn < area.end)
+        })
+    }
+
+    /// 从按需分配的区域中截掉 `range`，返回截下来的部分。
+    fn cut_lazy(&mut self, range: &Range<VPN<Sv39>>) -> Vec<(Range<VPN<Sv39>>, VmFlags<Sv39>)> {
+        let mut areas = Vec::new();
+        let mut cut = Vec::new();
+        for (area, flags) in self.lazy_areas.drain(..) {
+            if area.start < range.start {
+                areas.push((area.start..area.end.min(range.start), flags));
+            }
+            if area.start < range.end && range.start < area.end {
+                cut.push((area.start.max(range.start)..area.end.min(range.end), flags));
+            }
+            if area.end > range.end {
+                areas.push((area.start.max(range.end)..area.end, flags));
+            }
+        }
+        self.lazy_areas = areas;
+        cut
+    }
+
+    /// 处理对 `vpn` 的缺页，`store` 表示缺页由写入引起。返回是否处理了缺页，没有处理说明访问非法。
+    ///
+    /// - `vpn` 在按需分配的区域中且还没有映射：分配一页
+    /// - `vpn` 在用户栈下方且没有超过栈大小限制：把栈扩展到 `vpn`
+    /// - 写入写时复制的页：复制一份或恢复写权限
+    pub fn handle_page_fault(&mut self, vpn: VPN<Sv39>, store: bool) -> bool {
+        let lazy = self
+            .lazy_areas
+            .iter()
+            .find(|(area, _)| area.start <= vpn && vpn < area.end);
+        if let Some(&(_, flags)) = lazy {
+            if Sv39Manager::map_page(&mut self.address_space, vpn, flags) {
+                return true;
+            }
+        }
+        if let Some(stack) = Stack::index_of(vpn).and_then(|i| self.stacks.get_mut(i)) {
+            if stack.grow(&mut self.address_space, vpn, self.stack_rlimit.cur) {
+                return true;
+            }
+        }
+        store && Sv39Manager::copy_on_write(&mut self.address_space, vpn)
+    }
 }
@@ -1,10 +1,10 @@
-use crate::process::Process;
-use alloc::collections::{BTreeMap, VecDeque};
-use core::cell::UnsafeCell;
-use tg_task_manage::{Manage, PManager, ProcId, Schedule};
-
-/// stride 调度的大步长常数
-const BIG_STRIDE: usize = 0x7fff_ffff;
+use crate::{
+    process::Process,
+    sched::{self, Policy, SchedInfo},
+};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{cell::UnsafeCell, marker::PhantomData};
+use tg_task_manage::{Manage, ProcId, Schedule};
 
 pub struct Processor {
     inner: UnsafeCell<PManager<Process, ProcManager>>,
@@ -27,21 +27,219 @@ impl Processor {
 
 pub static PROCESSOR: Processor = Processor::new();
 
+/// `wait` 的子进程都还没有退出时返回的 pid。
+const STILL_RUNNING: usize = -2isize as usize;
+
+/// 进程状态。
+enum ProcState {
+    /// 就绪、运行或阻塞
+    Alive,
+    /// 已经退出，等待父进程用 `wait` 取走退出码
+    Zombie(isize),
+}
+
+/// 进程在进程树中的位置。
+struct ProcRel {
+    parent: ProcId,
+    children: Vec<ProcId>,
+    state: ProcState,
+}
+
+/// 进程管理器，在 `MP` 的基础上维护进程树。
+///
+/// 进程退出后成为僵尸进程，它的子进程过继给 initproc；父进程 `wait` 取走退出码时才从 `MP` 中删除进程，
+/// 释放地址空间、用户栈和进程控制块。没有父进程的进程退出时直接释放。
+pub struct PManager<P, MP: Manage<P, ProcId> + Schedule<ProcId>> {
+    rel_map: BTreeMap<ProcId, ProcRel>,
+    manager: Option<MP>,
+    current: Option<ProcId>,
+    /// 第一个加入的进程，孤儿进程的新父进程
+    init: Option<ProcId>,
+    phantom_data: PhantomData<P>,
+}
+
+impl<P, MP: Manage<P, ProcId> + Schedule<ProcId>> PManager<P, MP> {
+    /// 新建进程管理器
+    pub const fn new() -> Self {
+        Self {
+            rel_map: BTreeMap::new(),
+            manager: None,
+            current: None,
+            init: None,
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// 设置进程实体的管理器
+    pub fn set_manager(&mut self, manager: MP) {
+        self.manager = Some(manager);
+    }
+
+    /// 进程实体的管理器
+    pub fn manager(&mut self) -> &mut MP {
+        self.manager.as_mut().unwrap()
+    }
+
+    /// 取出下一个要运行的进程，设为当前进程
+    pub fn find_next(&mut self) -> Option<&mut P> {
+        let id = self.manager().fetch()?;
+        self.current = Some(id);
+        self.manager().get_mut(id)
+    }
+
+    /// 当前进程
+    pub fn current(&mut self) -> Option<&mut P> {
+        let id = self.current?;
+        self.manager().get_mut(id)
+    }
+
+    /// 加入一个父进程为 `parent` 的新进程
+    pub fn add(&mut self, id: ProcId, task: P, parent: ProcId) {
+        self.manager().insert(id, task);
+        self.manager().add(id);
+        if let Some(parent_rel) = self.rel_map.get_mut(&parent) {
+            parent_rel.children.push(id);
+        }
+        self.rel_map.insert(
+            id,
+            ProcRel {
+                parent,
+                children: Vec::new(),
+                state: ProcState::Alive,
+            },
+        );
+        self.init.get_or_insert(id);
+    }
+
+    /// 当前进程让出处理器，回到就绪队列
+    pub fn make_current_suspend(&mut self) {
+        let id = self.current.take().unwrap();
+        self.manager().add(id);
+    }
+
+    /// 当前进程退出，成为僵尸进程，子进程过继给 initproc
+    pub fn make_current_exited(&mut self, exit_code: isize) {
+        let id = self.current.take().unwrap();
+        let rel = self.rel_map.get_mut(&id).unwrap();
+        rel.state = ProcState::Zombie(exit_code);
+        let parent = rel.parent;
+        let children = core::mem::take(&mut rel.children);
+        match self.init.filter(|&init| init != id) {
+            Some(init) => {
+                for &child in &children {
+                    self.rel_map.get_mut(&child).unwrap().parent = init;
+                }
+                self.rel_map
+                    .get_mut(&init)
+                    .unwrap()
+                    .children
+                    .extend(children);
+            }
+            // initproc 退出后不会再有进程回收孤儿，孤儿中的僵尸进程直接释放
+            None => {
+                for child in children {
+                    let rel = self.rel_map.get_mut(&child).unwrap();
+                    rel.parent = ProcId::from_usize(usize::MAX);
+                    if let ProcState::Zombie(_) = rel.state {
+                        self.release(child);
+                    }
+                }
+            }
+        }
+        if !self.rel_map.contains_key(&parent) {
+            self.release(id);
+        }
+    }
+
+    /// 当前进程等待 pid 为 `child_pid` 的子进程退出，`child_pid` 为 `usize::MAX` 时等待任意子进程。
+    ///
+    /// 取走僵尸子进程的 pid 和退出码并释放它；子进程都还在运行时 pid 为 -2；没有符合条件的子进程时返回 `None`。
+    pub fn wait(&mut self, child_pid: ProcId) -> Option<(ProcId, isize)> {
+        let id = self.current.unwrap();
+        let children = &self.rel_map.get(&id).unwrap().children;
+        let any = child_pid.get_usize() == usize::MAX;
+        let mut found = false;
+        let mut zombie = None;
+        for &child in children {
+            if any || child == child_pid {
+                found = true;
+                if let ProcState::Zombie(exit_code) = self.rel_map.get(&child).unwrap().state {
+                    zombie = Some((child, exit_code));
+                    break;
+                }
+            }
+        }
+        match zombie {
+            Some((child, exit_code)) => {
+                let rel = self.rel_map.get_mut(&id).unwrap();
+                rel.children.retain(|&c| c != child);
+                self.release(child);
+                Some((child, exit_code))
+            }
+            None if found => Some((ProcId::from_usize(STILL_RUNNING), -1)),
+            None => None,
+        }
+    }
+
+    /// 释放僵尸进程 `id` 的全部资源
+    fn release(&mut self, id: ProcId) {
+        self.rel_map.remove(&id);
+        self.manager().delete(id);
+    }
+}
+
 /// 任务管理器
 /// `tasks` 中保存所有的任务实体
-/// `ready_queue` 保存就绪进程的 id
+/// `policy` 是调度策略，管理就绪队列和各进程的调度参数
 pub struct ProcManager {
     tasks: BTreeMap<ProcId, Process>,
-    ready_queue: VecDeque<ProcId>,
+    policy: Box<dyn Policy>,
 }
 
 impl ProcManager {
-    /// 新建任务管理器
+    /// 新建任务管理器，调度策略由 feature 决定
     pub fn new() -> Self {
         Self {
             tasks: BTreeMap::new(),
-            ready_queue: VecDeque::new(),
+            policy: sched::new_policy(),
+        }
+    }
+
+    /// 进程 `id` 下一次运行的时间片长度
+    pub fn time_slice(&self, id: ProcId) -> u64 {
+        self.policy.time_slice(id)
+    }
+
+    /// 进程 `id` 刚刚运行了 `elapsed` 个 `time` 计数，`preempted` 表示它用完了时间片
+    pub fn charge(&mut self, id: ProcId, elapsed: u64, preempted: bool) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.run_time += elapsed;
+            if preempted {
+                task.ticks += 1;
+            }
+        }
+        self.policy.charge(id, elapsed, preempted);
+    }
+
+    /// 修改进程 `id` 的优先级
+    pub fn set_priority(&mut self, id: ProcId, priority: usize) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.priority = priority;
         }
+        self.policy.set_priority(id, priority);
+    }
+
+    /// 进程 `id` 的调度信息
+    pub fn info(&self, id: ProcId) -> Option<SchedInfo> {
+        let task = self.tasks.get(&id)?;
+        Some(SchedInfo {
+            policy: self.policy.kind() as usize,
+            priority: task.priority,
+            dispatches: task.dispatches,
+            ticks: task.ticks,
+            run_time: task.run_time,
+            key: self.policy.key(id),
+        })
     }
 }
 
@@ -49,6 +247,7 @@ impl Manage<Process, ProcId> for ProcManager {
     /// 插入一个新任务
     #[inline]
     fn insert(&mut self, id: ProcId, task: Process) {
+        self.policy.insert(id, task.priority);
         self.tasks.insert(id, task);
     }
     /// 根据 id 获取对应的任务
@@ -60,33 +259,21 @@ impl Manage<Process, ProcId> for ProcManager {
     #[inline]
     fn delete(&mut self, id: ProcId) {
         self.tasks.remove(&id);
+        self.policy.delete(id);
     }
 }
 
 impl Schedule<ProcId> for ProcManager {
     /// 添加 id 进入调度队列
+    #[inline]
     fn add(&mut self, id: ProcId) {
-        self.ready_queue.push_back(id);
+        self.policy.add(id);
     }
-    /// stride 调度：从就绪队列中取出 stride 最小的进程
+    /// 按调度策略取出下一个要运行的进程
     fn fetch(&mut self) -> Option<ProcId> {
-        if self.ready_queue.is_empty() {
-            return None;
-        }
-        let mut min_idx = 0;
-        let mut min_stride = usize::MAX;
-        for (i, &id) in self.ready_queue.iter().enumerate() {
-            if let Some(proc) = self.tasks.get(&id) {
-                if proc.stride < min_stride {
-                    min_stride = proc.stride;
-                    min_idx = i;
-                }
-            }
-        }
-        let id = self.ready_queue.remove(min_idx).unwrap();
-        // 更新 stride
-        if let Some(proc) = self.tasks.get_mut(&id) {
-            proc.stride += BIG_STRIDE / proc.priority;
+        let id = self.policy.fetch()?;
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.dispatches += 1;
         }
         Some(id)
     }
@@ -0,0 +1,87 @@
+//! 按虚拟运行时间调度，类似 Linux 的 CFS。
+
+use super::{Policy, PolicyKind, ReadyQueue};
+use alloc::collections::BTreeMap;
+use tg_task_manage::{ProcId, Schedule};
+
+/// 默认优先级，这个优先级的进程虚拟运行时间和实际运行时间增长得一样快
+const NICE_0: u64 = 16;
+
+/// 进程的 CFS 调度参数。
+struct Entity {
+    vruntime: u64,
+    priority: usize,
+}
+
+/// 每次运行虚拟运行时间最小的进程。
+///
+/// 进程运行 `elapsed` 后虚拟运行时间增加 `elapsed * NICE_0 / priority`，优先级越高增长越慢，分到的处理器时间越多。
+/// 与 stride 调度不同，虚拟运行时间按实际时间累计，不受时间片长度限制。
+pub struct Cfs {
+    entities: BTreeMap<ProcId, Entity>,
+    ready: ReadyQueue,
+    /// 最近一次取出的进程的虚拟运行时间，新建和重新就绪的进程从这里开始
+    min_vruntime: u64,
+}
+
+impl Cfs {
+    pub const fn new() -> Self {
+        Self {
+            entities: BTreeMap::new(),
+            ready: ReadyQueue::new(),
+            min_vruntime: 0,
+        }
+    }
+}
+
+impl Schedule<ProcId> for Cfs {
+    /// 阻塞了很久的进程虚拟运行时间远远落后，提到当前的最小值，免得它长时间独占处理器。
+    fn add(&mut self, id: ProcId) {
+        let Some(entity) = self.entities.get_mut(&id) else {
+            return;
+        };
+        if super::key_cmp(entity.vruntime, self.min_vruntime).is_lt() {
+            entity.vruntime = self.min_vruntime;
+        }
+        self.ready.push(id, entity.vruntime);
+    }
+
+    fn fetch(&mut self) -> Option<ProcId> {
+        let (id, vruntime) = self.ready.pop()?;
+        self.min_vruntime = vruntime;
+        Some(id)
+    }
+}
+
+impl Policy for Cfs {
+    fn kind(&self) -> PolicyKind {
+        PolicyKind::Cfs
+    }
+
+    fn insert(&mut self, id: ProcId, priority: usize) {
+        let vruntime = self.min_vruntime;
+        self.entities.insert(id, Entity { vruntime, priority });
+    }
+
+    fn delete(&mut self, id: ProcId) {
+        self.entities.remove(&id);
+        self.ready.remove(id);
+    }
+
+    fn set_priority(&mut self, id: ProcId, priority: usize) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            entity.priority = priority;
+        }
+    }
+
+    fn charge(&mut self, id: ProcId, elapsed: u64, _preempted: bool) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            let delta = elapsed.saturating_mul(NICE_0) / entity.priority as u64;
+            entity.vruntime = entity.vruntime.wrapping_add(delta.max(1));
+        }
+    }
+
+    fn key(&self, id: ProcId) -> u64 {
+        self.entities.get(&id).map_or(0, |entity| entity.vruntime)
+    }
+}
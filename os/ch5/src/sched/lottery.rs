@@ -0,0 +1,88 @@
+//! 彩票调度。
+
+use super::{Policy, PolicyKind};
+use alloc::{collections::BTreeMap, vec::Vec};
+use tg_task_manage::{ProcId, Schedule};
+
+/// 每个进程最多持有的彩票数，免得彩票总数溢出
+const MAX_TICKETS: usize = 1 << 20;
+
+/// 彩票调度：每个进程持有和优先级相同数量（至多 [`MAX_TICKETS`]）的彩票，
+/// 每次从就绪进程的全部彩票中随机抽一张，持有者运行。
+///
+/// 长期来看进程分到的处理器时间与彩票数成正比，但短时间内会有波动。随机数种子固定，同样的负载得到同样的调度序列。
+pub struct Lottery {
+    tickets: BTreeMap<ProcId, usize>,
+    ready: Vec<ProcId>,
+    /// xorshift 随机数发生器的状态，不能为 0
+    seed: u64,
+}
+
+impl Lottery {
+    pub const fn new() -> Self {
+        Self {
+            tickets: BTreeMap::new(),
+            ready: Vec::new(),
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// 下一个伪随机数。
+    fn rand(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl Schedule<ProcId> for Lottery {
+    fn add(&mut self, id: ProcId) {
+        if self.tickets.contains_key(&id) {
+            self.ready.push(id);
+        }
+    }
+
+    fn fetch(&mut self) -> Option<ProcId> {
+        let total: usize = self.ready.iter().map(|id| self.tickets[id]).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut winner = (self.rand() % total as u64) as usize;
+        for index in 0..self.ready.len() {
+            let count = self.tickets[&self.ready[index]];
+            if winner < count {
+                return Some(self.ready.swap_remove(index));
+            }
+            winner -= count;
+        }
+        unreachable!()
+    }
+}
+
+impl Policy for Lottery {
+    fn kind(&self) -> PolicyKind {
+        PolicyKind::Lottery
+    }
+
+    fn insert(&mut self, id: ProcId, priority: usize) {
+        self.tickets.insert(id, priority.min(MAX_TICKETS));
+    }
+
+    fn delete(&mut self, id: ProcId) {
+        self.tickets.remove(&id);
+        self.ready.retain(|&ready| ready != id);
+    }
+
+    fn set_priority(&mut self, id: ProcId, priority: usize) {
+        if let Some(tickets) = self.tickets.get_mut(&id) {
+            *tickets = priority.min(MAX_TICKETS);
+        }
+    }
+
+    fn charge(&mut self, _id: ProcId, _elapsed: u64, _preempted: bool) {}
+
+    fn key(&self, id: ProcId) -> u64 {
+        self.tickets.get(&id).map_or(0, |&tickets| tickets as u64)
+    }
+}
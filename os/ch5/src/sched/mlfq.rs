@@ -0,0 +1,92 @@
+//! 多级反馈队列调度。
+
+use super::{Policy, PolicyKind};
+use crate::TIME_SLICE;
+use alloc::collections::{BTreeMap, VecDeque};
+use tg_task_manage::{ProcId, Schedule};
+
+/// 队列级数
+const LEVELS: usize = 4;
+/// 所有进程合计运行这么长时间后，把全部进程提回最高级，免得低级别的进程饿死
+const BOOST_PERIOD: u64 = 50 * TIME_SLICE;
+
+/// 多级反馈队列：新进程从第 0 级开始，总是先运行级别最高（编号最小）的非空队列。
+///
+/// 第 `i` 级的时间片是 `TIME_SLICE << i`。用完时间片的进程降一级，提前让出处理器的保持原级，
+/// 所以交互式的进程留在高级别，计算密集的进程沉到低级别。不使用优先级。
+pub struct Mlfq {
+    levels: BTreeMap<ProcId, usize>,
+    ready: [VecDeque<ProcId>; LEVELS],
+    /// 上次提升以来所有进程合计的运行时间
+    since_boost: u64,
+}
+
+impl Mlfq {
+    pub fn new() -> Self {
+        Self {
+            levels: BTreeMap::new(),
+            ready: Default::default(),
+            since_boost: 0,
+        }
+    }
+
+    /// 所有进程回到第 0 级，就绪队列按原来的级别依次接到第 0 级后面。
+    fn boost(&mut self) {
+        self.levels.values_mut().for_each(|level| *level = 0);
+        let (top, lower) = self.ready.split_first_mut().unwrap();
+        for queue in lower {
+            top.append(queue);
+        }
+        self.since_boost = 0;
+    }
+}
+
+impl Schedule<ProcId> for Mlfq {
+    fn add(&mut self, id: ProcId) {
+        if let Some(&level) = self.levels.get(&id) {
+            self.ready[level].push_back(id);
+        }
+    }
+
+    fn fetch(&mut self) -> Option<ProcId> {
+        self.ready.iter_mut().find_map(VecDeque::pop_front)
+    }
+}
+
+impl Policy for Mlfq {
+    fn kind(&self) -> PolicyKind {
+        PolicyKind::Mlfq
+    }
+
+    fn insert(&mut self, id: ProcId, _priority: usize) {
+        self.levels.insert(id, 0);
+    }
+
+    fn delete(&mut self, id: ProcId) {
+        if let Some(level) = self.levels.remove(&id) {
+            self.ready[level].retain(|&ready| ready != id);
+        }
+    }
+
+    fn set_priority(&mut self, _id: ProcId, _priority: usize) {}
+
+    fn time_slice(&self, id: ProcId) -> u64 {
+        TIME_SLICE << self.levels.get(&id).copied().unwrap_or(0)
+    }
+
+    fn charge(&mut self, id: ProcId, elapsed: u64, preempted: bool) {
+        if let Some(level) = self.levels.get_mut(&id) {
+            if preempted && *level + 1 < LEVELS {
+                *level += 1;
+            }
+        }
+        self.since_boost += elapsed;
+        if self.since_boost >= BOOST_PERIOD {
+            self.boost();
+        }
+    }
+
+    fn key(&self, id: ProcId) -> u64 {
+        self.levels.get(&id).map_or(0, |&level| level as u64)
+    }
+}
@@ -0,0 +1,163 @@
+//! 调度策略。
+//!
+//! 每种策略都实现 [`Schedule<ProcId>`]，管理自己的就绪队列和调度参数；[`Policy`] 在此之上让策略知道
+//! 进程的创建、删除、优先级和实际运行的时间。构建时用 cargo feature 选择策略，默认使用 stride 调度：
+//!
+//! | Feature | 策略 |
+//! |---------|------|
+//! | `sched-rr` | 时间片轮转 |
+//! | `sched-mlfq` | 多级反馈队列 |
+//! | `sched-lottery` | 彩票调度 |
+//! | `sched-cfs` | 按虚拟运行时间调度（类似 Linux CFS） |
+//!
+//! 同时打开多个 feature 时按上表的顺序取第一个。
+
+mod cfs;
+mod lottery;
+mod mlfq;
+mod rr;
+mod stride;
+
+use crate::TIME_SLICE;
+use alloc::{boxed::Box, collections::BinaryHeap};
+use core::cmp::Ordering;
+use tg_task_manage::{ProcId, Schedule};
+
+/// 调度策略的编号，`sched_getinfo` 用它告诉用户当前的策略。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(usize)]
+pub enum PolicyKind {
+    /// 时间片轮转
+    RoundRobin = 0,
+    /// stride 调度
+    Stride = 1,
+    /// 多级反馈队列
+    Mlfq = 2,
+    /// 彩票调度
+    Lottery = 3,
+    /// 按虚拟运行时间调度
+    Cfs = 4,
+}
+
+/// 调度策略。
+pub trait Policy: Schedule<ProcId> {
+    /// 策略编号
+    fn kind(&self) -> PolicyKind;
+    /// 新进程 `id` 加入，优先级为 `priority`
+    fn insert(&mut self, id: ProcId, priority: usize);
+    /// 删除进程 `id`
+    fn delete(&mut self, id: ProcId);
+    /// 修改进程 `id` 的优先级，不使用优先级的策略忽略它
+    fn set_priority(&mut self, id: ProcId, priority: usize);
+    /// 进程 `id` 下一次运行的时间片长度
+    fn time_slice(&self, _id: ProcId) -> u64 {
+        TIME_SLICE
+    }
+    /// 进程 `id` 刚刚运行了 `elapsed` 个 `time` 计数，`preempted` 表示它用完了时间片
+    fn charge(&mut self, id: ProcId, elapsed: u64, preempted: bool);
+    /// 进程 `id` 与策略相关的调度参数：stride、虚拟运行时间、所在级别或彩票数，时间片轮转为 0
+    fn key(&self, id: ProcId) -> u64;
+}
+
+/// 按 feature 选出的调度策略。
+pub fn new_policy() -> Box<dyn Policy> {
+    if cfg!(feature = "sched-rr") {
+        Box::new(rr::RoundRobin::new())
+    } else if cfg!(feature = "sched-mlfq") {
+        Box::new(mlfq::Mlfq::new())
+    } else if cfg!(feature = "sched-lottery") {
+        Box::new(lottery::Lottery::new())
+    } else if cfg!(feature = "sched-cfs") {
+        Box::new(cfs::Cfs::new())
+    } else {
+        Box::new(stride::Stride::new())
+    }
+}
+
+/// `sched_getinfo` 写给用户的调度信息，布局与用户库中的 `SchedInfo` 一致。
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SchedInfo {
+    /// [`PolicyKind`] 的编号
+    pub policy: usize,
+    /// 优先级
+    pub priority: usize,
+    /// 被调度运行的次数
+    pub dispatches: usize,
+    /// 用完时间片被时钟中断打断的次数
+    pub ticks: usize,
+    /// 在用户态运行的总时间（`time` 计数）
+    pub run_time: u64,
+    /// [`Policy::key`]
+    pub key: u64,
+}
+
+/// 按环绕比较键值，`a` 应当先于 `b` 运行时返回 `Less`。
+///
+/// 键值累加时允许溢出回绕。只要就绪进程的键值两两相差不超过 `u64::MAX / 2`，把差值当作有符号数就能比较先后。
+fn key_cmp(a: u64, b: u64) -> Ordering {
+    (a.wrapping_sub(b) as i64).cmp(&0)
+}
+
+/// 就绪队列中的一项：键值小的先出队，键值相同时先进先出。
+struct Ready {
+    key: u64,
+    seq: u64,
+    id: ProcId,
+}
+
+impl Ord for Ready {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` 是大顶堆，反过来比较，堆顶就是最先运行的
+        key_cmp(other.key, self.key).then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Ready {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ready {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ready {}
+
+/// 按键值排序的就绪队列，stride 调度和 CFS 共用。
+struct ReadyQueue {
+    heap: BinaryHeap<Ready>,
+    seq: u64,
+}
+
+impl ReadyQueue {
+    const fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// 以键值 `key` 加入进程 `id`。
+    fn push(&mut self, id: ProcId, key: u64) {
+        self.heap.push(Ready {
+            key,
+            seq: self.seq,
+            id,
+        });
+        self.seq = self.seq.wrapping_add(1);
+    }
+
+    /// 取出键值最小的进程和它的键值。
+    fn pop(&mut self) -> Option<(ProcId, u64)> {
+        self.heap.pop().map(|ready| (ready.id, ready.key))
+    }
+
+    /// 删除进程 `id`。
+    fn remove(&mut self, id: ProcId) {
+        self.heap.retain(|ready| ready.id != id);
+    }
+}
@@ -0,0 +1,48 @@
+//! 时间片轮转调度。
+
+use super::{Policy, PolicyKind};
+use alloc::collections::VecDeque;
+use tg_task_manage::{ProcId, Schedule};
+
+/// 时间片轮转：就绪进程按先进先出的顺序轮流运行，不考虑优先级。
+pub struct RoundRobin {
+    ready: VecDeque<ProcId>,
+}
+
+impl RoundRobin {
+    pub const fn new() -> Self {
+        Self {
+            ready: VecDeque::new(),
+        }
+    }
+}
+
+impl Schedule<ProcId> for RoundRobin {
+    fn add(&mut self, id: ProcId) {
+        self.ready.push_back(id);
+    }
+
+    fn fetch(&mut self) -> Option<ProcId> {
+        self.ready.pop_front()
+    }
+}
+
+impl Policy for RoundRobin {
+    fn kind(&self) -> PolicyKind {
+        PolicyKind::RoundRobin
+    }
+
+    fn insert(&mut self, _id: ProcId, _priority: usize) {}
+
+    fn delete(&mut self, id: ProcId) {
+        self.ready.retain(|&ready| ready != id);
+    }
+
+    fn set_priority(&mut self, _id: ProcId, _priority: usize) {}
+
+    fn charge(&mut self, _id: ProcId, _elapsed: u64, _preempted: bool) {}
+
+    fn key(&self, _id: ProcId) -> u64 {
+        0
+    }
+}
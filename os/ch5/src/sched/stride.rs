@@ -0,0 +1,91 @@
+//! stride 调度。
+
+use super::{Policy, PolicyKind, ReadyQueue};
+use crate::TIME_SLICE;
+use alloc::collections::BTreeMap;
+use tg_task_manage::{ProcId, Schedule};
+
+/// stride 调度的大步长常数
+const BIG_STRIDE: u64 = 0x7fff_ffff;
+
+/// 进程的 stride 调度参数。
+struct Entity {
+    stride: u64,
+    priority: usize,
+}
+
+/// stride 调度：每次运行 stride 最小的进程，进程运行后 stride 增加 `BIG_STRIDE / priority`。
+///
+/// 每次增加不超过 `BIG_STRIDE / 2`（优先级至少为 2），新建和重新就绪的进程也不会落后于最近一次调度的 stride，
+/// 所以就绪进程的 stride 两两相差不超过 `BIG_STRIDE / 2`，可以环绕比较。
+pub struct Stride {
+    entities: BTreeMap<ProcId, Entity>,
+    ready: ReadyQueue,
+    /// 最近一次取出的进程的 stride，新建和重新就绪的进程从这里开始
+    pass: u64,
+}
+
+impl Stride {
+    pub const fn new() -> Self {
+        Self {
+            entities: BTreeMap::new(),
+            ready: ReadyQueue::new(),
+            pass: 0,
+        }
+    }
+}
+
+impl Schedule<ProcId> for Stride {
+    /// 阻塞了很久的进程 stride 可能远远落后，提到当前的最小值，免得它长时间独占处理器。
+    fn add(&mut self, id: ProcId) {
+        let Some(entity) = self.entities.get_mut(&id) else {
+            return;
+        };
+        if super::key_cmp(entity.stride, self.pass).is_lt() {
+            entity.stride = self.pass;
+        }
+        self.ready.push(id, entity.stride);
+    }
+
+    fn fetch(&mut self) -> Option<ProcId> {
+        let (id, stride) = self.ready.pop()?;
+        self.pass = stride;
+        Some(id)
+    }
+}
+
+impl Policy for Stride {
+    fn kind(&self) -> PolicyKind {
+        PolicyKind::Stride
+    }
+
+    fn insert(&mut self, id: ProcId, priority: usize) {
+        let stride = self.pass;
+        self.entities.insert(id, Entity { stride, priority });
+    }
+
+    fn delete(&mut self, id: ProcId) {
+        self.entities.remove(&id);
+        self.ready.remove(id);
+    }
+
+    fn set_priority(&mut self, id: ProcId, priority: usize) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            entity.priority = priority;
+        }
+    }
+
+    /// 按实际用掉的时间片比例增加 stride：每次至少增加 1，频繁让出处理器的进程也会向前推进；
+    /// 至多增加一个完整的 `BIG_STRIDE / priority`，中断来得晚了也不会多算。
+    fn charge(&mut self, id: ProcId, elapsed: u64, _preempted: bool) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            let pass = (BIG_STRIDE / entity.priority as u64).max(1);
+            let used = pass.saturating_mul(elapsed) / TIME_SLICE;
+            entity.stride = entity.stride.wrapping_add(used.clamp(1, pass));
+        }
+    }
+
+    fn key(&self, id: ProcId) -> u64 {
+        self.entities.get(&id).map_or(0, |entity| entity.stride)
+    }
+}
@@ -0,0 +1,116 @@
+//! 用户栈。
+//!
+//! [`STACK_TOP`] 往下依次是 [`MAX_STACKS`] 个栈槽，进程内线程号为 `i` 的线程使用第 `i` 个栈槽。
+//! 每个栈槽最低的一页是保护页，永远不映射：栈溢出时访问到保护页会缺页，进程被杀死，不会踩坏下面的内存。
+//!
+//! 新栈只映射栈槽顶部的 [`INIT_PAGES`] 页。用户访问栈中还没有映射的页时，缺页处理把栈向下扩展到该页，
+//! 扩展后的大小不超过 `RLIMIT_STACK` 的软限制。栈页由地址空间持有，进程退出时随地址空间回收。
+
+use crate::{Sv39, Sv39Manager};
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VmFlags, VPN},
+    AddressSpace,
+};
+
+/// 第 0 个栈槽的顶部，主线程的栈从这里向下增长。
+pub const STACK_TOP: usize = 1 << 38;
+/// 栈槽的个数，也是一个进程最多同时拥有的栈数。
+pub const MAX_STACKS: usize = 256;
+/// 所有栈槽的最低地址，程序的段必须在它下面。
+pub const STACKS_BOTTOM: usize = STACK_TOP - MAX_STACKS * SLOT_SIZE;
+/// 栈大小的硬上限：栈槽除去保护页。
+pub const STACK_MAX: usize = SLOT_SIZE - PAGE_SIZE;
+/// `RLIMIT_STACK` 软限制的默认值。
+pub const STACK_DEFAULT: usize = 64 << 10;
+
+const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+/// 每个栈槽的大小，包括保护页。
+const SLOT_SIZE: usize = 1 << 20;
+/// 新栈映射的页数，`exec` 的参数要能放得下。
+const INIT_PAGES: usize = 2;
+
+/// `getrlimit`/`setrlimit` 的资源编号。
+pub const RLIMIT_STACK: usize = 3;
+
+/// `getrlimit`/`setrlimit` 读写的资源限制，布局与 C 的 `struct rlimit` 一致。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct RLimit {
+    /// 软限制
+    pub cur: usize,
+    /// 硬限制，软限制不能超过它
+    pub max: usize,
+}
+
+impl RLimit {
+    /// 新进程的栈大小限制。
+    pub const STACK: Self = Self {
+        cur: STACK_DEFAULT,
+        max: STACK_MAX,
+    };
+}
+
+/// 一个线程的用户栈。
+#[derive(Clone, Copy)]
+pub struct Stack {
+    /// 栈顶的下一页
+    top: VPN<Sv39>,
+    /// 已经映射的最低一页
+    bottom: VPN<Sv39>,
+    /// 栈页的权限
+    flags: VmFlags<Sv39>,
+}
+
+impl Stack {
+    /// 在 `space` 的第 `index` 个栈槽中映射一个新栈，栈页权限为 `flags`。
+    pub fn new(
+        space: &mut AddressSpace<Sv39, Sv39Manager>,
+        index: usize,
+        flags: VmFlags<Sv39>,
+    ) -> Self {
+        assert!(index < MAX_STACKS);
+        let top = VPN::new((STACK_TOP - index * SLOT_SIZE) >> Sv39::PAGE_BITS);
+        let bottom = VPN::new(top.val() - INIT_PAGES);
+        space.map(bottom..top, &[], 0, flags);
+        Self { top, bottom, flags }
+    }
+
+    /// 栈顶地址，新线程的栈指针从这里开始。
+    pub fn top(&self) -> usize {
+        self.top.base().val()
+    }
+
+    /// `vpn` 所在栈槽的编号，不在任何栈槽中时返回 `None`。
+    pub fn index_of(vpn: VPN<Sv39>) -> Option<usize> {
+        let addr = vpn.base().val();
+        (STACKS_BOTTOM..STACK_TOP)
+            .contains(&addr)
+            .then(|| (STACK_TOP - 1 - addr) / SLOT_SIZE)
+    }
+
+    /// 把栈向下扩展到包含 `vpn`，扩展后的栈不超过 `limit` 字节。返回是否扩展了。
+    ///
+    /// `vpn` 已经在栈中、扩展会超过限制或者碰到其他映射时不扩展。
+    pub fn grow(
+        &mut self,
+        space: &mut AddressSpace<Sv39, Sv39Manager>,
+        vpn: VPN<Sv39>,
+        limit: usize,
+    ) -> bool {
+        let limit = limit.min(STACK_MAX);
+        if vpn >= self.bottom || (self.top.val() - vpn.val()) << Sv39::PAGE_BITS > limit {
+            return false;
+        }
+        let range = vpn..self.bottom;
+        if space
+            .areas
+            .iter()
+            .any(|area| area.start < range.end && range.start < area.end)
+        {
+            return false;
+        }
+        space.map(range, &[], 0, self.flags);
+        self.bottom = vpn;
+        true
+    }
+}
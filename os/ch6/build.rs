@@ -0,0 +1,148 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+const TARGET_ARCH: &str = "riscv64gc-unknown-none-elf";
+const TG_USER_VERSION: &str = "0.2.0-preview.1";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=LOG");
+    println!("cargo:rerun-if-env-changed=TG_USER_DIR");
+    println!("cargo:rerun-if-env-changed=TG_USER_VERSION");
+    println!("cargo:rerun-if-env-changed=TG_SKIP_USER_APPS");
+    println!("cargo:rerun-if-env-changed=CARGO_FEATURE_EXERCISE");
+
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+
+    // 只在 RISC-V64 架构上使用链接脚本
+    if target_arch == "riscv64" {
+        write_linker();
+        if !should_skip_build_apps() {
+            build_apps();
+        }
+    }
+}
+
+fn should_skip_build_apps() -> bool {
+    if env::var_os("TG_SKIP_USER_APPS").is_some() {
+        return true;
+    }
+
+    is_packaged_build()
+}
+
+fn write_linker() {
+    let ld = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("linker.ld");
+    fs::write(&ld, tg_linker::NOBIOS_SCRIPT).unwrap_or_else(|err| {
+        panic!("failed to write linker script to {}: {}", ld.display(), err)
+    });
+    println!("cargo:rustc-link-arg=-T{}", ld.display());
+}
+
+fn is_packaged_build() -> bool {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let out_dir = out_dir.to_string_lossy();
+
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let manifest_dir = manifest_dir.to_string_lossy();
+
+    out_dir.contains("/target/package/")
+        || out_dir.contains("\\target\\package\\")
+        || manifest_dir.contains("/target/package/")
+        || manifest_dir.contains("\\target\\package\\")
+}
+
+fn build_apps() {
+    let tg_user_root = ensure_tg_user();
+    let cases_path = tg_user_root.join("cases.toml");
+    println!("cargo:rerun-if-changed={}", cases_path.display());
+    println!(
+        "cargo:rerun-if-changed={}",
+        tg_user_root.join("Cargo.toml").display()
+    );
+    println!("cargo:rerun-if-changed={}", tg_user_root.join("src").display());
+
+    let case_key = if env::var("CARGO_FEATURE_EXERCISE").is_ok() {
+        "ch6_exercise"
+    } else {
+        "ch6"
+    };
+    let names = tg_mkfs::chapter_cases(&cases_path, case_key)
+        .unwrap_or_else(|err| panic!("failed to read user cases: {err}"));
+
+    let target_dir = tg_user_root.join("target").join(TARGET_ARCH).join("debug");
+    let mut files: Vec<(String, PathBuf)> = Vec::with_capacity(names.len());
+    for name in names {
+        build_user_app(&tg_user_root, &name);
+        let elf = target_dir.join(&name);
+        files.push((name, elf));
+    }
+
+    // 打包成磁盘镜像，`cargo run` 时作为 virtio 块设备挂到 QEMU 上
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let fs_img = manifest_dir.join("target").join("fs.img");
+    fs::create_dir_all(fs_img.parent().unwrap()).unwrap();
+    tg_mkfs::create(&fs_img, &files, None)
+        .unwrap_or_else(|err| panic!("failed to create {}: {err}", fs_img.display()));
+}
+
+fn build_user_app(tg_user_root: &PathBuf, name: &str) {
+    let status = Command::new("cargo")
+        .args([
+            "build",
+            "--manifest-path",
+            tg_user_root.join("Cargo.toml").to_string_lossy().as_ref(),
+            "--bin",
+            name,
+            "--target",
+            TARGET_ARCH,
+        ])
+        .status()
+        .expect("failed to execute cargo build for user app");
+    if !status.success() {
+        panic!("failed to build user app {name}");
+    }
+}
+
+fn ensure_tg_user() -> PathBuf {
+    if let Ok(dir) = env::var("TG_USER_DIR") {
+        let path = PathBuf::from(dir);
+        if path.join("Cargo.toml").exists() {
+            return path;
+        }
+    }
+
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let tg_user_dir = manifest_dir.join("tg-user");
+    if tg_user_dir.join("Cargo.toml").exists() {
+        return tg_user_dir;
+    }
+
+    let version = env::var("TG_USER_VERSION").unwrap_or_else(|_| TG_USER_VERSION.to_string());
+    let crate_spec = format!("tg-user@{version}");
+    let status = Command::new("cargo")
+        .args([
+            "clone",
+            crate_spec.as_str(),
+            "--",
+            tg_user_dir.to_string_lossy().as_ref(),
+        ])
+        .status()
+        .expect("failed to execute cargo clone tg-user");
+
+    if !status.success() {
+        panic!(
+            "failed to clone tg-user into {}; ensure cargo-clone is installed or set TG_USER_DIR",
+            tg_user_dir.display()
+        );
+    }
+
+    if !tg_user_dir.join("Cargo.toml").exists() {
+        panic!(
+            "tg-user clone did not create a valid crate at {}; ensure tg-user {} exists on crates.io or set TG_USER_DIR",
+            tg_user_dir.display(),
+            version
+        );
+    }
+
+    tg_user_dir
+}
@@ -0,0 +1,359 @@
+//! ELF 加载。
+//!
+//! [`load`] 在映射之前检查全部程序头，格式不对的程序返回 [`LoadError`]，不会让内核 panic：
+//!
+//! - 只接受小端 RISC-V 64 位的 `ET_EXEC` 和静态位置无关的 `ET_DYN`，带动态链接器（`PT_INTERP`）的程序不支持
+//! - `PT_LOAD` 段必须在文件范围内、互不重叠，且整个落在 `limit` 以下，碰不到用户栈和异界传送门
+//! - `p_align` 是 2 的幂，段的虚拟地址和文件偏移模 `p_align` 同余
+//!
+//! `ET_DYN` 整体平移到 [`PIE_BASE`] 以上（按最大的 `p_align` 对齐），
+//! 内核像动态链接器那样处理 `R_RISCV_RELATIVE` 重定位。程序启动时自己再重定位一次也没关系，两次写入的值相同。
+//!
+//! 相邻的段可能共用一页（例如代码段的结尾和数据段的开头），这样的段放在一起映射：
+//! 共用的页合并各段的权限，各段的内容按虚拟地址拷进同一页，不属于任何段文件内容的部分（包括 BSS）都是 0。
+
+use crate::{build_flags, parse_flags, Sv39, Sv39Manager};
+use alloc::vec::Vec;
+use core::ops::Range;
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VAddr, VmFlags},
+    AddressSpace,
+};
+use xmas_elf::{
+    header::{self, Data, HeaderPt2, Machine},
+    program::{self, ProgramHeader},
+    ElfFile,
+};
+
+/// `ET_DYN` 程序的最低加载地址。
+pub const PIE_BASE: usize = 0x1000_0000;
+
+const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+/// 用户页。
+const USER: VmFlags<Sv39> = build_flags("U___V");
+
+/// `PT_GNU_STACK` 段的类型。
+const PT_GNU_STACK: u32 = 0x6474_e551;
+/// 段权限位。
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// `.dynamic` 中的标记。
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+/// 重定位类型。
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+/// `Elf64_Rela` 的大小。
+const RELA_SIZE: usize = 24;
+
+/// 加载 ELF 时的错误。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+    /// 不是小端 RISC-V 64 位的 `ET_EXEC` 或 `ET_DYN`
+    NotExecutable,
+    /// 需要动态链接器
+    Interpreter,
+    /// 程序头表或段的内容超出文件，或者段的文件大小大于内存大小
+    Truncated,
+    /// `p_align` 不是 2 的幂，或者段的虚拟地址和文件偏移模 `p_align` 不同余
+    Misaligned,
+    /// 两个 `PT_LOAD` 段重叠
+    Overlap,
+    /// 段超出用户程序可用的地址范围
+    OutOfRange,
+    /// 段没有任何访问权限
+    NoPermission,
+    /// `.dynamic` 格式不对或者有不支持的重定位
+    Relocation,
+}
+
+impl From<LoadError> for crate::errno::Errno {
+    fn from(_: LoadError) -> Self {
+        Self::ENOEXEC
+    }
+}
+
+/// 加载好的程序。
+pub struct Image {
+    /// 入口地址
+    pub entry: usize,
+    /// 段占用的最高地址，堆从它所在页的下一页开始
+    pub end: usize,
+    /// 用户栈的权限，`PT_GNU_STACK` 要求可执行时带 `X`
+    pub stack_flags: VmFlags<Sv39>,
+}
+
+/// 一个 `PT_LOAD` 段。
+struct Segment {
+    /// 平移后的虚拟地址范围
+    mem: Range<usize>,
+    /// 文件内容在 ELF 中的范围
+    file: Range<usize>,
+    /// `p_flags` 中的权限位
+    perm: u32,
+}
+
+/// 把 `elf` 的段映射到 `space`，段必须落在 `limit` 以下。
+pub fn load(
+    elf: &ElfFile,
+    space: &mut AddressSpace<Sv39, Sv39Manager>,
+    limit: usize,
+) -> Result<Image, LoadError> {
+    let HeaderPt2::Header64(pt2) = elf.header.pt2 else {
+        return Err(LoadError::NotExecutable);
+    };
+    let pie = match pt2.type_.as_type() {
+        header::Type::Executable => false,
+        header::Type::SharedObject => true,
+        _ => return Err(LoadError::NotExecutable),
+    };
+    if elf.header.pt1.data() != Data::LittleEndian || pt2.machine.as_machine() != Machine::RISC_V {
+        return Err(LoadError::NotExecutable);
+    }
+    let headers = program_headers(elf)?;
+
+    // 先按文件中的地址检查各段，确定平移量后再检查地址范围
+    let mut segments = Vec::new();
+    let mut max_align = PAGE_SIZE;
+    let mut stack_perm = PF_R | PF_W;
+    let mut dynamic = None;
+    for ph in &headers {
+        match ph.get_type() {
+            Ok(program::Type::Load) => {}
+            Ok(program::Type::Interp) => return Err(LoadError::Interpreter),
+            Ok(program::Type::Dynamic) => {
+                dynamic = Some(file_range(elf, ph.offset(), ph.file_size())?);
+                continue;
+            }
+            Ok(program::Type::OsSpecific(PT_GNU_STACK)) => {
+                stack_perm = PF_R | PF_W | (ph.flags().0 & PF_X);
+                continue;
+            }
+            _ => continue,
+        }
+        if ph.mem_size() == 0 {
+            continue;
+        }
+        if ph.file_size() > ph.mem_size() {
+            return Err(LoadError::Truncated);
+        }
+        let align = ph.align().max(1);
+        if !align.is_power_of_two() || ph.virtual_addr() % align != ph.offset() % align {
+            return Err(LoadError::Misaligned);
+        }
+        max_align = max_align.max(align as usize);
+        let start = ph.virtual_addr() as usize;
+        let end = start
+            .checked_add(ph.mem_size() as usize)
+            .ok_or(LoadError::OutOfRange)?;
+        segments.push(Segment {
+            mem: start..end,
+            file: file_range(elf, ph.offset(), ph.file_size())?,
+            perm: ph.flags().0 & (PF_R | PF_W | PF_X),
+        });
+    }
+    if segments.is_empty() {
+        return Err(LoadError::NotExecutable);
+    }
+
+    // 位置无关的程序整体平移，平移量是最大对齐的倍数，段内的对齐关系不变
+    let bias = if pie {
+        PIE_BASE
+            .checked_next_multiple_of(max_align)
+            .ok_or(LoadError::OutOfRange)?
+    } else {
+        0
+    };
+    for seg in &mut segments {
+        let start = seg.mem.start.checked_add(bias);
+        let end = seg.mem.end.checked_add(bias);
+        match (start, end) {
+            (Some(start), Some(end)) if end <= limit => seg.mem = start..end,
+            _ => return Err(LoadError::OutOfRange),
+        }
+    }
+    segments.sort_unstable_by_key(|seg| seg.mem.start);
+    if segments.windows(2).any(|w| w[1].mem.start < w[0].mem.end) {
+        return Err(LoadError::Overlap);
+    }
+
+    // 共用页的相邻段分成一组一起映射
+    for group in segments.chunk_by(|a, b| b.mem.start & !PAGE_MASK < page_ceil(a.mem.end)) {
+        map_group(elf, space, group)?;
+    }
+    if let Some(dynamic) = dynamic.filter(|_| pie) {
+        relocate(elf, space, &headers, &elf.input[dynamic], bias)?;
+    }
+
+    let entry = (pt2.entry_point as usize)
+        .checked_add(bias)
+        .ok_or(LoadError::OutOfRange)?;
+    Ok(Image {
+        entry,
+        end: segments[segments.len() - 1].mem.end,
+        stack_flags: vm_flags(stack_perm)?,
+    })
+}
+
+/// 检查程序头表在文件范围内且是 `Elf64_Phdr` 数组，读出所有程序头。
+fn program_headers<'a>(elf: &ElfFile<'a>) -> Result<Vec<ProgramHeader<'a>>, LoadError> {
+    const PHDR_SIZE: usize = 56;
+    let pt2 = &elf.header.pt2;
+    let count = pt2.ph_count() as usize;
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let offset = pt2.ph_offset() as usize;
+    let table_end = count
+        .checked_mul(PHDR_SIZE)
+        .and_then(|size| size.checked_add(offset));
+    if pt2.ph_entry_size() as usize != PHDR_SIZE
+        || !table_end.is_some_and(|end| end <= elf.input.len())
+        || !(elf.input.as_ptr() as usize + offset).is_multiple_of(align_of::<u64>())
+    {
+        return Err(LoadError::Truncated);
+    }
+    (0..count as u16)
+        .map(|i| elf.program_header(i).map_err(|_| LoadError::Truncated))
+        .collect()
+}
+
+/// 文件偏移 `offset` 开始的 `size` 字节，必须在文件范围内。
+fn file_range(elf: &ElfFile, offset: u64, size: u64) -> Result<Range<usize>, LoadError> {
+    let start = offset as usize;
+    match start.checked_add(size as usize) {
+        Some(end) if end <= elf.input.len() => Ok(start..end),
+        _ => Err(LoadError::Truncated),
+    }
+}
+
+/// `addr` 向上取整到页边界。
+fn page_ceil(addr: usize) -> usize {
+    addr.div_ceil(PAGE_SIZE) * PAGE_SIZE
+}
+
+/// 把段权限转换成用户页的页表项标志。RISC-V 中可写不可读是保留的组合，可写的页同时可读。
+fn vm_flags(perm: u32) -> Result<VmFlags<Sv39>, LoadError> {
+    if perm == 0 {
+        return Err(LoadError::NoPermission);
+    }
+    let mut flags: [u8; 5] = *b"U___V";
+    if perm & PF_X != 0 {
+        flags[1] = b'X';
+    }
+    if perm & PF_W != 0 {
+        flags[2] = b'W';
+    }
+    if perm & (PF_R | PF_W) != 0 {
+        flags[3] = b'R';
+    }
+    Ok(parse_flags(unsafe { core::str::from_utf8_unchecked(&flags) }).unwrap())
+}
+
+/// 映射一组共用页的段。
+fn map_group(
+    elf: &ElfFile,
+    space: &mut AddressSpace<Sv39, Sv39Manager>,
+    group: &[Segment],
+) -> Result<(), LoadError> {
+    let base = group[0].mem.start & !PAGE_MASK;
+    let end = page_ceil(group[group.len() - 1].mem.end);
+    let vpn = |addr: usize| VAddr::<Sv39>::new(addr).floor();
+    if let [seg] = group {
+        space.map(
+            vpn(base)..vpn(end),
+            &elf.input[seg.file.clone()],
+            seg.mem.start - base,
+            vm_flags(seg.perm)?,
+        );
+        return Ok(());
+    }
+    // 各段的内容拷进同一块缓冲区，其余部分是 0
+    let mut data = alloc::vec![0u8; end - base];
+    for seg in group {
+        data[seg.mem.start - base..][..seg.file.len()]
+            .copy_from_slice(&elf.input[seg.file.clone()]);
+    }
+    // 每页的权限是与它相交的段的权限之和，权限相同的相邻页一起映射
+    let perm = |page: usize| {
+        group
+            .iter()
+            .filter(|seg| seg.mem.start < page + PAGE_SIZE && page < seg.mem.end)
+            .fold(0, |perm, seg| perm | seg.perm)
+    };
+    let mut page = base;
+    while page < end {
+        let run_perm = perm(page);
+        let mut run_end = page + PAGE_SIZE;
+        while run_end < end && perm(run_end) == run_perm {
+            run_end += PAGE_SIZE;
+        }
+        space.map(
+            vpn(page)..vpn(run_end),
+            &data[page - base..run_end - base],
+            0,
+            vm_flags(run_perm)?,
+        );
+        page = run_end;
+    }
+    Ok(())
+}
+
+/// 处理 `.dynamic` 中 `DT_RELA` 表的重定位，只支持 `R_RISCV_RELATIVE`。
+fn relocate(
+    elf: &ElfFile,
+    space: &AddressSpace<Sv39, Sv39Manager>,
+    headers: &[ProgramHeader],
+    dynamic: &[u8],
+    bias: usize,
+) -> Result<(), LoadError> {
+    // 读出 `bytes` 中第 `i` 个小端的 64 位字，`bytes` 的长度已经是 8 的倍数
+    let word = |bytes: &[u8], i: usize| u64::from_le_bytes(bytes[i * 8..][..8].try_into().unwrap());
+    let (mut rela, mut rela_size, mut rela_ent) = (None, 0, RELA_SIZE as u64);
+    for entry in dynamic.as_chunks::<16>().0 {
+        match word(entry, 0) {
+            DT_NULL => break,
+            DT_RELA => rela = Some(word(entry, 1)),
+            DT_RELASZ => rela_size = word(entry, 1),
+            DT_RELAENT => rela_ent = word(entry, 1),
+            _ => {}
+        }
+    }
+    let Some(rela) = rela else {
+        return Ok(());
+    };
+    if rela_ent != RELA_SIZE as u64 {
+        return Err(LoadError::Relocation);
+    }
+    // 重定位表的地址是虚拟地址，换算成文件偏移
+    let offset = headers
+        .iter()
+        .filter(|ph| matches!(ph.get_type(), Ok(program::Type::Load)))
+        .find(|ph| ph.virtual_addr() <= rela && rela - ph.virtual_addr() < ph.file_size())
+        .map(|ph| ph.offset() + (rela - ph.virtual_addr()))
+        .ok_or(LoadError::Relocation)?;
+    let table = file_range(elf, offset, rela_size).map_err(|_| LoadError::Relocation)?;
+    for entry in elf.input[table].as_chunks::<RELA_SIZE>().0 {
+        let (r_offset, r_info, r_addend) = (word(entry, 0), word(entry, 1), word(entry, 2));
+        match r_info & 0xffff_ffff {
+            R_RISCV_NONE => continue,
+            R_RISCV_RELATIVE => {}
+            _ => return Err(LoadError::Relocation),
+        }
+        let target = (r_offset as usize).wrapping_add(bias);
+        if !target.is_multiple_of(8) {
+            return Err(LoadError::Relocation);
+        }
+        // 只读的页也要写，通过内核的恒等映射直接写物理页
+        let ptr = space
+            .translate::<u64>(VAddr::new(target), USER)
+            .ok_or(LoadError::Relocation)?;
+        unsafe { ptr.as_ptr().write(r_addend.wrapping_add(bias as u64)) };
+    }
+    Ok(())
+}
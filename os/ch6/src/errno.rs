@@ -0,0 +1,46 @@
+//! ch6 的系统调用错误码。
+//!
+//! 编号与 Linux 一致，系统调用失败时返回错误码的相反数，写作 `-Errno::EINVAL`。
+//! 本章加入文件系统：打开不存在的文件返回 `ENOENT`，对未以相应方式打开的文件读写返回 `EBADF`，
+//! 链接到已有的名字返回 `EEXIST`，文件映射的权限超出文件的打开方式时返回 `EACCES`。
+
+use core::ops::Neg;
+
+/// 系统调用错误码，变体名沿用 Linux 的宏名。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 操作不允许
+    EPERM = 1,
+    /// 文件或目录不存在
+    ENOENT = 2,
+    /// 参数列表过长
+    E2BIG = 7,
+    /// 可执行文件格式错误
+    ENOEXEC = 8,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 没有子进程
+    ECHILD = 10,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 权限不足
+    EACCES = 13,
+    /// 地址无效
+    EFAULT = 14,
+    /// 已经存在
+    EEXIST = 17,
+    /// 参数无效
+    EINVAL = 22,
+}
+
+impl Neg for Errno {
+    type Output = isize;
+
+    /// 作为系统调用返回值的负数错误码。
+    #[inline]
+    fn neg(self) -> isize {
+        -(self as isize)
+    }
+}
@@ -0,0 +1,223 @@
+use crate::virtio_block::{Completion, VirtIOBlock, VIRTIO0};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use bitflags::bitflags;
+use spin::{Lazy, Mutex};
+use tg_console::log;
+use tg_easy_fs::{
+    block_cache_stats, block_cache_sync_all, BlockDevice, EasyFileSystem, Inode, BLOCK_CACHE_SIZE,
+    BLOCK_SZ, NAME_LENGTH_LIMIT,
+};
+
+bitflags! {
+    /// `open` 的标志，取值与用户库的 `OpenFlags` 一致。
+    pub struct OpenFlags: u32 {
+        /// 只读
+        const RDONLY = 0;
+        /// 只写
+        const WRONLY = 1 << 0;
+        /// 读写
+        const RDWR = 1 << 1;
+        /// 文件不存在时创建，存在时清空
+        const CREATE = 1 << 9;
+        /// 打开时清空
+        const TRUNC = 1 << 10;
+    }
+}
+
+/// 没有块设备时临时格式化的内存盘容量（块数），共 4 MiB。
+const RAM_DISK_BLOCKS: usize = 8192;
+
+/// 用内存模拟的块设备。
+struct RamDisk(Mutex<Vec<u8>>);
+
+impl RamDisk {
+    fn new(blocks: usize) -> Self {
+        Self(Mutex::new(vec![0u8; blocks * BLOCK_SZ]))
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let disk = self.0.lock();
+        buf.copy_from_slice(&disk[block_id * BLOCK_SZ..][..BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut disk = self.0.lock();
+        disk[block_id * BLOCK_SZ..][..BLOCK_SZ].copy_from_slice(buf);
+    }
+}
+
+/// 全局文件系统，首次访问时挂载 virtio 块设备上的磁盘镜像。
+pub static FS: Lazy<FileSystem> = Lazy::new(|| {
+    let efs = VirtIOBlock::new(VIRTIO0, Completion::Interrupt)
+        .and_then(|device| EasyFileSystem::open(Arc::new(device)))
+        .unwrap_or_else(|| {
+            log::warn!("no file system image, formatting an empty RAM disk");
+            let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(RAM_DISK_BLOCKS));
+            EasyFileSystem::create(device, RAM_DISK_BLOCKS as _, 1)
+        });
+    FileSystem {
+        root: EasyFileSystem::root_inode(&efs),
+    }
+});
+
+/// 只有根目录的扁平文件系统。
+pub struct FileSystem {
+    root: Inode,
+}
+
+impl FileSystem {
+    /// 按 `flags` 打开文件，必要时创建或截断。
+    pub fn open(&self, path: &str, flags: OpenFlags) -> Option<FileHandle> {
+        let name = Self::name_of(path)?;
+        let (readable, writable) = read_write(flags);
+        let inode = if flags.contains(OpenFlags::CREATE) {
+            match self.root.find(name) {
+                Some(inode) => {
+                    inode.clear();
+                    inode
+                }
+                None => self.root.create(name)?,
+            }
+        } else {
+            let inode = self.root.find(name)?;
+            if flags.contains(OpenFlags::TRUNC) {
+                inode.clear();
+            }
+            inode
+        };
+        Some(FileHandle::new(readable, writable, inode))
+    }
+
+    /// 查找文件 `path`。
+    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
+        self.root.find(Self::name_of(path)?)
+    }
+
+    /// 根目录下的所有文件名。
+    pub fn ls(&self) -> Vec<String> {
+        self.root.ls()
+    }
+
+    /// 为 `old` 建立硬链接 `new`。
+    pub fn link(&self, old: &str, new: &str) -> Option<()> {
+        self.root.link(Self::name_of(old)?, Self::name_of(new)?)
+    }
+
+    /// 删除目录项 `path`，链接数归零时回收 inode。
+    pub fn unlink(&self, path: &str) -> Option<()> {
+        self.root.unlink(Self::name_of(path)?)
+    }
+
+    /// 去掉开头的 `/`，并拒绝空名字和超长名字。
+    fn name_of(path: &str) -> Option<&str> {
+        let name = path.trim_start_matches('/');
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// 把块缓存中的脏块全部写回设备，并打印缓存命中统计。
+///
+/// 块缓存是写回式的，关机前必须调用，否则最近的修改不会落盘。
+pub fn sync() {
+    block_cache_sync_all();
+    let stats = block_cache_stats();
+    log::info!(
+        "block cache ({BLOCK_CACHE_SIZE} blocks): {} hits, {} misses, {} evictions, {} writebacks",
+        stats.hits,
+        stats.misses,
+        stats.evictions,
+        stats.writebacks,
+    );
+}
+
+/// 读出整个文件。
+pub fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut buf = vec![0u8; inode.size()];
+    let len = inode.read_at(0, &mut buf);
+    buf.truncate(len);
+    buf
+}
+
+/// 由打开标志得到 (可读, 可写)。
+fn read_write(flags: OpenFlags) -> (bool, bool) {
+    if flags.contains(OpenFlags::RDWR) {
+        (true, true)
+    } else if flags.contains(OpenFlags::WRONLY) {
+        (false, true)
+    } else {
+        (true, false)
+    }
+}
+
+/// 进程打开的文件。
+///
+/// 标准输入输出没有对应的 inode，只占用文件描述符。
+#[derive(Clone)]
+pub struct FileHandle {
+    readable: bool,
+    writable: bool,
+    inode: Option<Arc<Inode>>,
+    offset: usize,
+}
+
+impl FileHandle {
+    /// 不对应任何 inode 的文件描述符。
+    pub fn empty(readable: bool, writable: bool) -> Self {
+        Self {
+            readable,
+            writable,
+            inode: None,
+            offset: 0,
+        }
+    }
+
+    fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inode: Some(inode),
+            offset: 0,
+        }
+    }
+
+    /// 是否可读。
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    /// 是否可写。
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// 对应的 inode。
+    pub fn inode(&self) -> Option<&Arc<Inode>> {
+        self.inode.as_ref()
+    }
+
+    /// 从当前偏移读取，返回读到的字节数。
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let Some(inode) = &self.inode else {
+            return 0;
+        };
+        let n = inode.read_at(self.offset, buf);
+        self.offset += n;
+        n
+    }
+
+    /// 从当前偏移写入，返回写入的字节数。
+    pub fn write(&mut self, buf: &[u8]) -> usize {
+        let Some(inode) = &self.inode else {
+            return 0;
+        };
+        let n = inode.write_at(self.offset, buf);
+        self.offset += n;
+        n
+    }
+}
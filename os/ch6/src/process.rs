@@ -0,0 +1,308 @@
+use crate::{
+    args::InitStack,
+    build_flags,
+    elf::{self, LoadError},
+    fs::FileHandle,
+    map_portal,
+    mmap::SharedMapping,
+    processor::BIG_STRIDE,
+    stack::{RLimit, Stack, STACKS_BOTTOM},
+    user_buffer::UserBuffer,
+    Sv39, Sv39Manager, TIME_SLICE,
+};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+use spin::Mutex;
+use tg_kernel_context::{foreign::ForeignContext, LocalContext};
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VAddr, VmFlags, VPN},
+    AddressSpace,
+};
+use tg_task_manage::ProcId;
+use xmas_elf::ElfFile;
+
+/// 进程。
+pub struct Process {
+    /// 不可变
+    pub pid: ProcId,
+    /// 可变
+    pub context: ForeignContext,
+    pub address_space: AddressSpace<Sv39, Sv39Manager>,
+    /// 堆底
+    pub heap_bottom: usize,
+    /// 当前程序 break 位置
+    pub program_brk: usize,
+    /// stride 调度：当前步进值
+    pub stride: usize,
+    /// stride 调度：优先级（>= 2）
+    pub priority: usize,
+    /// 用完时间片被时钟中断打断的次数
+    pub ticks: usize,
+    /// 在用户态运行的总时间（`time` 计数）
+    pub run_time: u64,
+    /// 文件描述符表
+    pub fd_table: Vec<Option<Mutex<FileHandle>>>,
+    /// 用户栈，下标是栈槽编号
+    pub stacks: Vec<Stack>,
+    /// 栈大小限制，`fork` 和 `exec` 时保留
+    pub stack_rlimit: RLimit,
+    /// 共享映射，`fork` 的子进程映射同一组页
+    pub shared_maps: Vec<SharedMapping>,
+}
+
+impl Process {
+    /// 用 `elf` 替换进程的地址空间，初始用户栈为 `stack`。`elf` 无法加载时进程保持不变。
+    pub fn exec(&mut self, elf: ElfFile, stack: &InitStack) -> Result<(), LoadError> {
+        let proc = Process::from_elf(elf)?;
+        self.address_space = proc.address_space;
+        self.context = proc.context;
+        self.heap_bottom = proc.heap_bottom;
+        self.program_brk = proc.program_brk;
+        self.stacks = proc.stacks;
+        self.shared_maps = proc.shared_maps;
+        self.push_args(stack);
+        Ok(())
+    }
+
+    /// 把排布好的参数写到用户栈上，设置栈指针和 `_start` 的参数 `a0`、`a1`、`a2`。
+    pub fn push_args(&mut self, stack: &InitStack) {
+        UserBuffer::new(stack.sp, stack.data.len())
+            .write(&self.address_space, &stack.data)
+            .unwrap();
+        let ctx = &mut self.context.context;
+        *ctx.sp_mut() = stack.sp;
+        *ctx.a_mut(0) = stack.argc;
+        *ctx.a_mut(1) = stack.argv;
+        *ctx.a_mut(2) = stack.envp;
+    }
+
+    /// 记录进程刚刚运行了 `elapsed` 个 `time` 计数，按实际用掉的时间片比例增加 stride。
+    ///
+    /// 每次至少增加 1，频繁让出处理器的进程也会向前推进；至多增加一个完整的 `BIG_STRIDE / priority`，
+    /// 中断来得晚了也不会多算。
+    pub fn charge(&mut self, elapsed: u64) {
+        self.run_time += elapsed;
+        let pass = (BIG_STRIDE / self.priority).max(1);
+        let used = ((pass as u64).saturating_mul(elapsed) / TIME_SLICE) as usize;
+        self.stride = self.stride.wrapping_add(used.clamp(1, pass));
+    }
+
+    pub fn fork(&mut self) -> Option<Process> {
+        // 子进程 pid
+        let pid = ProcId::new();
+        // 复制父进程地址空间
+        let mut address_space: AddressSpace<Sv39, Sv39Manager> = AddressSpace::new();
+        // 共享映射不复制：复制时先从 areas 中拿掉，子进程再映射同一组页
+        let areas = self.address_space.areas.clone();
+        let shared_maps = &self.shared_maps;
+        self.address_space
+            .areas
+            .retain(|area| !shared_maps.iter().any(|mapping| mapping.overlaps(area)));
+        self.address_space.cloneself(&mut address_space);
+        self.address_space.areas = areas;
+        for mapping in &self.shared_maps {
+            mapping.map(&mut address_space);
+        }
+        map_portal(&address_space);
+        // 复制父进程上下文
+        let context = self.context.context.clone();
+        let satp = (8 << 60) | address_space.root_ppn().val();
+        let foreign_ctx = ForeignContext { context, satp };
+        // 复制父进程文件描述符表
+        let fd_table = self
+            .fd_table
+            .iter()
+            .map(|fd| fd.as_ref().map(|file| Mutex::new(file.lock().clone())))
+            .collect();
+        Some(Self {
+            pid,
+            context: foreign_ctx,
+            address_space,
+            heap_bottom: self.heap_bottom,
+            program_brk: self.program_brk,
+            stride: 0,
+            priority: self.priority,
+            ticks: 0,
+            run_time: 0,
+            fd_table,
+            stacks: self.stacks.clone(),
+            stack_rlimit: self.stack_rlimit,
+            shared_maps: self.shared_maps.clone(),
+        })
+    }
+
+    /// 加载 `elf` 创建进程，ELF 格式不对时返回 [`LoadError`]。
+    pub fn from_elf(elf: ElfFile) -> Result<Self, LoadError> {
+        let mut address_space = AddressSpace::new();
+        // 程序的段不能碰到栈槽
+        let image = elf::load(&elf, &mut address_space, STACKS_BOTTOM)?;
+
+        // 堆底从 ELF 加载的最高地址的下一页开始
+        let heap_bottom = VAddr::<Sv39>::new(image.end).ceil().base().val();
+
+        // 在第 0 个栈槽映射主线程的用户栈，栈页属于地址空间，进程被回收时一起释放
+        let stack = Stack::new(&mut address_space, 0, image.stack_flags);
+        // 映射异界传送门
+        map_portal(&address_space);
+
+        let mut context = LocalContext::user(image.entry);
+        let satp = (8 << 60) | address_space.root_ppn().val();
+        *context.sp_mut() = stack.top();
+        Ok(Self {
+            pid: ProcId::new(),
+            context: ForeignContext { context, satp },
+            address_space,
+            heap_bottom,
+            program_brk: heap_bottom,
+            stride: 0,
+            priority: 16,
+            ticks: 0,
+            run_time: 0,
+            fd_table: vec![
+                // stdin
+                Some(Mutex::new(FileHandle::empty(true, false))),
+                // stdout
+                Some(Mutex::new(FileHandle::empty(false, true))),
+                // stderr
+                Some(Mutex::new(FileHandle::empty(false, true))),
+            ],
+            stacks: vec![stack],
+            stack_rlimit: RLimit::STACK,
+            shared_maps: Vec::new(),
+        })
+    }
+
+    /// 修改程序 break 位置，返回旧的 break 地址，失败返回 None
+    ///
+    /// 堆最多长到栈槽的下边界 `STACKS_BOTTOM`，也不能长进 `mmap` 的区域。
+    pub fn change_program_brk(&mut self, size: isize) -> Option<usize> {
+        let old_brk = self.program_brk;
+        let new_brk = self.program_brk as isize + size;
+        if new_brk < self.heap_bottom as isize || new_brk as usize > STACKS_BOTTOM {
+            return None;
+        }
+        let new_brk = new_brk as usize;
+
+        let old_brk_ceil = VAddr::<Sv39>::new(old_brk).ceil();
+        let new_brk_ceil = VAddr::<Sv39>::new(new_brk).ceil();
+
+        if size > 0 {
+            // 扩展堆
+            if new_brk_ceil.val() > old_brk_ceil.val() {
+                if self.overlaps(&(old_brk_ceil..new_brk_ceil)) {
+                    return None;
+                }
+                // 需要映射新页面
+                self.address_space
+                    .map(old_brk_ceil..new_brk_ceil, &[], 0, build_flags("U_WRV"));
+            }
+        } else if size < 0 {
+            // 收缩堆
+            if old_brk_ceil.val() > new_brk_ceil.val() {
+                // 需要取消映射页面
+                Sv39Manager::unmap(&mut self.address_space, new_brk_ceil..old_brk_ceil);
+            }
+        }
+
+        self.program_brk = new_brk;
+        Some(old_brk)
+    }
+
+    /// 取消 `range` 的映射，释放其中属于地址空间的页；文件共享映射的页先写回文件。
+    pub fn unmap(&mut self, range: Range<VPN<Sv39>>) {
+        self.msync(&range);
+        Sv39Manager::unmap(&mut self.address_space, range.clone());
+        let mut shared_maps = Vec::new();
+        for mapping in self.shared_maps.drain(..) {
+            shared_maps.extend(mapping.cut(&range));
+        }
+        self.shared_maps = shared_maps;
+    }
+
+    /// 把 `range` 的权限改为 `flags`，共享映射记下新权限，`fork` 的子进程按新权限映射。
+    ///
+    /// `range` 中有没有映射的页，或者碰到栈槽时返回 `false`。
+    pub fn protect(&mut self, range: Range<VPN<Sv39>>, flags: VmFlags<Sv39>) -> bool {
+        if range.end.base().val() > STACKS_BOTTOM || !self.is_mapped(&range) {
+            return false;
+        }
+        Sv39Manager::protect(&mut self.address_space, range.clone(), flags);
+        let mut shared_maps = Vec::new();
+        for mapping in self.shared_maps.drain(..) {
+            shared_maps.extend(mapping.protect(&range, flags));
+        }
+        self.shared_maps = shared_maps;
+        true
+    }
+
+    /// `range` 中的页是否都已经映射。
+    fn is_mapped(&self, range: &Range<VPN<Sv39>>) -> bool {
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let area = self
+                .address_space
+                .areas
+                .iter()
+                .find(|area| area.start <= vpn && vpn < area.end);
+            match area {
+                Some(area) => vpn = area.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// 堆占用的页，从 `heap_bottom` 到当前 break 所在的页。
+    pub fn heap(&self) -> Range<VPN<Sv39>> {
+        VAddr::<Sv39>::new(self.heap_bottom).floor()..VAddr::<Sv39>::new(self.program_brk).ceil()
+    }
+
+    /// 在堆顶和栈槽之间从高往低找 `pages` 页连续的空闲虚拟地址，返回起始页号。
+    pub fn find_free(&self, pages: usize) -> Option<VPN<Sv39>> {
+        let floor = VAddr::<Sv39>::new(self.program_brk).ceil();
+        let mut end = VPN::<Sv39>::new(STACKS_BOTTOM >> Sv39::PAGE_BITS);
+        loop {
+            let start = VPN::new(end.val().checked_sub(pages)?);
+            if start < floor {
+                return None;
+            }
+            // 与已有区域重叠时，从重叠区域中最低的起点往下接着找
+            let lowest = self
+                .address_space
+                .areas
+                .iter()
+                .filter(|area| area.start < end && start < area.end)
+                .map(|area| area.start)
+                .min();
+            match lowest {
+                Some(lowest) => end = lowest,
+                None => return Some(start),
+            }
+        }
+    }
+
+    /// `range` 是否与已有的映射重叠。
+    pub fn overlaps(&self, range: &Range<VPN<Sv39>>) -> bool {
+        self.address_space
+            .areas
+            .iter()
+            .any(|area| area.start < range.end && range.start < area.end)
+    }
+
+    /// 把 `range` 中文件共享映射的页写回文件。
+    pub fn msync(&self, range: &Range<VPN<Sv39>>) {
+        for mapping in &self.shared_maps {
+            mapping.sync(range);
+        }
+    }
+
+    /// 处理用户对 `vpn` 的缺页，返回是否处理了缺页，没有处理说明访问非法。
+    ///
+    /// 目前只处理用户栈的扩展：`vpn` 在用户栈下方且没有超过栈大小限制时把栈扩展到 `vpn`。
+    pub fn handle_page_fault(&mut self, vpn: VPN<Sv39>) -> bool {
+        match Stack::index_of(vpn).and_then(|i| self.stacks.get_mut(i)) {
+            Some(stack) => stack.grow(&mut self.address_space, vpn, self.stack_rlimit.cur),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::process::Process;
+use alloc::collections::{BTreeMap, BinaryHeap};
+use core::{cell::UnsafeCell, cmp::Ordering};
+use tg_task_manage::{Manage, PManager, ProcId, Schedule};
+
+/// stride 调度的大步长常数
+pub const BIG_STRIDE: usize = 0x7fff_ffff;
+
+pub struct Processor {
+    inner: UnsafeCell<PManager<Process, ProcManager>>,
+}
+
+unsafe impl Sync for Processor {}
+
+impl Processor {
+    pub const fn new() -> Self {
+        Self {
+            inner: UnsafeCell::new(PManager::new()),
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&self) -> &mut PManager<Process, ProcManager> {
+        unsafe { &mut (*self.inner.get()) }
+    }
+}
+
+pub static PROCESSOR: Processor = Processor::new();
+
+/// 按环绕比较 stride，`a` 应当先于 `b` 运行时返回 `Less`。
+///
+/// stride 累加时允许溢出回绕。每次增加不超过 `BIG_STRIDE / 2`，重新就绪的进程也不会落后于最近一次调度的 stride，
+/// 所以就绪进程的 stride 两两相差不超过 `BIG_STRIDE / 2`，把差值当作有符号数就能比较先后。
+fn stride_cmp(a: usize, b: usize) -> Ordering {
+    (a.wrapping_sub(b) as isize).cmp(&0)
+}
+
+/// 就绪队列中的一项：stride 小的先出队，stride 相同时先进先出。
+struct Ready {
+    stride: usize,
+    seq: usize,
+    id: ProcId,
+}
+
+impl Ord for Ready {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` 是大顶堆，反过来比较，堆顶就是最先运行的
+        stride_cmp(other.stride, self.stride).then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Ready {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ready {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ready {}
+
+/// 任务管理器
+/// `tasks` 中保存所有的任务实体
+/// `ready_queue` 按 stride 保存就绪进程的 id
+/// `pass` 是最近一次取出的进程的 stride，新建和重新就绪的进程从这里开始
+pub struct ProcManager {
+    tasks: BTreeMap<ProcId, Process>,
+    ready_queue: BinaryHeap<Ready>,
+    pass: usize,
+    seq: usize,
+}
+
+impl ProcManager {
+    /// 新建任务管理器
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            ready_queue: BinaryHeap::new(),
+            pass: 0,
+            seq: 0,
+        }
+    }
+}
+
+impl Manage<Process, ProcId> for ProcManager {
+    /// 插入一个新任务，它的 stride 从当前的最小值开始
+    #[inline]
+    fn insert(&mut self, id: ProcId, mut task: Process) {
+        task.stride = self.pass;
+        self.tasks.insert(id, task);
+    }
+    /// 根据 id 获取对应的任务
+    #[inline]
+    fn get_mut(&mut self, id: ProcId) -> Option<&mut Process> {
+        self.tasks.get_mut(&id)
+    }
+    /// 删除任务实体
+    #[inline]
+    fn delete(&mut self, id: ProcId) {
+        self.tasks.remove(&id);
+    }
+}
+
+impl Schedule<ProcId> for ProcManager {
+    /// 添加 id 进入调度队列
+    ///
+    /// 阻塞了很久的进程 stride 可能远远落后，提到当前的最小值，免得它长时间独占处理器。
+    fn add(&mut self, id: ProcId) {
+        let Some(task) = self.tasks.get_mut(&id) else {
+            return;
+        };
+        if stride_cmp(task.stride, self.pass) == Ordering::Less {
+            task.stride = self.pass;
+        }
+        self.ready_queue.push(Ready {
+            stride: task.stride,
+            seq: self.seq,
+            id,
+        });
+        self.seq = self.seq.wrapping_add(1);
+    }
+    /// stride 调度：从就绪队列中取出 stride 最小的进程
+    ///
+    /// stride 在进程运行后按实际用掉的时间增加，见 `Process::charge`。
+    fn fetch(&mut self) -> Option<ProcId> {
+        while let Some(Ready { stride, id, .. }) = self.ready_queue.pop() {
+            // 已经删除的进程留下的项直接丢弃
+            if self.tasks.contains_key(&id) {
+                self.pass = stride;
+                return Some(id);
+            }
+        }
+        None
+    }
+}
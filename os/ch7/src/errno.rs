@@ -0,0 +1,57 @@
+//! ch7 的系统调用错误码。
+//!
+//! 编号与 Linux 一致，系统调用失败时返回错误码的相反数，写作 `-Errno::EINVAL`。
+//! 管道、信号和终端带来了会阻塞或被打断的操作：管道暂时读写不了时返回 `EAGAIN` 让进程稍后重试，
+//! 读端都关闭后写管道返回 `EPIPE`，等待终端输入时收到信号返回 `EINTR`，
+//! 对非终端做 `ioctl` 返回 `ENOTTY`，`kill` 找不到目标进程返回 `ESRCH`。
+
+use core::ops::Neg;
+
+/// 系统调用错误码，变体名沿用 Linux 的宏名。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 操作不允许
+    EPERM = 1,
+    /// 文件或目录不存在
+    ENOENT = 2,
+    /// 进程不存在
+    ESRCH = 3,
+    /// 系统调用被中断
+    EINTR = 4,
+    /// 参数列表过长
+    E2BIG = 7,
+    /// 可执行文件格式错误
+    ENOEXEC = 8,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 没有子进程
+    ECHILD = 10,
+    /// 资源暂时不可用，稍后重试
+    EAGAIN = 11,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 权限不足
+    EACCES = 13,
+    /// 地址无效
+    EFAULT = 14,
+    /// 已经存在
+    EEXIST = 17,
+    /// 参数无效
+    EINVAL = 22,
+    /// 不是终端
+    ENOTTY = 25,
+    /// 管道另一端已关闭
+    EPIPE = 32,
+}
+
+impl Neg for Errno {
+    type Output = isize;
+
+    /// 作为系统调用返回值的负数错误码。
+    #[inline]
+    fn neg(self) -> isize {
+        -(self as isize)
+    }
+}
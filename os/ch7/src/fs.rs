@@ -0,0 +1,297 @@
+use crate::{
+    errno::Errno,
+    processor::PROCESSOR,
+    tty::TTY,
+    virtio_block::{Completion, VirtIOBlock, VIRTIO0},
+};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use bitflags::bitflags;
+use spin::{Lazy, Mutex};
+use tg_console::log;
+use tg_easy_fs::{
+    block_cache_stats, block_cache_sync_all, BlockDevice, EasyFileSystem, Inode, BLOCK_CACHE_SIZE,
+    BLOCK_SZ, NAME_LENGTH_LIMIT,
+};
+
+bitflags! {
+    /// `open` 的标志，取值与用户库的 `OpenFlags` 一致。
+    pub struct OpenFlags: u32 {
+        /// 只读
+        const RDONLY = 0;
+        /// 只写
+        const WRONLY = 1 << 0;
+        /// 读写
+        const RDWR = 1 << 1;
+        /// 文件不存在时创建，存在时清空
+        const CREATE = 1 << 9;
+        /// 打开时清空
+        const TRUNC = 1 << 10;
+    }
+}
+
+/// 没有块设备时临时格式化的内存盘容量（块数），共 4 MiB。
+const RAM_DISK_BLOCKS: usize = 8192;
+
+/// 用内存模拟的块设备。
+struct RamDisk(Mutex<Vec<u8>>);
+
+impl RamDisk {
+    fn new(blocks: usize) -> Self {
+        Self(Mutex::new(vec![0u8; blocks * BLOCK_SZ]))
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let disk = self.0.lock();
+        buf.copy_from_slice(&disk[block_id * BLOCK_SZ..][..BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut disk = self.0.lock();
+        disk[block_id * BLOCK_SZ..][..BLOCK_SZ].copy_from_slice(buf);
+    }
+}
+
+/// 全局文件系统，首次访问时挂载 virtio 块设备上的磁盘镜像。
+pub static FS: Lazy<FileSystem> = Lazy::new(|| {
+    let efs = VirtIOBlock::new(VIRTIO0, Completion::Interrupt)
+        .and_then(|device| EasyFileSystem::open(Arc::new(device)))
+        .unwrap_or_else(|| {
+            log::warn!("no file system image, formatting an empty RAM disk");
+            let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(RAM_DISK_BLOCKS));
+            EasyFileSystem::create(device, RAM_DISK_BLOCKS as _, 1)
+        });
+    FileSystem {
+        root: EasyFileSystem::root_inode(&efs),
+    }
+});
+
+/// 只有根目录的扁平文件系统。
+pub struct FileSystem {
+    root: Inode,
+}
+
+impl FileSystem {
+    /// 按 `flags` 打开文件，必要时创建或截断。
+    pub fn open(&self, path: &str, flags: OpenFlags) -> Option<FileHandle> {
+        let name = Self::name_of(path)?;
+        let (readable, writable) = read_write(flags);
+        let inode = if flags.contains(OpenFlags::CREATE) {
+            match self.root.find(name) {
+                Some(inode) => {
+                    inode.clear();
+                    inode
+                }
+                None => self.root.create(name)?,
+            }
+        } else {
+            let inode = self.root.find(name)?;
+            if flags.contains(OpenFlags::TRUNC) {
+                inode.clear();
+            }
+            inode
+        };
+        Some(FileHandle::new(readable, writable, inode))
+    }
+
+    /// 查找文件 `path`。
+    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
+        self.root.find(Self::name_of(path)?)
+    }
+
+    /// 根目录下的所有文件名。
+    pub fn ls(&self) -> Vec<String> {
+        self.root.ls()
+    }
+
+    /// 为 `old` 建立硬链接 `new`。
+    pub fn link(&self, old: &str, new: &str) -> Option<()> {
+        self.root.link(Self::name_of(old)?, Self::name_of(new)?)
+    }
+
+    /// 删除目录项 `path`，链接数归零时回收 inode。
+    pub fn unlink(&self, path: &str) -> Option<()> {
+        self.root.unlink(Self::name_of(path)?)
+    }
+
+    /// 去掉开头的 `/`，并拒绝空名字和超长名字。
+    fn name_of(path: &str) -> Option<&str> {
+        let name = path.trim_start_matches('/');
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// 把块缓存中的脏块全部写回设备，并打印缓存命中统计。
+///
+/// 块缓存是写回式的，关机前必须调用，否则最近的修改不会落盘。
+pub fn sync() {
+    block_cache_sync_all();
+    let stats = block_cache_stats();
+    log::info!(
+        "block cache ({BLOCK_CACHE_SIZE} blocks): {} hits, {} misses, {} evictions, {} writebacks",
+        stats.hits,
+        stats.misses,
+        stats.evictions,
+        stats.writebacks,
+    );
+}
+
+/// 读出整个文件。
+pub fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut buf = vec![0u8; inode.size()];
+    let len = inode.read_at(0, &mut buf);
+    buf.truncate(len);
+    buf
+}
+
+/// 由打开标志得到 (可读, 可写)。
+fn read_write(flags: OpenFlags) -> (bool, bool) {
+    if flags.contains(OpenFlags::RDWR) {
+        (true, true)
+    } else if flags.contains(OpenFlags::WRONLY) {
+        (false, true)
+    } else {
+        (true, false)
+    }
+}
+
+/// 可以放进文件描述符表的对象：普通文件、标准输入输出、管道的一端。
+///
+/// 文件对象以 `Arc<dyn File>` 的形式被多个文件描述符（`dup`、`fork` 得到）共享，
+/// 读写位置等状态也随之共享。
+pub trait File: Send + Sync {
+    /// 是否可读。
+    fn readable(&self) -> bool;
+    /// 是否可写。
+    fn writable(&self) -> bool;
+    /// 读入 `buf`，返回读到的字节数；0 表示文件结束，`-EAGAIN` 表示暂时没有数据、稍后重试。
+    fn read(&self, buf: &mut [u8]) -> isize;
+    /// 写出 `buf`，返回写入的字节数；`-EAGAIN` 表示暂时写不进去、稍后重试。
+    fn write(&self, buf: &[u8]) -> isize;
+    /// 对应的 inode，只有普通文件才有。
+    fn inode(&self) -> Option<&Arc<Inode>> {
+        None
+    }
+    /// 是否是控制台终端，只有终端支持 `ioctl`。
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+/// 打开的普通文件。
+pub struct FileHandle {
+    readable: bool,
+    writable: bool,
+    inode: Arc<Inode>,
+    offset: Mutex<usize>,
+}
+
+impl FileHandle {
+    fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inode,
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for FileHandle {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, buf: &mut [u8]) -> isize {
+        let mut offset = self.offset.lock();
+        let n = self.inode.read_at(*offset, buf);
+        *offset += n;
+        n as _
+    }
+
+    fn write(&self, buf: &[u8]) -> isize {
+        let mut offset = self.offset.lock();
+        let n = self.inode.write_at(*offset, buf);
+        *offset += n;
+        n as _
+    }
+
+    fn inode(&self) -> Option<&Arc<Inode>> {
+        Some(&self.inode)
+    }
+}
+
+/// 标准输入，经过终端行规程从控制台读取。
+pub struct Stdin;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// 阻塞到有输入为止；等待期间收到可以递送的信号则返回 `-EINTR`，让信号尽快得到处理。
+    fn read(&self, buf: &mut [u8]) -> isize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let reader = PROCESSOR.get_mut().current().unwrap().pid.get_usize();
+        loop {
+            TTY.poll();
+            if let Some(n) = TTY.read(reader, buf) {
+                break n as _;
+            }
+            let current = PROCESSOR.get_mut().current();
+            if current.is_some_and(|process| process.signal.has_deliverable()) {
+                break -Errno::EINTR;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> isize {
+        -Errno::EBADF
+    }
+
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
+
+/// 标准输出和标准错误，写到 SBI 控制台。
+pub struct Stdout;
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> isize {
+        -Errno::EBADF
+    }
+
+    fn write(&self, buf: &[u8]) -> isize {
+        print!("{}", String::from_utf8_lossy(buf));
+        buf.len() as _
+    }
+
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,240 @@
+//! 控制台终端的行规程。
+//!
+//! 键盘输入先经过这里再交给读标准输入的进程：
+//!
+//! - `ISIG`：^C、^\、^Z 分别向前台进程组发送 `SIGINT`、`SIGQUIT`、`SIGTSTP`，字符本身被丢弃
+//! - `ICANON`：规范模式，按行编辑（退格、^U 删除整行），回车后整行才可读；空行上的 ^D 表示文件结束
+//! - `ECHO`：由内核回显输入
+//!
+//! 默认只打开 `ISIG`，其余行为与原来直接读 SBI 控制台相同，用户程序可以通过 `ioctl` 切换模式。
+//!
+//! 输入直接从 UART 的接收寄存器轮询，不会阻塞，因此可以在调度循环里调用。
+
+use crate::processor::{signal_group, PROCESSOR};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::ptr::read_volatile;
+use spin::Mutex;
+use tg_syscall::SignalNo;
+use tg_task_manage::ProcId;
+
+/// QEMU virt 上 16550 UART 的基址。
+const UART0: usize = 0x1000_0000;
+/// 需要映射进内核地址空间的 MMIO 区域：(起始地址, 长度)。
+pub const MMIO: &[(usize, usize)] = &[(UART0, 0x1000)];
+
+/// 收到中断字符时发送信号。
+pub const ISIG: u32 = 0o1;
+/// 规范模式。
+pub const ICANON: u32 = 0o2;
+/// 回显。
+pub const ECHO: u32 = 0o10;
+
+/// 读取终端模式，参数指向一个 `u32`，只包含 termios 的 `c_lflag`。
+pub const TCGETS: usize = 0x5401;
+/// 设置终端模式，参数同 [`TCGETS`]。
+pub const TCSETS: usize = 0x5402;
+/// 读取前台进程组，参数指向一个 `i32`。
+pub const TIOCGPGRP: usize = 0x540f;
+/// 设置前台进程组，参数同 [`TIOCGPGRP`]。
+pub const TIOCSPGRP: usize = 0x5410;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BS: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const CTRL_Z: u8 = 0x1a;
+const CTRL_BACKSLASH: u8 = 0x1c;
+const DEL: u8 = 0x7f;
+
+/// 控制台终端。
+pub struct Tty(Mutex<TtyInner>);
+
+struct TtyInner {
+    /// 本地模式标志
+    lflag: u32,
+    /// 已经可以读取的字节
+    ready: VecDeque<u8>,
+    /// 规范模式下正在编辑的行
+    line: Vec<u8>,
+    /// 规范模式下在空行上收到了 ^D
+    eof: bool,
+    /// 前台进程组，`None` 表示还没有进程设置过
+    foreground: Option<usize>,
+    /// 最近一个读终端的进程
+    last_reader: Option<usize>,
+}
+
+/// 唯一的控制台终端。
+pub static TTY: Tty = Tty(Mutex::new(TtyInner {
+    lflag: ISIG,
+    ready: VecDeque::new(),
+    line: Vec::new(),
+    eof: false,
+    foreground: None,
+    last_reader: None,
+}));
+
+impl Tty {
+    /// 取走控制台上已经到达的全部字符，必要时向前台进程组发送信号。
+    ///
+    /// 除了读标准输入时调用，调度循环每一轮也会调用，这样不读输入的进程也能被 ^C 打断。
+    pub fn poll(&self) {
+        while let Some(c) = uart_getchar() {
+            let mut inner = self.0.lock();
+            let Some(signum) = inner.input(c) else {
+                continue;
+            };
+            let (foreground, last_reader) = (inner.foreground, inner.last_reader);
+            drop(inner);
+            // 没有进程设置过前台进程组时，信号发给最近读终端的进程
+            let delivered = foreground.is_some_and(|pgid| signal_group(pgid, signum) > 0);
+            if !delivered {
+                let manager = PROCESSOR.get_mut();
+                if let Some(task) =
+                    last_reader.and_then(|pid| manager.get_task(ProcId::from_usize(pid)))
+                {
+                    task.signal.add(signum);
+                }
+            }
+        }
+    }
+
+    /// 读出可读的数据：规范模式下最多一行，非规范模式下有多少读多少。
+    ///
+    /// 返回 `None` 表示暂时没有输入，`Some(0)` 表示文件结束。
+    pub fn read(&self, reader: usize, buf: &mut [u8]) -> Option<usize> {
+        let mut inner = self.0.lock();
+        inner.last_reader = Some(reader);
+        if inner.ready.is_empty() {
+            return core::mem::take(&mut inner.eof).then_some(0);
+        }
+        let canonical = inner.lflag & ICANON != 0;
+        let mut n = 0;
+        while n < buf.len() {
+            let Some(c) = inner.ready.pop_front() else {
+                break;
+            };
+            buf[n] = c;
+            n += 1;
+            if canonical && c == b'\n' {
+                break;
+            }
+        }
+        Some(n)
+    }
+
+    /// 本地模式标志。
+    pub fn lflag(&self) -> u32 {
+        self.0.lock().lflag
+    }
+
+    /// 设置本地模式标志；离开规范模式时，正在编辑的行立即变为可读。
+    pub fn set_lflag(&self, lflag: u32) {
+        let mut inner = self.0.lock();
+        if lflag & ICANON == 0 {
+            let line = core::mem::take(&mut inner.line);
+            inner.ready.extend(line);
+        }
+        inner.lflag = lflag & (ISIG | ICANON | ECHO);
+    }
+
+    /// 前台进程组。
+    pub fn foreground(&self) -> Option<usize> {
+        self.0.lock().foreground
+    }
+
+    /// 设置前台进程组。
+    pub fn set_foreground(&self, pgid: usize) {
+        self.0.lock().foreground = Some(pgid);
+    }
+}
+
+/// 非阻塞地从 UART 读一个字符。
+fn uart_getchar() -> Option<u8> {
+    /// 线路状态寄存器
+    const LSR: usize = 5;
+    /// 接收缓冲有数据
+    const LSR_DR: u8 = 1;
+    unsafe {
+        if read_volatile((UART0 + LSR) as *const u8) & LSR_DR != 0 {
+            Some(read_volatile(UART0 as *const u8))
+        } else {
+            None
+        }
+    }
+}
+
+impl TtyInner {
+    /// 处理一个输入字符，返回需要发送的信号。
+    fn input(&mut self, c: u8) -> Option<usize> {
+        if self.lflag & ISIG != 0 {
+            let signum = match c {
+                CTRL_C => Some(SignalNo::SIGINT),
+                CTRL_BACKSLASH => Some(SignalNo::SIGQUIT),
+                CTRL_Z => Some(SignalNo::SIGTSTP),
+                _ => None,
+            };
+            if let Some(signum) = signum {
+                self.echo_control(c);
+                self.line.clear();
+                return Some(signum as usize);
+            }
+        }
+        if self.lflag & ICANON == 0 {
+            self.ready.push_back(c);
+            self.echo(c);
+            return None;
+        }
+        match c {
+            b'\r' | b'\n' => {
+                self.line.push(b'\n');
+                let line = core::mem::take(&mut self.line);
+                self.ready.extend(line);
+                self.echo(b'\n');
+            }
+            CTRL_D => {
+                if self.line.is_empty() {
+                    self.eof = true;
+                } else {
+                    let line = core::mem::take(&mut self.line);
+                    self.ready.extend(line);
+                }
+            }
+            BS | DEL => {
+                if self.line.pop().is_some() {
+                    self.erase();
+                }
+            }
+            CTRL_U => {
+                while self.line.pop().is_some() {
+                    self.erase();
+                }
+            }
+            c => {
+                self.line.push(c);
+                self.echo(c);
+            }
+        }
+        None
+    }
+
+    fn echo(&self, c: u8) {
+        if self.lflag & ECHO != 0 {
+            tg_sbi::console_putchar(c);
+        }
+    }
+
+    /// 回显控制字符，形如 `^C` 并换行。
+    fn echo_control(&self, c: u8) {
+        if self.lflag & ECHO != 0 {
+            println!("^{}", (c + b'@') as char);
+        }
+    }
+
+    /// 在屏幕上擦掉一个字符。
+    fn erase(&self) {
+        if self.lflag & ECHO != 0 {
+            print!("\x08 \x08");
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! virtio-mmio 块设备驱动。
+//!
+//! 面向 QEMU `virt` 机器，同时支持 legacy（version 1）和 modern（version 2）两种 MMIO 接口。
+//! 只使用一个长度为 [`QUEUE_SIZE`] 的 virtqueue，每次请求由 请求头、数据、状态 三个描述符组成，
+//! 同步等待完成。完成方式可以是轮询，也可以是经 PLIC 转发的外部中断。
+//!
+//! 描述符表、可用环、已用环以及请求缓冲都放在 [`Sv39Manager::page_alloc`] 分配的页上，
+//! 内核堆恒等映射，所以虚拟地址就是设备看到的物理地址。
+
+use crate::Sv39Manager;
+use core::{
+    ptr::{addr_of, addr_of_mut, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+use riscv::register::sie;
+use spin::Mutex;
+use tg_console::log;
+use tg_easy_fs::{BlockDevice, BLOCK_SZ};
+
+/// QEMU virt 上 PLIC 的基址。
+const PLIC: usize = 0x0c00_0000;
+/// 第一个 virtio-mmio 设备的基址。
+pub const VIRTIO0: usize = 0x1000_1000;
+/// 第一个 virtio-mmio 设备的外部中断号。
+const VIRTIO0_IRQ: usize = 1;
+/// 需要映射进内核地址空间的 MMIO 区域：(起始地址, 长度)。
+pub const MMIO: &[(usize, usize)] = &[(PLIC, 0x21_0000), (VIRTIO0, 0x1000)];
+
+/// virtqueue 长度。
+const QUEUE_SIZE: usize = 8;
+/// 页大小，也是 legacy 接口的 GuestPageSize 和 QueueAlign。
+const PAGE_SIZE: usize = 4096;
+
+/// MMIO 寄存器偏移。
+mod reg {
+    pub const MAGIC: usize = 0x000;
+    pub const VERSION: usize = 0x004;
+    pub const DEVICE_ID: usize = 0x008;
+    pub const DEVICE_FEATURES_SEL: usize = 0x014;
+    pub const DEVICE_FEATURES: usize = 0x010;
+    pub const DRIVER_FEATURES: usize = 0x020;
+    pub const DRIVER_FEATURES_SEL: usize = 0x024;
+    pub const GUEST_PAGE_SIZE: usize = 0x028;
+    pub const QUEUE_SEL: usize = 0x030;
+    pub const QUEUE_NUM_MAX: usize = 0x034;
+    pub const QUEUE_NUM: usize = 0x038;
+    pub const QUEUE_ALIGN: usize = 0x03c;
+    pub const QUEUE_PFN: usize = 0x040;
+    pub const QUEUE_READY: usize = 0x044;
+    pub const QUEUE_NOTIFY: usize = 0x050;
+    pub const INTERRUPT_STATUS: usize = 0x060;
+    pub const INTERRUPT_ACK: usize = 0x064;
+    pub const STATUS: usize = 0x070;
+    pub const QUEUE_DESC_LOW: usize = 0x080;
+    pub const QUEUE_DESC_HIGH: usize = 0x084;
+    pub const QUEUE_DRIVER_LOW: usize = 0x090;
+    pub const QUEUE_DRIVER_HIGH: usize = 0x094;
+    pub const QUEUE_DEVICE_LOW: usize = 0x0a0;
+    pub const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+}
+
+/// 设备状态位。
+mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+}
+
+/// "virt" 的小端表示。
+const MAGIC: u32 = 0x7472_6976;
+/// 块设备的设备号。
+const DEVICE_BLOCK: u32 = 2;
+/// modern 接口必须协商的 VIRTIO_F_VERSION_1：第 32 位，即高 32 位特性字的第 0 位。
+const F_VERSION_1_HIGH: u32 = 1;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const BLK_T_IN: u32 = 0;
+const BLK_T_OUT: u32 = 1;
+/// 设备还没写回状态时的占位值。
+const BLK_S_PENDING: u8 = 0xff;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+/// 块设备请求头。
+#[repr(C)]
+struct BlockRequest {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// 请求完成的等待方式。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Completion {
+    /// 忙等已用环更新。
+    Poll,
+    /// `wfi` 睡眠，由 PLIC 转发的外部中断唤醒。
+    Interrupt,
+}
+
+struct Inner {
+    base: usize,
+    desc: *mut Descriptor,
+    avail: *mut AvailRing,
+    used: *mut UsedRing,
+    req: *mut BlockRequest,
+    status: *mut u8,
+    data: *mut u8,
+    last_used: u16,
+    completion: Completion,
+}
+
+// 裸指针都指向驱动独占的 DMA 页和 MMIO 区域。
+unsafe impl Send for Inner {}
+
+/// virtio-mmio 块设备。
+pub struct VirtIOBlock(Mutex<Inner>);
+
+impl VirtIOBlock {
+    /// 探测并初始化 `base` 处的块设备，不是块设备或初始化失败时返回 `None`。
+    pub fn new(base: usize, completion: Completion) -> Option<Self> {
+        let read = |offset| unsafe { read_volatile((base + offset) as *const u32) };
+        let write =
+            |offset, value: u32| unsafe { write_volatile((base + offset) as *mut u32, value) };
+
+        if read(reg::MAGIC) != MAGIC || read(reg::DEVICE_ID) != DEVICE_BLOCK {
+            return None;
+        }
+        let version = read(reg::VERSION);
+        if version != 1 && version != 2 {
+            log::warn!("virtio-mmio version {version} is not supported");
+            return None;
+        }
+        // 复位并告知设备驱动已就位
+        write(reg::STATUS, 0);
+        let mut s = status::ACKNOWLEDGE | status::DRIVER;
+        write(reg::STATUS, s);
+        // 不使用任何可选特性
+        write(reg::DEVICE_FEATURES_SEL, 0);
+        let _ = read(reg::DEVICE_FEATURES);
+        write(reg::DRIVER_FEATURES_SEL, 0);
+        write(reg::DRIVER_FEATURES, 0);
+        if version == 2 {
+            write(reg::DRIVER_FEATURES_SEL, 1);
+            write(reg::DRIVER_FEATURES, F_VERSION_1_HIGH);
+            s |= status::FEATURES_OK;
+            write(reg::STATUS, s);
+            if read(reg::STATUS) & status::FEATURES_OK == 0 {
+                log::warn!("virtio-blk rejected the feature set");
+                return None;
+            }
+        }
+        // 初始化 0 号队列：第 0 页放描述符表和可用环，第 1 页放已用环，第 2 页放请求缓冲
+        write(reg::QUEUE_SEL, 0);
+        let max = read(reg::QUEUE_NUM_MAX) as usize;
+        if max < QUEUE_SIZE {
+            log::warn!("virtio-blk queue too short: {max}");
+            return None;
+        }
+        write(reg::QUEUE_NUM, QUEUE_SIZE as _);
+        let pages = Sv39Manager::page_alloc::<u8>(3) as usize;
+        let desc = pages;
+        let avail = pages + QUEUE_SIZE * core::mem::size_of::<Descriptor>();
+        let used = pages + PAGE_SIZE;
+        let dma = pages + 2 * PAGE_SIZE;
+        if version == 1 {
+            write(reg::GUEST_PAGE_SIZE, PAGE_SIZE as _);
+            write(reg::QUEUE_ALIGN, PAGE_SIZE as _);
+            write(reg::QUEUE_PFN, (pages / PAGE_SIZE) as _);
+        } else {
+            write(reg::QUEUE_DESC_LOW, desc as u32);
+            write(reg::QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            write(reg::QUEUE_DRIVER_LOW, avail as u32);
+            write(reg::QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            write(reg::QUEUE_DEVICE_LOW, used as u32);
+            write(reg::QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            write(reg::QUEUE_READY, 1);
+        }
+        s |= status::DRIVER_OK;
+        write(reg::STATUS, s);
+
+        let completion = match completion {
+            Completion::Interrupt if !enable_irq(VIRTIO0_IRQ) => {
+                log::warn!("supervisor external interrupt unavailable, polling virtio-blk");
+                Completion::Poll
+            }
+            c => c,
+        };
+        log::info!("virtio-blk v{version} at {base:#x}, completion: {completion:?}");
+        Some(Self(Mutex::new(Inner {
+            base,
+            desc: desc as _,
+            avail: avail as _,
+            used: used as _,
+            req: dma as _,
+            status: (dma + core::mem::size_of::<BlockRequest>()) as _,
+            data: (dma + BLOCK_SZ) as _,
+            last_used: 0,
+            completion,
+        })))
+    }
+}
+
+impl Inner {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as _
+    }
+
+    /// 提交一次单块读写并等待完成。
+    fn request(&mut self, block_id: usize, write: bool) {
+        unsafe {
+            self.req.write_volatile(BlockRequest {
+                type_: if write { BLK_T_OUT } else { BLK_T_IN },
+                reserved: 0,
+                sector: (block_id * BLOCK_SZ / 512) as _,
+            });
+            self.status.write_volatile(BLK_S_PENDING);
+            let chain = [
+                (self.req as usize, core::mem::size_of::<BlockRequest>(), 0),
+                (
+                    self.data as usize,
+                    BLOCK_SZ,
+                    if write { 0 } else { DESC_F_WRITE },
+                ),
+                (self.status as usize, 1, DESC_F_WRITE),
+            ];
+            for (i, &(addr, len, flags)) in chain.iter().enumerate() {
+                let next = i + 1 < chain.len();
+                self.desc.add(i).write_volatile(Descriptor {
+                    addr: addr as _,
+                    len: len as _,
+                    flags: flags | if next { DESC_F_NEXT } else { 0 },
+                    next: if next { (i + 1) as _ } else { 0 },
+                });
+            }
+            // 把描述符链 0 放进可用环
+            let idx = read_volatile(addr_of!((*self.avail).idx));
+            write_volatile(
+                addr_of_mut!((*self.avail).ring[idx as usize % QUEUE_SIZE]),
+                0,
+            );
+            fence(Ordering::SeqCst);
+            write_volatile(addr_of_mut!((*self.avail).idx), idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+            write_volatile(self.reg(reg::QUEUE_NOTIFY), 0);
+
+            // 等待已用环前进
+            while read_volatile(addr_of!((*self.used).idx)) == self.last_used {
+                match self.completion {
+                    Completion::Poll => core::hint::spin_loop(),
+                    Completion::Interrupt => riscv::asm::wfi(),
+                }
+                fence(Ordering::SeqCst);
+            }
+            self.last_used = self.last_used.wrapping_add(1);
+
+            // 应答设备，中断模式下还要向 PLIC 完成这次中断
+            let pending = read_volatile(self.reg(reg::INTERRUPT_STATUS));
+            write_volatile(self.reg(reg::INTERRUPT_ACK), pending);
+            if self.completion == Completion::Interrupt {
+                let irq = read_volatile(plic_claim());
+                if irq != 0 {
+                    write_volatile(plic_claim(), irq);
+                }
+            }
+
+            let status = self.status.read_volatile();
+            assert_eq!(status, 0, "virtio-blk request on block {block_id} failed");
+        }
+    }
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut inner = self.0.lock();
+        inner.request(block_id, false);
+        buf.copy_from_slice(unsafe { core::slice::from_raw_parts(inner.data, BLOCK_SZ) });
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut inner = self.0.lock();
+        unsafe { core::slice::from_raw_parts_mut(inner.data, BLOCK_SZ) }.copy_from_slice(buf);
+        inner.request(block_id, true);
+    }
+}
+
+/// hart 0 在 S 态对应的 PLIC 上下文。
+const PLIC_CONTEXT: usize = 1;
+
+/// 当前上下文的 claim/complete 寄存器。
+fn plic_claim() -> *mut u32 {
+    (PLIC + 0x20_0004 + 0x1000 * PLIC_CONTEXT) as _
+}
+
+/// 在 PLIC 和 `sie` 中打开外部中断 `irq`。
+///
+/// `sstatus.SIE` 保持关闭，中断只用来把 `wfi` 唤醒，不会真正陷入。
+/// 外部中断没有委托给 S 态时 `sie.SEIE` 写不进去，返回 `false`。
+fn enable_irq(irq: usize) -> bool {
+    unsafe {
+        // 优先级
+        write_volatile((PLIC + 4 * irq) as *mut u32, 1);
+        // 使能位
+        let enable = (PLIC + 0x2000 + 0x80 * PLIC_CONTEXT + irq / 32 * 4) as *mut u32;
+        write_volatile(enable, read_volatile(enable) | 1 << (irq % 32));
+        // 阈值
+        write_volatile((PLIC + 0x20_0000 + 0x1000 * PLIC_CONTEXT) as *mut u32, 0);
+        sie::set_sext();
+    }
+    sie::read().sext()
+}
@@ -0,0 +1,84 @@
+//! 程序参数和环境变量。
+//!
+//! `exec` 和 `spawn` 按 System V RISC-V 约定把参数放在新程序的用户栈上，从 `sp` 开始依次是：
+//!
+//! ```text
+//! argc
+//! argv[0] .. argv[argc - 1], 0
+//! envp[0] .. envp[envc - 1], 0
+//! auxv：(AT_PAGESZ, 页大小), (AT_NULL, 0)
+//! 填充
+//! 参数和环境变量字符串，以 0 结尾  <- 栈顶
+//! ```
+//!
+//! 同时 `a0`、`a1`、`a2` 分别是 `argc`、`argv`、`envp`，用户库的 `_start` 直接把它们当作参数。
+
+use crate::errno::Errno;
+use alloc::vec::Vec;
+
+/// 参数和环境变量一共最多占用的字节数，包括字符串和指针数组。
+pub const ARG_MAX: usize = 4096;
+
+/// 辅助向量中的页大小
+const AT_PAGESZ: usize = 6;
+/// 辅助向量的结束标记
+const AT_NULL: usize = 0;
+
+/// 排布好的初始用户栈。
+pub struct InitStack {
+    /// 新程序的栈指针，16 字节对齐
+    pub sp: usize,
+    /// 参数个数
+    pub argc: usize,
+    /// `argv` 数组的用户地址
+    pub argv: usize,
+    /// `envp` 数组的用户地址
+    pub envp: usize,
+    /// 要写到 `[sp, 栈顶)` 的内容
+    pub data: Vec<u8>,
+}
+
+impl InitStack {
+    /// 在栈顶 `top` 以下排布参数 `argv` 和环境变量 `envp`，字符串中不能有 0。
+    ///
+    /// 总大小超过 [`ARG_MAX`] 时返回 [`Errno::E2BIG`]。
+    pub fn new<S: AsRef<[u8]>>(top: usize, argv: &[S], envp: &[S]) -> Result<Self, Errno> {
+        const WORD: usize = size_of::<usize>();
+        let strings: usize = argv.iter().chain(envp).map(|s| s.as_ref().len() + 1).sum();
+        let words = 1 + argv.len() + 1 + envp.len() + 1 + 4;
+        if strings + words * WORD + 2 * 16 > ARG_MAX {
+            return Err(Errno::E2BIG);
+        }
+        let strings_base = (top - strings) & !15;
+        let sp = (strings_base - words * WORD) & !15;
+        let mut data = alloc::vec![0u8; top - sp];
+        // 写入字符串，记下每个字符串的用户地址
+        let mut pointers = Vec::with_capacity(words);
+        pointers.push(argv.len());
+        let mut addr = strings_base;
+        for (i, s) in argv.iter().chain(envp).enumerate() {
+            if i == argv.len() {
+                pointers.push(0);
+            }
+            let s = s.as_ref();
+            data[addr - sp..][..s.len()].copy_from_slice(s);
+            pointers.push(addr);
+            addr += s.len() + 1;
+        }
+        if envp.is_empty() {
+            pointers.push(0);
+        }
+        pointers.extend([0, AT_PAGESZ, 4096, AT_NULL, 0]);
+        // 写入 argc、两个指针数组和辅助向量
+        for (i, word) in pointers.iter().enumerate() {
+            data[i * WORD..][..WORD].copy_from_slice(&word.to_ne_bytes());
+        }
+        Ok(Self {
+            sp,
+            argc: argv.len(),
+            argv: sp + WORD,
+            envp: sp + (argv.len() + 2) * WORD,
+            data,
+        })
+    }
+}
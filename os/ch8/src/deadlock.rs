@@ -0,0 +1,149 @@
+//! 基于银行家算法的死锁检测。
+//!
+//! 进程中的每个互斥锁和信号量都是一类资源，互斥锁有 1 个资源，信号量的资源数是创建时的初值。
+//! 检测器按进程内线程号记录：
+//!
+//! - `available[r]`：资源 `r` 剩余可分配的数量
+//! - `allocation[t][r]`：线程 `t` 已经拿到的资源 `r` 的数量
+//! - `need[t][r]`：线程 `t` 还在等待的资源 `r` 的数量
+//!
+//! 不论是否开启检测都会维护这些矩阵，开启后每次加锁或 P 操作先假设请求已经记入 `need`，
+//! 再检查能否找到一个让所有线程都执行完的顺序；找不到说明满足请求会导致死锁，请求被拒绝且状态不变。
+
+use alloc::{vec, vec::Vec};
+
+/// 请求会导致死锁时系统调用的返回值。
+pub const DEADLOCK: isize = -0xdead;
+
+/// 参与死锁检测的资源。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resource {
+    /// 编号为参数的互斥锁
+    Mutex(usize),
+    /// 编号为参数的信号量
+    Semaphore(usize),
+}
+
+/// 一个进程的死锁检测器。
+pub struct DeadlockDetector {
+    enabled: bool,
+    /// 第 `r` 列对应的资源
+    resources: Vec<Resource>,
+    available: Vec<usize>,
+    allocation: Vec<Vec<usize>>,
+    need: Vec<Vec<usize>>,
+}
+
+impl DeadlockDetector {
+    /// 没有资源、没有开启检测的检测器。
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            resources: Vec::new(),
+            available: Vec::new(),
+            allocation: Vec::new(),
+            need: Vec::new(),
+        }
+    }
+
+    /// 开启或关闭检测。
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// 登记一类新资源，共有 `units` 个。
+    pub fn add_resource(&mut self, res: Resource, units: usize) {
+        self.resources.push(res);
+        self.available.push(units);
+        for row in self.allocation.iter_mut().chain(self.need.iter_mut()) {
+            row.push(0);
+        }
+    }
+
+    /// 线程 `tid` 申请一个 `res`。
+    ///
+    /// 开启检测且满足请求可能导致死锁时返回 `false`，状态保持不变；否则请求记入 `need`，
+    /// 之后由 [`grant`](Self::grant) 或 [`cancel`](Self::cancel) 了结。
+    pub fn request(&mut self, tid: usize, res: Resource) -> bool {
+        self.wait(tid, res);
+        if self.enabled && !self.is_safe() {
+            self.cancel(tid, res);
+            return false;
+        }
+        true
+    }
+
+    /// 线程 `tid` 等待一个 `res`，不做检测。用于条件变量唤醒后重新获得互斥锁，这一步不能失败。
+    pub fn wait(&mut self, tid: usize, res: Resource) {
+        let r = self.column(res);
+        self.row(tid);
+        self.need[tid][r] += 1;
+    }
+
+    /// 线程 `tid` 放弃一个还没有得到满足的请求。
+    pub fn cancel(&mut self, tid: usize, res: Resource) {
+        let r = self.column(res);
+        self.need[tid][r] -= 1;
+    }
+
+    /// 线程 `tid` 的请求得到满足，拿到一个 `res`。
+    pub fn grant(&mut self, tid: usize, res: Resource) {
+        let r = self.column(res);
+        self.need[tid][r] -= 1;
+        self.allocation[tid][r] += 1;
+        self.available[r] -= 1;
+    }
+
+    /// 线程 `tid` 释放一个 `res`。
+    ///
+    /// 信号量也可以由没有做过 P 操作的线程 V，这时只增加可分配的数量；
+    /// 互斥锁只能由持有者释放，否则什么也不做，可分配的数量不会超过 1。
+    pub fn release(&mut self, tid: usize, res: Resource) {
+        let r = self.column(res);
+        self.row(tid);
+        if self.allocation[tid][r] > 0 {
+            self.allocation[tid][r] -= 1;
+        } else if matches!(res, Resource::Mutex(_)) {
+            return;
+        }
+        self.available[r] += 1;
+    }
+
+    /// 线程号 `tid` 被回收，清空它的记录。
+    pub fn remove_thread(&mut self, tid: usize) {
+        if tid < self.allocation.len() {
+            self.allocation[tid].fill(0);
+            self.need[tid].fill(0);
+        }
+    }
+
+    /// 安全性检查：能否找到一个顺序，让每个线程依次拿到它等待的资源并执行完、归还已分配的资源。
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut finish = vec![false; self.need.len()];
+        loop {
+            let can_finish = |t: usize| self.need[t].iter().zip(&work).all(|(n, w)| n <= w);
+            let runnable = (0..finish.len()).find(|&t| !finish[t] && can_finish(t));
+            let Some(t) = runnable else {
+                break;
+            };
+            for (work, allocation) in work.iter_mut().zip(&self.allocation[t]) {
+                *work += allocation;
+            }
+            finish[t] = true;
+        }
+        finish.iter().all(|&finished| finished)
+    }
+
+    fn column(&self, res: Resource) -> usize {
+        self.resources.iter().position(|&r| r == res).unwrap()
+    }
+
+    /// 保证线程 `tid` 在矩阵中有一行。
+    fn row(&mut self, tid: usize) {
+        while self.allocation.len() <= tid {
+            self.allocation.push(vec![0; self.resources.len()]);
+            self.need.push(vec![0; self.resources.len()]);
+        }
+    }
+}
@@ -0,0 +1,1654 @@
+//! 第八章：并发
+//!
+//! 本章在第七章的基础上把进程拆成进程和线程：进程持有地址空间、文件描述符表和信号等资源，
+//! 线程持有上下文和用户栈，是调度的基本单位。支持 `thread_create`、`gettid` 和 `waittid`，
+//! 以及带等待队列和死锁检测的互斥锁、信号量和条件变量。
+#![no_std]
+#![no_main]
+#![cfg_attr(target_arch = "riscv64", deny(warnings, missing_docs))]
+#![cfg_attr(not(target_arch = "riscv64"), allow(dead_code, unused_imports))]
+
+mod args;
+mod deadlock;
+mod elf;
+mod errno;
+mod fs;
+mod mmap;
+mod pipe;
+mod process;
+mod processor;
+mod signal;
+mod stack;
+mod sync;
+mod tty;
+mod user_buffer;
+mod virtio_block;
+
+#[macro_use]
+extern crate tg_console;
+
+extern crate alloc;
+
+use crate::{
+    args::InitStack,
+    fs::{read_all, FS},
+    impls::{Console, Sv39Manager, SyscallContext},
+    process::{Process, Thread},
+    processor::{ProcManager, ThreadManager, PROCESSOR},
+    signal::SignalResult,
+    stack::STACK_TOP,
+    tty::TTY,
+};
+use alloc::{alloc::alloc, vec::Vec};
+use core::{alloc::Layout, cell::UnsafeCell, mem::MaybeUninit};
+use riscv::register::*;
+#[cfg(not(target_arch = "riscv64"))]
+use stub::Sv39;
+use tg_console::log;
+use tg_kernel_context::foreign::MultislotPortal;
+#[cfg(target_arch = "riscv64")]
+use tg_kernel_vm::page_table::Sv39;
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VAddr, VmFlags, VmMeta, PPN, VPN},
+    AddressSpace,
+};
+use tg_sbi;
+use tg_syscall::Caller;
+use tg_task_manage::{PThreadManager, ProcId};
+use xmas_elf::ElfFile;
+
+/// 构建 VmFlags。
+#[cfg(target_arch = "riscv64")]
+const fn build_flags(s: &str) -> VmFlags<Sv39> {
+    VmFlags::build_from_str(s)
+}
+
+/// 解析 VmFlags。
+#[cfg(target_arch = "riscv64")]
+fn parse_flags(s: &str) -> Result<VmFlags<Sv39>, ()> {
+    s.parse()
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+use stub::{build_flags, parse_flags};
+
+/// 时间片长度（`time` 计数），默认 12500，可以在构建时用环境变量 `TIME_SLICE` 修改。
+const TIME_SLICE: u64 = match option_env!("TIME_SLICE") {
+    Some(s) => parse_u64(s),
+    None => 12500,
+};
+
+/// 在编译期解析十进制整数。
+const fn parse_u64(s: &str) -> u64 {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "TIME_SLICE must not be empty");
+    let mut value = 0u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "TIME_SLICE must be a decimal number"
+        );
+        value = value * 10 + (bytes[i] - b'0') as u64;
+        i += 1;
+    }
+    assert!(value > 0, "TIME_SLICE must be positive");
+    value
+}
+
+// 定义内核入口。
+#[cfg(target_arch = "riscv64")]
+tg_linker::boot0!(rust_main; stack = 32 * 4096);
+// 物理内存容量 = 48 MiB。
+const MEMORY: usize = 48 << 20;
+// 传送门所在虚页。
+const PROTAL_TRANSIT: VPN<Sv39> = VPN::MAX;
+// 内核地址空间。
+struct KernelSpace {
+    inner: UnsafeCell<MaybeUninit<AddressSpace<Sv39, Sv39Manager>>>,
+}
+
+unsafe impl Sync for KernelSpace {}
+
+impl KernelSpace {
+    const fn new() -> Self {
+        Self {
+            inner: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    unsafe fn write(&self, space: AddressSpace<Sv39, Sv39Manager>) {
+        *self.inner.get() = MaybeUninit::new(space);
+    }
+
+    unsafe fn assume_init_ref(&self) -> &AddressSpace<Sv39, Sv39Manager> {
+        &*(*self.inner.get()).as_ptr()
+    }
+}
+
+static KERNEL_SPACE: KernelSpace = KernelSpace::new();
+/// 按名字在文件系统根目录中查找用户程序，读出整个 ELF。
+fn load_app(name: &str) -> Option<Vec<u8>> {
+    FS.find(name).map(|inode| read_all(&inode))
+}
+
+extern "C" fn rust_main() -> ! {
+    let layout = tg_linker::KernelLayout::locate();
+    // bss 段清零
+    unsafe { layout.zero_bss() };
+    // 初始化 `console`
+    tg_console::init_console(&Console);
+    tg_console::set_log_level(option_env!("LOG"));
+    tg_console::test_log();
+    // 初始化内核堆
+    tg_kernel_alloc::init(layout.start() as _);
+    unsafe {
+        tg_kernel_alloc::transfer(core::slice::from_raw_parts_mut(
+            layout.end() as _,
+            MEMORY - layout.len(),
+        ))
+    };
+    // 建立异界传送门
+    let portal_size = MultislotPortal::calculate_size(1);
+    let portal_layout = Layout::from_size_align(portal_size, 1 << Sv39::PAGE_BITS).unwrap();
+    let portal_ptr = unsafe { alloc(portal_layout) };
+    assert!(portal_layout.size() < 1 << Sv39::PAGE_BITS);
+    // 建立内核地址空间
+    kernel_space(layout, MEMORY, portal_ptr as _);
+    // 初始化异界传送门
+    let portal = unsafe { MultislotPortal::init_transit(PROTAL_TRANSIT.base().val(), 1) };
+    // 初始化 syscall
+    tg_syscall::init_io(&SyscallContext);
+    tg_syscall::init_process(&SyscallContext);
+    tg_syscall::init_scheduling(&SyscallContext);
+    tg_syscall::init_clock(&SyscallContext);
+    tg_syscall::init_memory(&SyscallContext);
+    tg_syscall::init_signal(&SyscallContext);
+    tg_syscall::init_thread(&SyscallContext);
+    tg_syscall::init_sync_mutex(&SyscallContext);
+    // 加载初始进程
+    let initproc_data = load_app("initproc").unwrap();
+    if let Ok((process, mut thread)) = Process::from_elf(ElfFile::new(&initproc_data).unwrap()) {
+        process.push_args(
+            &mut thread,
+            &InitStack::new(STACK_TOP, &["initproc"], &[]).unwrap(),
+        );
+        let manager = PROCESSOR.get_mut();
+        manager.set_manager(ThreadManager::new());
+        manager.set_proc_manager(ProcManager::new());
+        let pid = process.pid;
+        manager.add_proc(pid, process, ProcId::from_usize(usize::MAX));
+        manager.add(thread.tid, thread, pid);
+    }
+    // 打开时钟中断
+    unsafe { sie::set_stimer() };
+    loop {
+        // 收取键盘输入，^C 等控制字符在这里转换成信号
+        TTY.poll();
+        let processor: *mut PThreadManager<Process, Thread, ThreadManager, ProcManager> =
+            PROCESSOR.get_mut() as *mut _;
+        if let Some(task) = unsafe { (*processor).find_next() } {
+            let Some(process) = (unsafe { (*processor).get_current_proc() }) else {
+                unsafe { (*processor).make_current_exited(-1) };
+                continue;
+            };
+            // 进程已经退出，剩下的线程不再运行
+            if let Some(exit_code) = process.exit_code {
+                exit_thread(process, task, exit_code);
+                continue;
+            }
+            // 返回用户态之前处理信号
+            match process.signal.handle(&mut task.context.context) {
+                SignalResult::Continue => {}
+                SignalResult::Stopped => {
+                    unsafe { (*processor).make_current_suspend() };
+                    continue;
+                }
+                SignalResult::Killed(exit_code) => {
+                    exit_process(process, exit_code);
+                    exit_thread(process, task, exit_code);
+                    continue;
+                }
+            }
+            let start = time::read64();
+            tg_sbi::set_timer(start + TIME_SLICE);
+            unsafe { task.context.execute(portal, ()) };
+            task.charge(time::read64() - start);
+            match scause::read().cause() {
+                scause::Trap::Interrupt(scause::Interrupt::SupervisorTimer) => {
+                    tg_sbi::set_timer(u64::MAX);
+                    task.ticks += 1;
+                    unsafe { (*processor).make_current_suspend() };
+                }
+                scause::Trap::Exception(scause::Exception::UserEnvCall) => {
+                    use tg_syscall::{SyscallId as Id, SyscallResult as Ret};
+                    let ctx = &mut task.context.context;
+                    ctx.move_next();
+                    let nr = ctx.a(7);
+                    let id: Id = nr.into();
+                    let args = [ctx.a(0), ctx.a(1), ctx.a(2), ctx.a(3), ctx.a(4), ctx.a(5)];
+                    match tg_syscall::handle(Caller { entity: 0, flow: 0 }, id, args) {
+                        Ret::Done(ret) => match id {
+                            Id::EXIT => exit_thread(process, task, ret),
+                            Id::MUTEX_LOCK | Id::SEMAPHORE_DOWN | Id::CONDVAR_WAIT
+                                if ret == sync::BLOCKED =>
+                            {
+                                // 线程已进入等待队列，被唤醒时已经拿到资源
+                                *task.context.context.a_mut(0) = 0;
+                                unsafe { (*processor).make_current_blocked() };
+                            }
+                            Id::MUTEX_LOCK if ret == sync::RETRY => {
+                                // 自旋锁被占用：回到 ecall 指令，下次被调度时重新加锁
+                                *task.context.context.pc_mut() -= 4;
+                                unsafe { (*processor).make_current_suspend() };
+                            }
+                            _ => {
+                                let ctx = &mut task.context.context;
+                                *ctx.a_mut(0) = ret as _;
+                                unsafe { (*processor).make_current_suspend() };
+                            }
+                        },
+                        Ret::Unsupported(_) => match impls::handle_extra(process, nr, args) {
+                            Some(ret) => {
+                                *task.context.context.a_mut(0) = ret as _;
+                                unsafe { (*processor).make_current_suspend() };
+                            }
+                            None => {
+                                log::info!("id = {id:?}");
+                                exit_process(process, -2);
+                                exit_thread(process, task, -2);
+                            }
+                        },
+                    }
+                }
+                scause::Trap::Exception(
+                    e @ (scause::Exception::StorePageFault
+                    | scause::Exception::LoadPageFault
+                    | scause::Exception::InstructionPageFault),
+                ) => {
+                    let addr = stval::read();
+                    if process.handle_page_fault(VAddr::<Sv39>::new(addr).floor()) {
+                        unsafe { (*processor).make_current_suspend() };
+                    } else {
+                        log::error!(
+                            "unsupported trap: {e:?}, stval = {addr:#x}, sepc = {:#x}",
+                            task.context.context.pc()
+                        );
+                        exit_process(process, -3);
+                        exit_thread(process, task, -3);
+                    }
+                }
+                e => {
+                    log::error!("unsupported trap: {e:?}");
+                    exit_process(process, -3);
+                    exit_thread(process, task, -3);
+                }
+            }
+        } else {
+            println!("no task");
+            break;
+        }
+    }
+    fs::sync();
+    tg_sbi::shutdown(false)
+}
+
+/// 结束当前线程，主线程退出时整个进程随之退出。
+fn exit_thread(process: &mut Process, thread: &Thread, exit_code: isize) {
+    if thread.tid == process.main_tid {
+        exit_process(process, exit_code);
+    }
+    if process.exit_thread(thread) {
+        log::info!(
+            "process {} exited with code {}, {} ticks, {} cycles",
+            process.pid.get_usize(),
+            process.exit_code.unwrap_or(exit_code),
+            process.ticks,
+            process.run_time,
+        );
+    }
+    PROCESSOR.get_mut().make_current_exited(exit_code);
+}
+
+/// 让进程退出：阻塞的线程被放回就绪队列，所有线程在下次被调度到时退出。
+fn exit_process(process: &mut Process, exit_code: isize) {
+    let manager = PROCESSOR.get_mut();
+    for tid in process.exit(exit_code) {
+        manager.re_enque(tid);
+    }
+}
+
+/// Rust 异常处理函数，以异常方式关机。
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{info}");
+    tg_sbi::shutdown(true)
+}
+
+fn kernel_space(layout: tg_linker::KernelLayout, memory: usize, portal: usize) {
+    let mut space = AddressSpace::new();
+    for region in layout.iter() {
+        log::info!("{region}");
+        use tg_linker::KernelRegionTitle::*;
+        let flags = match region.title {
+            Text => "X_RV",
+            Rodata => "__RV",
+            Data | Boot => "_WRV",
+        };
+        let s = VAddr::<Sv39>::new(region.range.start);
+        let e = VAddr::<Sv39>::new(region.range.end);
+        space.map_extern(
+            s.floor()..e.ceil(),
+            PPN::new(s.floor().val()),
+            build_flags(flags),
+        )
+    }
+    let s = VAddr::<Sv39>::new(layout.end());
+    let e = VAddr::<Sv39>::new(layout.start() + memory);
+    log::info!("(heap) ---> {:#10x}..{:#10x}", s.val(), e.val());
+    space.map_extern(
+        s.floor()..e.ceil(),
+        PPN::new(s.floor().val()),
+        build_flags("_WRV"),
+    );
+    for &(base, len) in virtio_block::MMIO.iter().chain(tty::MMIO) {
+        let s = VAddr::<Sv39>::new(base);
+        let e = VAddr::<Sv39>::new(base + len);
+        log::info!("(mmio) ---> {:#10x}..{:#10x}", s.val(), e.val());
+        space.map_extern(
+            s.floor()..e.ceil(),
+            PPN::new(s.floor().val()),
+            build_flags("_WRV"),
+        );
+    }
+    space.map_extern(
+        PROTAL_TRANSIT..PROTAL_TRANSIT + 1,
+        PPN::new(portal >> Sv39::PAGE_BITS),
+        build_flags("__G_XWRV"),
+    );
+    println!();
+    unsafe { satp::set(satp::Mode::Sv39, 0, space.root_ppn().val()) };
+    unsafe { KERNEL_SPACE.write(space) };
+}
+
+/// 映射异界传送门。传送门所在的页表属于内核地址空间，用户地址空间回收时不释放。
+fn map_portal(space: &AddressSpace<Sv39, Sv39Manager>) {
+    let portal_idx = PROTAL_TRANSIT.index_in(Sv39::MAX_LEVEL);
+    let pte = unsafe { KERNEL_SPACE.assume_init_ref() }.root()[portal_idx];
+    space.root()[portal_idx] = Sv39Manager::share(pte);
+}
+
+/// 各种接口库的实现。
+mod impls {
+    use crate::{
+        args::{InitStack, ARG_MAX},
+        deadlock::{Resource, DEADLOCK},
+        errno::Errno,
+        fs::{OpenFlags, FS},
+        load_app,
+        mmap::{SharedMapping, SharedPages, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED},
+        pipe::make_pipe,
+        process::{Process as ProcStruct, Thread as ThreadStruct},
+        processor::{ProcManager, ThreadManager},
+        signal::{SignalAction, SignalState},
+        stack::{RLimit, RLIMIT_STACK, STACKS_BOTTOM, STACK_TOP},
+        sync::{self, Condvar, Mutex, Semaphore},
+        tty::{self, TTY},
+        user_buffer::{UserBuffer, UserPtr},
+        Sv39, PROCESSOR,
+    };
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc},
+        collections::BTreeMap,
+        string::String,
+        sync::Arc,
+        vec,
+        vec::Vec,
+    };
+    use core::{alloc::Layout, ops::Range, ptr::NonNull};
+    use tg_console::log;
+    use tg_kernel_vm::{
+        page_table::{MmuMeta, Pte, VAddr, VmFlags, VmMeta, PPN, VPN},
+        AddressSpace, PageManager,
+    };
+    use tg_syscall::*;
+    use tg_task_manage::{PThreadManager, ProcId, ThreadId};
+    use xmas_elf::ElfFile;
+
+    /// 线程管理器的完整类型。
+    type Manager = PThreadManager<ProcStruct, ThreadStruct, ThreadManager, ProcManager>;
+
+    /// 一次分配了多个页的块：起始物理页号 → (块的页数, 其中还没有释放的页数)。只有一页的不登记。
+    static BLOCKS: spin::Mutex<BTreeMap<usize, (usize, usize)>> = spin::Mutex::new(BTreeMap::new());
+
+    #[repr(transparent)]
+    pub struct Sv39Manager(NonNull<Pte<Sv39>>);
+
+    impl Sv39Manager {
+        const OWNED: VmFlags<Sv39> = unsafe { VmFlags::from_raw(1 << 8) };
+
+        /// 分配 `count` 个清零的物理页，内核堆恒等映射，也可以直接用作 DMA 缓冲。
+        #[inline]
+        pub fn page_alloc<T>(count: usize) -> *mut T {
+            let ptr = unsafe { alloc_zeroed(Self::block_layout(count)) };
+            if count > 1 {
+                let ppn = ptr as usize >> Sv39::PAGE_BITS;
+                BLOCKS.lock().insert(ppn, (count, count));
+            }
+            ptr.cast()
+        }
+
+        /// 释放从 `ptr` 开始的 `count` 个页。
+        ///
+        /// 这些页可能只是 [`page_alloc`](Self::page_alloc) 一次分配的块中的一部分，
+        /// 块中的页全部释放后才按分配时的大小把整块还给堆。
+        fn page_free<T>(ptr: *mut T, count: usize) {
+            let ppn = ptr as usize >> Sv39::PAGE_BITS;
+            let blocks = &mut BLOCKS.lock();
+            let (ptr, count) = match blocks.range_mut(..=ppn).next_back() {
+                Some((&start, (pages, live))) if ppn < start + *pages => {
+                    *live -= count;
+                    if *live > 0 {
+                        return;
+                    }
+                    let pages = *pages;
+                    blocks.remove(&start);
+                    ((start << Sv39::PAGE_BITS) as *mut u8, pages)
+                }
+                _ => (ptr.cast(), count),
+            };
+            unsafe { dealloc(ptr, Self::block_layout(count)) }
+        }
+
+        #[inline]
+        fn block_layout(count: usize) -> Layout {
+            unsafe {
+                Layout::from_size_align_unchecked(count << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS)
+            }
+        }
+
+        #[inline]
+        fn frame_ptr<T>(ppn: PPN<Sv39>) -> *mut T {
+            unsafe { VPN::<Sv39>::new(ppn.val()).base().as_mut_ptr() }
+        }
+
+        /// 去掉页表项的 `OWNED` 标记，用于把其他地址空间的页表项复制过来：回收页表时不会释放它指向的页。
+        pub fn share(pte: Pte<Sv39>) -> Pte<Sv39> {
+            let flags = unsafe { VmFlags::from_raw(pte.flags().val() & !Self::OWNED.val()) };
+            flags.build_pte(pte.ppn())
+        }
+
+        /// 找到 `vpn` 的叶子页表项，中间页表不存在时返回 `None`。
+        fn leaf(space: &mut AddressSpace<Sv39, Self>, vpn: VPN<Sv39>) -> Option<&mut Pte<Sv39>> {
+            let mut table = Self::frame_ptr::<Pte<Sv39>>(space.root_ppn());
+            for level in (1..=Sv39::MAX_LEVEL).rev() {
+                let pte = unsafe { *table.add(vpn.index_in(level)) };
+                if !pte.is_valid() || Sv39::is_leaf(pte.flags().val()) {
+                    return None;
+                }
+                table = Self::frame_ptr(pte.ppn());
+            }
+            Some(unsafe { &mut *table.add(vpn.index_in(0)) })
+        }
+
+        /// 把 `range` 中已经映射的页的权限改为 `flags`，保留 `OWNED` 标记。
+        pub fn protect(
+            space: &mut AddressSpace<Sv39, Self>,
+            range: Range<VPN<Sv39>>,
+            flags: VmFlags<Sv39>,
+        ) {
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(pte) = Self::leaf(space, vpn).filter(|pte| pte.is_valid()) {
+                    let raw = flags.val() | pte.flags().val() & Self::OWNED.val();
+                    *pte = unsafe { VmFlags::from_raw(raw) }.build_pte(pte.ppn());
+                }
+                vpn += 1;
+            }
+            unsafe { riscv::asm::sfence_vma_all() };
+        }
+
+        /// 取消 `range` 的映射，释放其中带 `OWNED` 标记的页，并从 `areas` 中截掉这一段。
+        pub fn unmap(space: &mut AddressSpace<Sv39, Self>, range: Range<VPN<Sv39>>) {
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(pte) = Self::leaf(space, vpn).filter(|pte| pte.is_valid()) {
+                    if pte.flags().contains(Self::OWNED) {
+                        Self::page_free(Self::frame_ptr::<u8>(pte.ppn()), 1);
+                    }
+                }
+                vpn += 1;
+            }
+            space.unmap(range);
+        }
+
+        /// 回收页表 `table` 指向的子树：`level` 层的页表项中，带 `OWNED` 标记的中间页表递归回收，
+        /// 带 `OWNED` 标记的叶子页通过 [`deallocate`](PageManager::deallocate) 释放，最后释放页表本身。
+        unsafe fn drop_table(&mut self, table: NonNull<Pte<Sv39>>, level: usize) {
+            for i in 0..1 << Sv39::LEVEL_BITS[level] {
+                let pte = *table.as_ptr().add(i);
+                if !pte.is_valid() || !self.check_owned(pte) {
+                    continue;
+                }
+                if level > 0 && !Sv39::is_leaf(pte.flags().val()) {
+                    self.drop_table(self.p_to_v(pte.ppn()), level - 1);
+                } else {
+                    self.deallocate(pte, 1);
+                }
+            }
+            Self::page_free(table.as_ptr(), 1);
+        }
+    }
+
+    impl PageManager<Sv39> for Sv39Manager {
+        #[inline]
+        fn new_root() -> Self {
+            Self(NonNull::new(Self::page_alloc(1)).unwrap())
+        }
+
+        #[inline]
+        fn root_ppn(&self) -> PPN<Sv39> {
+            PPN::new(self.0.as_ptr() as usize >> Sv39::PAGE_BITS)
+        }
+
+        #[inline]
+        fn root_ptr(&self) -> NonNull<Pte<Sv39>> {
+            self.0
+        }
+
+        #[inline]
+        fn p_to_v<T>(&self, ppn: PPN<Sv39>) -> NonNull<T> {
+            unsafe { NonNull::new_unchecked(VPN::<Sv39>::new(ppn.val()).base().as_mut_ptr()) }
+        }
+
+        #[inline]
+        fn v_to_p<T>(&self, ptr: NonNull<T>) -> PPN<Sv39> {
+            PPN::new(VAddr::<Sv39>::new(ptr.as_ptr() as _).floor().val())
+        }
+
+        #[inline]
+        fn check_owned(&self, pte: Pte<Sv39>) -> bool {
+            pte.flags().contains(Self::OWNED)
+        }
+
+        #[inline]
+        fn allocate(&mut self, len: usize, flags: &mut VmFlags<Sv39>) -> NonNull<u8> {
+            *flags |= Self::OWNED;
+            NonNull::new(Self::page_alloc(len)).unwrap()
+        }
+
+        fn deallocate(&mut self, pte: Pte<Sv39>, len: usize) -> usize {
+            if !self.check_owned(pte) {
+                return 0;
+            }
+            for i in 0..len {
+                Self::page_free(Self::frame_ptr::<u8>(pte.ppn() + i), 1);
+            }
+            len
+        }
+
+        fn drop_root(&mut self) {
+            if self.0 == NonNull::dangling() {
+                return;
+            }
+            unsafe { self.drop_table(self.0, Sv39::MAX_LEVEL) };
+            self.0 = NonNull::dangling();
+        }
+    }
+
+    impl Drop for Sv39Manager {
+        fn drop(&mut self) {
+            self.drop_root();
+        }
+    }
+
+    pub struct Console;
+
+    impl tg_console::Console for Console {
+        #[inline]
+        fn put_char(&self, c: u8) {
+            tg_sbi::console_putchar(c);
+        }
+    }
+
+    pub struct SyscallContext;
+
+    /// 从用户地址空间读取以 `\0` 结尾的字符串。
+    fn read_cstr(space: &AddressSpace<Sv39, Sv39Manager>, mut addr: usize) -> Option<String> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        let mut s = String::new();
+        loop {
+            let len = PAGE_SIZE - addr % PAGE_SIZE;
+            let chunk = UserBuffer::new(addr, len).read(space)?;
+            let end = chunk.iter().position(|&b| b == 0);
+            s.extend(chunk[..end.unwrap_or(len)].iter().map(|&b| b as char));
+            if end.is_some() {
+                break Some(s);
+            }
+            addr += len;
+        }
+    }
+
+    /// tg-syscall 分发表之外、由内核直接处理的系统调用，不认识的返回 `None`。
+    pub fn handle_extra(process: &mut ProcStruct, id: usize, args: [usize; 6]) -> Option<isize> {
+        const DUP: usize = 23;
+        const IOCTL: usize = 29;
+        const SETPGID: usize = 154;
+        const GETPGID: usize = 155;
+        const EXECVE_ARGS: usize = 421;
+        const SPAWN_ARGS: usize = 422;
+        const GETRLIMIT: usize = 163;
+        const SETRLIMIT: usize = 164;
+        const MPROTECT: usize = 226;
+        const MSYNC: usize = 227;
+        Some(match id {
+            DUP => process.dup(args[0]).map_or(-Errno::EBADF, |fd| fd as _),
+            IOCTL => ioctl(process, args[0], args[1], args[2]),
+            SETPGID => setpgid(process, args[0], args[1]),
+            GETPGID => getpgid(process, args[0]),
+            EXECVE_ARGS => exec_app(args[0], args[1], Some((args[2], args[3]))),
+            SPAWN_ARGS => spawn_app(args[0], args[1], Some((args[2], args[3]))),
+            GETRLIMIT => getrlimit(process, args[0], args[1]),
+            SETRLIMIT => setrlimit(process, args[0], args[1]),
+            MPROTECT => mprotect(process, args[0], args[1], args[2]),
+            MSYNC => msync(process, args[0], args[1], args[2]),
+            _ => return None,
+        })
+    }
+
+    /// 把 `[addr, addr + len)` 中文件共享映射的页写回文件。
+    ///
+    /// 写回总是同步完成的，`MS_ASYNC` 和 `MS_SYNC` 效果相同；私有映射是复制出来的，`MS_INVALIDATE` 没有作用。
+    fn msync(process: &mut ProcStruct, addr: usize, len: usize, flags: usize) -> isize {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        const MS_ASYNC: usize = 1;
+        const MS_INVALIDATE: usize = 2;
+        const MS_SYNC: usize = 4;
+        if !addr.is_multiple_of(PAGE_SIZE)
+            || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+            || flags & MS_ASYNC != 0 && flags & MS_SYNC != 0
+        {
+            return -Errno::EINVAL;
+        }
+        let Some(range) = page_range(addr, len) else {
+            return -Errno::ENOMEM;
+        };
+        let mut vpn = range.start;
+        while vpn < range.end {
+            if !process
+                .address_space
+                .areas
+                .iter()
+                .any(|area| area.start <= vpn && vpn < area.end)
+            {
+                return -Errno::ENOMEM;
+            }
+            vpn += 1;
+        }
+        process.msync(&range);
+        0
+    }
+
+    /// `[addr, addr + len)` 覆盖的页，`addr` 没有页对齐或者范围溢出时返回 `None`。
+    fn page_range(addr: usize, len: usize) -> Option<Range<VPN<Sv39>>> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        if !addr.is_multiple_of(PAGE_SIZE) {
+            return None;
+        }
+        let end = addr.checked_add(len)?.checked_add(PAGE_SIZE - 1)?;
+        Some(VPN::new(addr >> Sv39::PAGE_BITS)..VPN::new(end >> Sv39::PAGE_BITS))
+    }
+
+    /// 把 `mmap`/`mprotect` 的 `prot`（读 1、写 2、执行 4）转换为页表项权限。
+    ///
+    /// `prot` 为 0 或有其他位时返回 `None`。硬件不支持只写的页，可写的页同时可读。
+    fn prot_flags(prot: usize) -> Option<VmFlags<Sv39>> {
+        if prot & 0x7 == 0 || prot & !0x7 != 0 {
+            return None;
+        }
+        let mut flags: [u8; 5] = *b"U___V";
+        if prot & 0x4 != 0 {
+            flags[1] = b'X';
+        }
+        if prot & 0x2 != 0 {
+            flags[2] = b'W';
+        }
+        if prot & 0x3 != 0 {
+            flags[3] = b'R';
+        }
+        crate::parse_flags(unsafe { core::str::from_utf8_unchecked(&flags) }).ok()
+    }
+
+    /// 把 `[addr, addr + len)` 的权限改为 `prot`，范围中有没有映射的页或者碰到栈槽时返回 `-ENOMEM`。
+    fn mprotect(process: &mut ProcStruct, addr: usize, len: usize, prot: usize) -> isize {
+        let (Some(range), Some(flags)) = (page_range(addr, len), prot_flags(prot)) else {
+            return -Errno::EINVAL;
+        };
+        if process.protect(range, flags) {
+            0
+        } else {
+            -Errno::ENOMEM
+        }
+    }
+
+    /// 把资源 `resource` 的限制写到用户地址 `rlim`，目前只支持 `RLIMIT_STACK`。
+    fn getrlimit(process: &mut ProcStruct, resource: usize, rlim: usize) -> isize {
+        if resource != RLIMIT_STACK {
+            return -Errno::EINVAL;
+        }
+        match UserPtr::new(rlim).write(&process.address_space, process.stack_rlimit) {
+            Some(()) => 0,
+            None => -Errno::EFAULT,
+        }
+    }
+
+    /// 把资源 `resource` 的限制设为用户地址 `rlim` 处的值：软限制不能超过硬限制，硬限制只能调低。
+    ///
+    /// 调低栈的软限制不会回收已经映射的栈页，只是栈不能再扩展。
+    fn setrlimit(process: &mut ProcStruct, resource: usize, rlim: usize) -> isize {
+        if resource != RLIMIT_STACK {
+            return -Errno::EINVAL;
+        }
+        let Some(limit) = UserPtr::<RLimit>::new(rlim).read(&process.address_space) else {
+            return -Errno::EFAULT;
+        };
+        if limit.cur > limit.max {
+            -Errno::EINVAL
+        } else if limit.max > process.stack_rlimit.max {
+            -Errno::EPERM
+        } else {
+            process.stack_rlimit = limit;
+            0
+        }
+    }
+
+    /// 终端控制，只支持控制台终端上的 [`tty`](crate::tty) 命令。
+    fn ioctl(process: &mut ProcStruct, fd: usize, cmd: usize, arg: usize) -> isize {
+        if !matches!(process.fd_table.get(fd), Some(Some(file)) if file.is_tty()) {
+            return -Errno::ENOTTY;
+        }
+        let space = &process.address_space;
+        match cmd {
+            tty::TCGETS => match UserPtr::new(arg).write(space, TTY.lflag()) {
+                Some(()) => 0,
+                None => -Errno::EFAULT,
+            },
+            tty::TCSETS => match UserPtr::<u32>::new(arg).read(space) {
+                Some(lflag) => {
+                    TTY.set_lflag(lflag);
+                    0
+                }
+                None => -Errno::EFAULT,
+            },
+            tty::TIOCGPGRP => {
+                let pgid = TTY.foreground().unwrap_or(process.pgid) as i32;
+                match UserPtr::new(arg).write(space, pgid) {
+                    Some(()) => 0,
+                    None => -Errno::EFAULT,
+                }
+            }
+            tty::TIOCSPGRP => match UserPtr::<i32>::new(arg).read(space) {
+                Some(pgid) => match pgid {
+                    pgid if pgid > 0 => {
+                        TTY.set_foreground(pgid as _);
+                        0
+                    }
+                    _ => -Errno::EINVAL,
+                },
+                None => -Errno::EFAULT,
+            },
+            _ => -Errno::EINVAL,
+        }
+    }
+
+    /// 把进程 `pid`（0 表示调用者）移入进程组 `pgid`（0 表示以该进程的 pid 为组号）。
+    fn setpgid(process: &mut ProcStruct, pid: usize, pgid: usize) -> isize {
+        let target = if pid == 0 || pid == process.pid.get_usize() {
+            process
+        } else {
+            match PROCESSOR.get_mut().get_proc(ProcId::from_usize(pid)) {
+                Some(target) => target,
+                None => return -Errno::ESRCH,
+            }
+        };
+        target.pgid = if pgid == 0 {
+            target.pid.get_usize()
+        } else {
+            pgid
+        };
+        0
+    }
+
+    /// 进程 `pid`（0 表示调用者）的进程组号。
+    fn getpgid(process: &mut ProcStruct, pid: usize) -> isize {
+        if pid == 0 || pid == process.pid.get_usize() {
+            return process.pgid as _;
+        }
+        PROCESSOR
+            .get_mut()
+            .get_proc(ProcId::from_usize(pid))
+            .map_or(-Errno::ESRCH, |target| target.pgid as _)
+    }
+
+    /// 读出用户地址 `addr` 处以 0 结尾的参数字符串，不含结尾的 0。
+    fn read_arg(
+        space: &AddressSpace<Sv39, Sv39Manager>,
+        mut addr: usize,
+    ) -> Result<Vec<u8>, Errno> {
+        const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+        let mut s = Vec::new();
+        loop {
+            let len = PAGE_SIZE - addr % PAGE_SIZE;
+            let chunk = UserBuffer::new(addr, len)
+                .read(space)
+                .ok_or(Errno::EFAULT)?;
+            if let Some(end) = chunk.iter().position(|&b| b == 0) {
+                s.extend_from_slice(&chunk[..end]);
+                return Ok(s);
+            }
+            s.extend_from_slice(&chunk);
+            if s.len() > ARG_MAX {
+                return Err(Errno::E2BIG);
+            }
+            addr += len;
+        }
+    }
+
+    /// 读出用户地址 `addr` 处以 0 结尾的指针数组指向的全部字符串，`addr` 为 0 时视为空数组。
+    fn read_args(
+        space: &AddressSpace<Sv39, Sv39Manager>,
+        addr: usize,
+    ) -> Result<Vec<Vec<u8>>, Errno> {
+        const WORD: usize = size_of::<usize>();
+        let mut strings = Vec::new();
+        let mut total = 0;
+        if addr == 0 {
+            return Ok(strings);
+        }
+        loop {
+            let ptr_addr = addr
+                .checked_add(strings.len() * WORD)
+                .ok_or(Errno::EFAULT)?;
+            let arg = UserPtr::<usize>::new(ptr_addr)
+                .read(space)
+                .ok_or(Errno::EFAULT)?;
+            if arg == 0 {
+                return Ok(strings);
+            }
+            let s = read_arg(space, arg)?;
+            total += s.len() + 1 + WORD;
+            if total > ARG_MAX {
+                return Err(Errno::E2BIG);
+            }
+            strings.push(s);
+        }
+    }
+
+    /// 读出用户地址 `path` 处长度为 `count` 的应用名，找到对应的 ELF 数据，排布新程序主线程的初始用户栈。
+    ///
+    /// `args` 是 `argv` 和 `envp` 数组的用户地址，为 `None` 时参数只有应用名。
+    fn find_app(
+        space: &AddressSpace<Sv39, Sv39Manager>,
+        path: usize,
+        count: usize,
+        args: Option<(usize, usize)>,
+    ) -> Result<(Vec<u8>, InitStack), Errno> {
+        let name = UserBuffer::new(path, count)
+            .read(space)
+            .ok_or(Errno::EFAULT)?;
+        let (argv, envp) = match args {
+            Some((argv, envp)) => (read_args(space, argv)?, read_args(space, envp)?),
+            None => (vec![name.clone()], Vec::new()),
+        };
+        let stack = InitStack::new(STACK_TOP, &argv, &envp)?;
+        let data = core::str::from_utf8(&name)
+            .ok()
+            .and_then(load_app)
+            .ok_or(Errno::ENOENT)?;
+        Ok((data, stack))
+    }
+
+    /// 当前进程执行用户地址 `path` 处长度为 `count` 的应用，`args` 同 [`find_app`]。
+    ///
+    /// 成功时返回新程序的 `argc`：调度循环把返回值写进 `a0`，它正好是 `_start` 的第一个参数。
+    fn exec_app(path: usize, count: usize, args: Option<(usize, usize)>) -> isize {
+        let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+        let current = unsafe { (*processor).get_current_proc().unwrap() };
+        let thread = unsafe { (*processor).current().unwrap() };
+        match find_app(&current.address_space, path, count, args) {
+            Ok((data, stack)) => match ElfFile::new(&data) {
+                Ok(elf) => match current.exec(elf, thread, &stack) {
+                    Ok(()) => stack.argc as isize,
+                    Err(errno) => -errno,
+                },
+                Err(_) => -Errno::ENOEXEC,
+            },
+            Err(errno) => {
+                if errno == Errno::ENOENT {
+                    log::error!("unknown app, select one in the list: ");
+                    FS.ls().iter().for_each(|app| println!("{app}"));
+                    println!();
+                }
+                -errno
+            }
+        }
+    }
+
+    /// 以用户地址 `path` 处长度为 `count` 的应用创建子进程，`args` 同 [`find_app`]。
+    fn spawn_app(path: usize, count: usize, args: Option<(usize, usize)>) -> isize {
+        let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+        let current = unsafe { (*processor).get_current_proc().unwrap() };
+        let parent_pid = current.pid;
+        let result =
+            find_app(&current.address_space, path, count, args).and_then(|(data, stack)| {
+                let elf = ElfFile::new(&data).map_err(|_| Errno::ENOEXEC)?;
+                let (child, mut thread) = ProcStruct::from_elf(elf)?;
+                child.push_args(&mut thread, &stack);
+                Ok((child, thread))
+            });
+        match result {
+            Ok((child, thread)) => {
+                let pid = child.pid;
+                unsafe {
+                    (*processor).add_proc(pid, child, parent_pid);
+                    (*processor).add(thread.tid, thread, pid);
+                }
+                pid.get_usize() as isize
+            }
+            Err(errno) => -errno,
+        }
+    }
+
+    impl IO for SyscallContext {
+        fn write(&self, _caller: Caller, fd: usize, buf: usize, count: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let Some(data) = UserBuffer::new(buf, count).read(&current.address_space) else {
+                log::error!("ptr not readable");
+                return -Errno::EFAULT;
+            };
+            match current.fd_table.get(fd) {
+                Some(Some(file)) if file.writable() => file.write(&data),
+                Some(Some(_)) => {
+                    log::error!("file not writable");
+                    -Errno::EBADF
+                }
+                _ => {
+                    log::error!("unsupported fd: {fd}");
+                    -Errno::EBADF
+                }
+            }
+        }
+
+        fn read(&self, _caller: Caller, fd: usize, buf: usize, count: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let buffer = UserBuffer::new(buf, count);
+            if !buffer.writable(&current.address_space) {
+                log::error!("ptr not writeable");
+                return -Errno::EFAULT;
+            }
+            match current.fd_table.get(fd) {
+                Some(Some(file)) if file.readable() => {
+                    let mut data = vec![0; count];
+                    let n = file.read(&mut data);
+                    if n > 0 {
+                        buffer.write(&current.address_space, &data[..n as usize]);
+                    }
+                    n
+                }
+                Some(Some(_)) => {
+                    log::error!("file not readable");
+                    -Errno::EBADF
+                }
+                _ => {
+                    log::error!("unsupported fd: {fd}");
+                    -Errno::EBADF
+                }
+            }
+        }
+
+        fn open(&self, _caller: Caller, path: usize, flags: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let Some(path) = read_cstr(&current.address_space, path) else {
+                log::error!("path not readable");
+                return -Errno::EFAULT;
+            };
+            let flags = OpenFlags::from_bits_truncate(flags as _);
+            match FS.open(&path, flags) {
+                Some(file) => current.alloc_fd(Arc::new(file)) as _,
+                None => -Errno::ENOENT,
+            }
+        }
+
+        fn close(&self, _caller: Caller, fd: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            match current.fd_table.get_mut(fd) {
+                Some(file @ Some(_)) => {
+                    *file = None;
+                    0
+                }
+                _ => -Errno::EBADF,
+            }
+        }
+
+        fn pipe(&self, _caller: Caller, pipe: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let ptr = UserPtr::<[usize; 2]>::new(pipe);
+            if !ptr.writable(&current.address_space) {
+                log::error!("ptr not writeable");
+                return -Errno::EFAULT;
+            }
+            let (read_end, write_end) = make_pipe();
+            let read_fd = current.alloc_fd(read_end);
+            let write_fd = current.alloc_fd(write_end);
+            ptr.write(&current.address_space, [read_fd, write_fd]);
+            0
+        }
+
+        fn linkat(
+            &self,
+            _caller: Caller,
+            _olddirfd: i32,
+            oldpath: usize,
+            _newdirfd: i32,
+            newpath: usize,
+            _flags: u32,
+        ) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let old = read_cstr(&current.address_space, oldpath);
+            let new = read_cstr(&current.address_space, newpath);
+            match (old, new) {
+                (Some(_), Some(new)) if FS.find(&new).is_some() => -Errno::EEXIST,
+                (Some(old), Some(new)) => FS.link(&old, &new).map_or(-Errno::ENOENT, |_| 0),
+                _ => -Errno::EFAULT,
+            }
+        }
+
+        fn unlinkat(&self, _caller: Caller, _dirfd: i32, path: usize, _flags: u32) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            match read_cstr(&current.address_space, path) {
+                Some(path) => FS.unlink(&path).map_or(-Errno::ENOENT, |_| 0),
+                None => -Errno::EFAULT,
+            }
+        }
+
+        fn fstat(&self, _caller: Caller, fd: usize, st: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let Some(Some(file)) = current.fd_table.get(fd) else {
+                return -Errno::EBADF;
+            };
+            let Some(inode) = file.inode() else {
+                return -Errno::EINVAL;
+            };
+            let mut stat = Stat::new();
+            stat.ino = inode.inode_id() as _;
+            stat.mode = if inode.is_dir() {
+                StatMode::DIR
+            } else {
+                StatMode::FILE
+            };
+            stat.nlink = inode.nlink();
+            match UserPtr::new(st).write(&current.address_space, stat) {
+                Some(()) => 0,
+                None => {
+                    log::error!("ptr not writeable");
+                    -Errno::EFAULT
+                }
+            }
+        }
+    }
+
+    impl Process for SyscallContext {
+        #[inline]
+        fn exit(&self, _caller: Caller, exit_code: usize) -> isize {
+            exit_code as isize
+        }
+
+        fn fork(&self, _caller: Caller) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let parent_pid = current.pid; // 先保存父进程 pid
+            let (child_proc, mut child_thread) = current.fork(thread);
+            let pid = child_proc.pid;
+            let context = &mut child_thread.context.context;
+            *context.a_mut(0) = 0 as _;
+            unsafe {
+                (*processor).add_proc(pid, child_proc, parent_pid);
+                (*processor).add(child_thread.tid, child_thread, pid);
+            }
+            pid.get_usize() as isize
+        }
+
+        fn exec(&self, _caller: Caller, path: usize, count: usize) -> isize {
+            exec_app(path, count, None)
+        }
+
+        fn wait(&self, _caller: Caller, pid: isize, exit_code_ptr: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            if let Some((dead_pid, exit_code)) =
+                unsafe { (*processor).wait(ProcId::from_usize(pid as usize)) }
+            {
+                UserPtr::new(exit_code_ptr).write(&current.address_space, exit_code as i32);
+                return dead_pid.get_usize() as isize;
+            } else {
+                // 等待的子进程不存在
+                return -Errno::ECHILD;
+            }
+        }
+
+        fn getpid(&self, _caller: Caller) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            current.pid.get_usize() as _
+        }
+
+        // 实现 spawn 系统调用
+        fn spawn(&self, _caller: Caller, path: usize, count: usize) -> isize {
+            spawn_app(path, count, None)
+        }
+
+        fn sbrk(&self, _caller: Caller, size: i32) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            if let Some(old_brk) = current.change_program_brk(size as isize) {
+                old_brk as isize
+            } else {
+                -Errno::ENOMEM
+            }
+        }
+    }
+
+    impl Scheduling for SyscallContext {
+        #[inline]
+        fn sched_yield(&self, _caller: Caller) -> isize {
+            0
+        }
+
+        // 实现 set_priority 系统调用
+        fn set_priority(&self, _caller: Caller, prio: isize) -> isize {
+            if prio < 2 {
+                return -Errno::EINVAL;
+            }
+            let current = PROCESSOR.get_mut().current().unwrap();
+            current.priority = prio as usize;
+            prio
+        }
+    }
+
+    impl Clock for SyscallContext {
+        #[inline]
+        fn clock_gettime(&self, _caller: Caller, clock_id: ClockId, tp: usize) -> isize {
+            match clock_id {
+                ClockId::CLOCK_MONOTONIC => {
+                    let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+                    let time = riscv::register::time::read() * 10000 / 125;
+                    let time = TimeSpec {
+                        tv_sec: time / 1_000_000_000,
+                        tv_nsec: time % 1_000_000_000,
+                    };
+                    match UserPtr::new(tp).write(&current.address_space, time) {
+                        Some(()) => 0,
+                        None => {
+                            log::error!("ptr not writable");
+                            -Errno::EFAULT
+                        }
+                    }
+                }
+                _ => -Errno::EINVAL,
+            }
+        }
+    }
+
+    impl Memory for SyscallContext {
+        fn mmap(
+            &self,
+            _caller: Caller,
+            addr: usize,
+            len: usize,
+            prot: i32,
+            flags: i32,
+            fd: i32,
+            offset: usize,
+        ) -> isize {
+            const PAGE_SIZE: usize = 1 << <Sv39 as MmuMeta>::PAGE_BITS;
+
+            let (Some(range), Some(vm_flags)) = (page_range(addr, len), prot_flags(prot as usize))
+            else {
+                return -Errno::EINVAL;
+            };
+            if !offset.is_multiple_of(PAGE_SIZE) {
+                return -Errno::EINVAL;
+            }
+            if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0
+                || flags & MAP_SHARED != 0 && flags & MAP_PRIVATE != 0
+            {
+                return -Errno::EINVAL;
+            }
+            let fixed = flags & MAP_FIXED != 0;
+            // 不能映射第 0 页，空指针总是非法
+            if addr == 0 && fixed {
+                return -Errno::EPERM;
+            }
+            if range.is_empty() {
+                return 0;
+            }
+            let len_aligned = (range.end.val() - range.start.val()) * PAGE_SIZE;
+
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+
+            // `flags` 为 0 是前几章的匿名私有映射，否则不带 `MAP_ANONYMOUS` 时映射文件 `fd`
+            let file = if flags & !MAP_FIXED == 0 || flags & MAP_ANONYMOUS != 0 {
+                None
+            } else {
+                let Some(Some(file)) = current.fd_table.get(fd as usize) else {
+                    return -Errno::EBADF;
+                };
+                // 映射的每一页在文件中的位置都不能溢出
+                if offset.checked_add(len_aligned).is_none() {
+                    return -Errno::EINVAL;
+                }
+                // 文件要可读，可写的共享映射还要求文件可写；标准输入输出不能映射
+                let writable = flags & MAP_SHARED != 0 && prot & 0x2 != 0;
+                match file.inode() {
+                    Some(inode) if file.readable() && (file.writable() || !writable) => {
+                        Some((inode.clone(), offset))
+                    }
+                    _ => return -Errno::EACCES,
+                }
+            };
+
+            let range = if addr == 0 && !fixed {
+                // 由内核挑选地址
+                let pages = range.end.val() - range.start.val();
+                match current.find_free(pages) {
+                    Some(start) => start..start + pages,
+                    None => return -Errno::ENOMEM,
+                }
+            } else {
+                range
+            };
+            // 映射不能碰到栈槽
+            if range.end.base().val() > STACKS_BOTTOM {
+                return -Errno::ENOMEM;
+            }
+
+            if fixed {
+                // 固定地址的映射替换掉原有的映射，但不能拿走堆
+                let heap = current.heap();
+                if !heap.is_empty() && range.start < heap.end && heap.start < range.end {
+                    return -Errno::EEXIST;
+                }
+                current.unmap(range.clone());
+            } else if current.overlaps(&range) {
+                return -Errno::EEXIST;
+            }
+
+            let start = range.start.base().val();
+            if flags & MAP_SHARED != 0 {
+                let pages = SharedPages::new(len_aligned / PAGE_SIZE, file);
+                let mapping =
+                    SharedMapping::new(&mut current.address_space, range, vm_flags, pages);
+                current.shared_maps.push(mapping);
+            } else if let Some((inode, offset)) = file {
+                // 私有的文件映射复制一份文件内容
+                let mut data = vec![0u8; len_aligned];
+                let len = inode.read_at(offset, &mut data);
+                current.address_space.map(range, &data[..len], 0, vm_flags);
+            } else {
+                current.address_space.map(range, &[], 0, vm_flags);
+            }
+            // 指定地址时沿用前几章的约定返回 0，否则返回内核挑选的地址
+            if addr == 0 {
+                start as isize
+            } else {
+                0
+            }
+        }
+
+        fn munmap(&self, _caller: Caller, addr: usize, len: usize) -> isize {
+            let Some(range) = page_range(addr, len) else {
+                return -Errno::EINVAL;
+            };
+            if range.is_empty() {
+                return 0;
+            }
+
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+
+            let mut vpn = range.start;
+            while vpn < range.end {
+                let covered = current.address_space.areas.iter().any(|area| {
+                    vpn >= area.start && vpn < area.end
+                });
+                if !covered {
+                    return -Errno::EINVAL;
+                }
+                vpn = vpn + 1;
+            }
+
+            current.unmap(range);
+            0
+        }
+    }
+
+    impl Signal for SyscallContext {
+        fn kill(&self, _caller: Caller, pid: isize, signum: u8) -> isize {
+            let signum = signum as usize;
+            if pid <= 0 || !SignalState::is_valid(signum) {
+                return -Errno::EINVAL;
+            }
+            let pid = ProcId::from_usize(pid as usize);
+            match PROCESSOR.get_mut().get_proc(pid) {
+                Some(target) => {
+                    target.signal.add(signum);
+                    // 线程都阻塞在同步对象上时没有机会处理信号，SIGKILL 直接让进程退出
+                    if signum == SignalNo::SIGKILL as usize {
+                        crate::exit_process(target, -(signum as isize));
+                    }
+                    0
+                }
+                None => -Errno::ESRCH,
+            }
+        }
+
+        fn sigaction(
+            &self,
+            _caller: Caller,
+            signum: u8,
+            action: usize,
+            old_action: usize,
+        ) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let new = if action == 0 {
+                None
+            } else {
+                match UserPtr::<SignalAction>::new(action).read(&current.address_space) {
+                    Some(action) => Some(action),
+                    None => return -Errno::EFAULT,
+                }
+            };
+            let old_ptr = if old_action == 0 {
+                None
+            } else {
+                let ptr = UserPtr::<SignalAction>::new(old_action);
+                if !ptr.writable(&current.address_space) {
+                    return -Errno::EFAULT;
+                }
+                Some(ptr)
+            };
+            let Some(old) = current.signal.set_action(signum as _, new) else {
+                return -Errno::EINVAL;
+            };
+            if let Some(ptr) = old_ptr {
+                ptr.write(&current.address_space, old);
+            }
+            0
+        }
+
+        fn sigprocmask(&self, _caller: Caller, mask: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            current.signal.set_mask(mask) as _
+        }
+
+        fn sigreturn(&self, _caller: Caller) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            // 返回值会写回 a0，所以要返回被打断时的 a0
+            current
+                .signal
+                .sigreturn(&mut thread.context.context)
+                .map_or(-Errno::EINVAL, |a0| a0 as _)
+        }
+    }
+
+    impl Thread for SyscallContext {
+        fn thread_create(&self, _caller: Caller, entry: usize, arg: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let pid = current.pid;
+            let Some(thread) = current.new_thread(entry, arg) else {
+                return -Errno::EAGAIN;
+            };
+            let local_tid = thread.local_tid;
+            unsafe { (*processor).add(thread.tid, thread, pid) };
+            local_tid as _
+        }
+
+        fn gettid(&self, _caller: Caller) -> isize {
+            let current = PROCESSOR.get_mut().current().unwrap();
+            current.local_tid as _
+        }
+
+        fn waittid(&self, _caller: Caller, tid: usize) -> isize {
+            // tg-task-manage 和用户库约定用 -2 表示线程还没退出，用户库见到它会让出处理器后重试；
+            // 它是约定的返回值而不是错误码，数值恰好与 `-ENOENT` 相同
+            const RUNNING: isize = -2;
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            // 线程不能等待自己
+            if thread.local_tid == tid {
+                return -Errno::EDEADLK;
+            }
+            let Some(target) = current.thread(tid) else {
+                return -Errno::ESRCH;
+            };
+            match unsafe { (*processor).waittid(target) } {
+                Some(RUNNING) => RUNNING,
+                Some(exit_code) => {
+                    current.release_thread(tid);
+                    exit_code
+                }
+                None => -Errno::ESRCH,
+            }
+        }
+    }
+
+    /// 线程 `tid` 在所属进程中的线程号。
+    fn local_tid(tid: ThreadId) -> usize {
+        PROCESSOR.get_mut().get_task(tid).unwrap().local_tid
+    }
+
+    /// 资源 `res` 被直接交给等待它的线程 `tid`：记入死锁检测，并把线程放回就绪队列。
+    fn hand_over(process: &mut ProcStruct, tid: ThreadId, res: Resource) {
+        process.deadlock.grant(local_tid(tid), res);
+        PROCESSOR.get_mut().re_enque(tid);
+    }
+
+    impl SyncMutex for SyscallContext {
+        fn semaphore_create(&self, _caller: Caller, res_count: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let sem_id = current.semaphores.len();
+            current.semaphores.push(Arc::new(Semaphore::new(res_count)));
+            current
+                .deadlock
+                .add_resource(Resource::Semaphore(sem_id), res_count);
+            sem_id as _
+        }
+
+        fn semaphore_up(&self, _caller: Caller, sem_id: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let Some(sem) = current.semaphores.get(sem_id).cloned() else {
+                return -Errno::EINVAL;
+            };
+            let res = Resource::Semaphore(sem_id);
+            current.deadlock.release(thread.local_tid, res);
+            if let Some(tid) = sem.up() {
+                hand_over(current, tid, res);
+            }
+            0
+        }
+
+        fn semaphore_down(&self, _caller: Caller, sem_id: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let Some(sem) = current.semaphores.get(sem_id).cloned() else {
+                return -Errno::EINVAL;
+            };
+            let res = Resource::Semaphore(sem_id);
+            if !current.deadlock.request(thread.local_tid, res) {
+                return DEADLOCK;
+            }
+            let ret = sem.down(thread.tid);
+            if ret == 0 {
+                current.deadlock.grant(thread.local_tid, res);
+            }
+            ret
+        }
+
+        fn mutex_create(&self, _caller: Caller, blocking: bool) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let mutex_id = current.mutexes.len();
+            current.mutexes.push(Arc::new(Mutex::new(blocking)));
+            current.deadlock.add_resource(Resource::Mutex(mutex_id), 1);
+            mutex_id as _
+        }
+
+        fn mutex_lock(&self, _caller: Caller, mutex_id: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let Some(mutex) = current.mutexes.get(mutex_id).cloned() else {
+                return -Errno::EINVAL;
+            };
+            let res = Resource::Mutex(mutex_id);
+            if !current.deadlock.request(thread.local_tid, res) {
+                return DEADLOCK;
+            }
+            match mutex.lock(thread.tid) {
+                0 => {
+                    current.deadlock.grant(thread.local_tid, res);
+                    0
+                }
+                // 自旋的线程不在等待队列里，重试时再重新申请
+                sync::RETRY => {
+                    current.deadlock.cancel(thread.local_tid, res);
+                    sync::RETRY
+                }
+                ret => ret,
+            }
+        }
+
+        fn mutex_unlock(&self, _caller: Caller, mutex_id: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let Some(mutex) = current.mutexes.get(mutex_id).cloned() else {
+                return -Errno::EINVAL;
+            };
+            let Ok(waking) = mutex.unlock(thread.tid) else {
+                return -Errno::EPERM;
+            };
+            let res = Resource::Mutex(mutex_id);
+            current.deadlock.release(thread.local_tid, res);
+            if let Some(tid) = waking {
+                hand_over(current, tid, res);
+            }
+            0
+        }
+
+        fn condvar_create(&self, _caller: Caller, _arg: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            current.condvars.push(Arc::new(Condvar::new()));
+            (current.condvars.len() - 1) as _
+        }
+
+        fn condvar_signal(&self, _caller: Caller, condvar_id: usize) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            let Some(condvar) = current.condvars.get(condvar_id).cloned() else {
+                return -Errno::EINVAL;
+            };
+            // 被唤醒的线程重新获得互斥锁，锁被占用时在锁上排队
+            if let Some((tid, mutex_id)) = condvar.signal() {
+                let res = Resource::Mutex(mutex_id);
+                current.deadlock.wait(local_tid(tid), res);
+                if current.mutexes[mutex_id].lock_or_wait(tid) {
+                    hand_over(current, tid, res);
+                }
+            }
+            0
+        }
+
+        fn condvar_wait(&self, _caller: Caller, condvar_id: usize, mutex_id: usize) -> isize {
+            let processor: *mut Manager = PROCESSOR.get_mut() as *mut _;
+            let current = unsafe { (*processor).get_current_proc().unwrap() };
+            let thread = unsafe { (*processor).current().unwrap() };
+            let (Some(condvar), Some(mutex)) = (
+                current.condvars.get(condvar_id).cloned(),
+                current.mutexes.get(mutex_id).cloned(),
+            ) else {
+                return -Errno::EINVAL;
+            };
+            // 先释放锁再等待，被唤醒时已经重新拿到锁；没有持有锁时不能等待
+            let Ok(waking) = mutex.unlock(thread.tid) else {
+                return -Errno::EPERM;
+            };
+            let res = Resource::Mutex(mutex_id);
+            current.deadlock.release(thread.local_tid, res);
+            if let Some(waking) = waking {
+                hand_over(current, waking, res);
+            }
+            condvar.wait(thread.tid, mutex_id);
+            sync::BLOCKED
+        }
+
+        fn enable_deadlock_detect(&self, _caller: Caller, is_enable: i32) -> isize {
+            let current = PROCESSOR.get_mut().get_current_proc().unwrap();
+            match is_enable {
+                0 | 1 => {
+                    current.deadlock.set_enabled(is_enable == 1);
+                    0
+                }
+                _ => -Errno::EINVAL,
+            }
+        }
+    }
+}
+
+/// 非 RISC-V64 架构的占位实现
+#[cfg(not(target_arch = "riscv64"))]
+mod stub {
+    use tg_kernel_vm::page_table::{MmuMeta, VmFlags};
+
+    /// Sv39 占位类型
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    pub struct Sv39;
+
+    impl MmuMeta for Sv39 {
+        const P_ADDR_BITS: usize = 56;
+        const PAGE_BITS: usize = 12;
+        const LEVEL_BITS: &'static [usize] = &[9, 9, 9];
+        const PPN_POS: usize = 10;
+
+        #[inline]
+        fn is_leaf(value: usize) -> bool {
+            value & 0b1110 != 0
+        }
+    }
+
+    /// 构建 VmFlags 占位。
+    pub const fn build_flags(_s: &str) -> VmFlags<Sv39> {
+        unsafe { VmFlags::from_raw(0) }
+    }
+
+    /// 解析 VmFlags 占位。
+    pub fn parse_flags(_s: &str) -> Result<VmFlags<Sv39>, ()> {
+        Ok(unsafe { VmFlags::from_raw(0) })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn main() -> i32 {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __libc_start_main() -> i32 {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn rust_eh_personality() {}
+}
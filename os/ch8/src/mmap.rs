@@ -0,0 +1,197 @@
+//! 文件映射和共享映射。
+//!
+//! 私有映射（`MAP_PRIVATE`）在 `mmap` 时分配页并复制文件内容，之后的修改只属于这个进程，`fork` 时随地址空间复制。
+//! 这里没有实现写时复制：本章的页管理器没有共享计数和 `COW` 标记，所以直接复制一份，语义不变，只是多占内存。
+//!
+//! 共享映射（`MAP_SHARED`）的页由 [`SharedPages`] 持有，不属于任何地址空间：映射它的进程都用 `map_extern`
+//! 映射同一组物理页，`fork` 的子进程也是，父子进程能看到彼此的写入。文件的共享映射在 `msync`、`munmap`
+//! 和最后一个映射消失时，把与文件内容不同的页经过块缓存写回文件。
+
+use crate::{Sv39, Sv39Manager};
+use alloc::{alloc::dealloc, sync::Arc, vec, vec::Vec};
+use core::{alloc::Layout, ops::Range};
+use tg_easy_fs::Inode;
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VmFlags, PPN, VPN},
+    AddressSpace,
+};
+
+const PAGE_SIZE: usize = 1 << Sv39::PAGE_BITS;
+
+/// `mmap` 的 `flags`：共享映射、私有映射、固定地址、匿名映射。
+pub const MAP_SHARED: i32 = 0x01;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_FIXED: i32 = 0x10;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+
+/// 一组被共享映射的连续物理页。
+pub struct SharedPages {
+    /// 第一页的物理页号
+    ppn: PPN<Sv39>,
+    /// 页数
+    pages: usize,
+    /// 映射的文件和映射起点在文件中的偏移，匿名映射为 `None`
+    file: Option<(Arc<Inode>, usize)>,
+}
+
+impl SharedPages {
+    /// 分配 `pages` 个清零的页。文件映射读入文件从偏移处开始的内容，文件末尾之后的部分保持为 0。
+    pub fn new(pages: usize, file: Option<(Arc<Inode>, usize)>) -> Arc<Self> {
+        let ptr = Sv39Manager::page_alloc::<u8>(pages);
+        let this = Self {
+            ppn: PPN::new(ptr as usize >> Sv39::PAGE_BITS),
+            pages,
+            file,
+        };
+        if let Some((inode, offset)) = &this.file {
+            inode.read_at(*offset, this.bytes(0..pages));
+        }
+        Arc::new(this)
+    }
+
+    /// 第 `pages` 页的内容，内核堆恒等映射，可以直接访问。
+    #[allow(clippy::mut_from_ref)]
+    fn bytes(&self, pages: Range<usize>) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.ptr().add(pages.start * PAGE_SIZE),
+                pages.len() * PAGE_SIZE,
+            )
+        }
+    }
+
+    fn ptr(&self) -> *mut u8 {
+        (self.ppn.val() << Sv39::PAGE_BITS) as *mut u8
+    }
+
+    /// 把第 `pages` 页中与文件内容不同的部分写回文件，匿名映射什么也不做。
+    ///
+    /// 写回只经过块缓存，由 `fs::sync` 落盘；文件末尾之后的部分不写回，映射不会让文件变长。
+    pub fn sync(&self, pages: Range<usize>) {
+        let Some((inode, offset)) = &self.file else {
+            return;
+        };
+        let mut buf = vec![0u8; PAGE_SIZE];
+        for page in pages {
+            let Some(pos) = offset.checked_add(page * PAGE_SIZE) else {
+                break;
+            };
+            let size = inode.size();
+            if pos >= size {
+                break;
+            }
+            let data = &self.bytes(page..page + 1)[..(size - pos).min(PAGE_SIZE)];
+            let n = inode.read_at(pos, &mut buf[..data.len()]);
+            if buf[..n] != *data {
+                inode.write_at(pos, data);
+            }
+        }
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        self.sync(0..self.pages);
+        unsafe {
+            dealloc(
+                self.ptr(),
+                Layout::from_size_align_unchecked(self.pages * PAGE_SIZE, PAGE_SIZE),
+            )
+        };
+    }
+}
+
+/// 进程中的一段共享映射。
+#[derive(Clone)]
+pub struct SharedMapping {
+    /// 映射的虚拟页
+    range: Range<VPN<Sv39>>,
+    /// 页表项权限
+    flags: VmFlags<Sv39>,
+    /// 映射的页，`range.start` 对应其中第 `first` 页
+    pages: Arc<SharedPages>,
+    first: usize,
+}
+
+impl SharedMapping {
+    /// 在 `space` 的 `range` 处以 `flags` 映射 `pages`。
+    pub fn new(
+        space: &mut AddressSpace<Sv39, Sv39Manager>,
+        range: Range<VPN<Sv39>>,
+        flags: VmFlags<Sv39>,
+        pages: Arc<SharedPages>,
+    ) -> Self {
+        let mapping = Self {
+            range,
+            flags,
+            pages,
+            first: 0,
+        };
+        mapping.map(space);
+        mapping
+    }
+
+    /// 在 `space` 中映射这段共享页，`fork` 的子进程用它映射父进程的共享页。
+    pub fn map(&self, space: &mut AddressSpace<Sv39, Sv39Manager>) {
+        let ppn = PPN::new(self.pages.ppn.val() + self.first);
+        space.map_extern(self.range.clone(), ppn, self.flags);
+    }
+
+    /// 是否与 `range` 重叠。
+    pub fn overlaps(&self, range: &Range<VPN<Sv39>>) -> bool {
+        self.range.start < range.end && range.start < self.range.end
+    }
+
+    /// 把与 `range` 重叠的页写回文件。
+    pub fn sync(&self, range: &Range<VPN<Sv39>>) {
+        let pages = self.pages_in(range);
+        self.pages.sync(pages);
+    }
+
+    /// 截掉 `range`，返回剩下的部分。
+    pub fn cut(self, range: &Range<VPN<Sv39>>) -> Vec<Self> {
+        if !self.overlaps(range) {
+            return vec![self];
+        }
+        let mut rest = Vec::new();
+        if self.range.start < range.start {
+            rest.push(Self {
+                range: self.range.start..range.start,
+                ..self.clone()
+            });
+        }
+        if range.end < self.range.end {
+            rest.push(Self {
+                range: range.end..self.range.end,
+                first: self.first + (range.end.val() - self.range.start.val()),
+                ..self.clone()
+            });
+        }
+        rest
+    }
+
+    /// 把与 `range` 重叠的部分的权限改为 `flags`，返回改过之后的各段。
+    pub fn protect(self, range: &Range<VPN<Sv39>>, flags: VmFlags<Sv39>) -> Vec<Self> {
+        let pages = self.pages_in(range);
+        if pages.is_empty() {
+            return vec![self];
+        }
+        let middle = Self {
+            range: self.range.start.max(range.start)..self.range.end.min(range.end),
+            flags,
+            pages: self.pages.clone(),
+            first: pages.start,
+        };
+        let mut rest = self.cut(range);
+        rest.push(middle);
+        rest
+    }
+
+    /// 与 `range` 重叠的页在 [`SharedPages`] 中的下标。
+    fn pages_in(&self, range: &Range<VPN<Sv39>>) -> Range<usize> {
+        let start = self.range.start.max(range.start);
+        let end = self.range.end.min(range.end).max(start);
+        let base = self.range.start.val() - self.first;
+        start.val() - base..end.val() - base
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{errno::Errno, fs::File};
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+/// 管道缓冲区容量（字节）。
+const RING_BUFFER_SIZE: usize = 512;
+
+/// 管道两端共享的环形缓冲区。
+struct PipeRingBuffer {
+    buf: [u8; RING_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    /// 写端。只持有弱引用，所有写端关闭后升级失败，读端据此判断文件结束。
+    write_end: Weak<Pipe>,
+    /// 读端，同理用于判断写入是否还有意义。
+    read_end: Weak<Pipe>,
+}
+
+impl PipeRingBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; RING_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            write_end: Weak::new(),
+            read_end: Weak::new(),
+        }
+    }
+
+    fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len);
+        for c in &mut out[..n] {
+            *c = self.buf[self.head];
+            self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        }
+        self.len -= n;
+        n
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(RING_BUFFER_SIZE - self.len);
+        let mut tail = (self.head + self.len) % RING_BUFFER_SIZE;
+        for &c in &data[..n] {
+            self.buf[tail] = c;
+            tail = (tail + 1) % RING_BUFFER_SIZE;
+        }
+        self.len += n;
+        n
+    }
+}
+
+/// 管道的一端。
+pub struct Pipe {
+    readable: bool,
+    buffer: Arc<Mutex<PipeRingBuffer>>,
+}
+
+/// 创建一个管道，返回 (读端, 写端)。
+pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+    let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
+    let read_end = Arc::new(Pipe {
+        readable: true,
+        buffer: buffer.clone(),
+    });
+    let write_end = Arc::new(Pipe {
+        readable: false,
+        buffer: buffer.clone(),
+    });
+    let mut inner = buffer.lock();
+    inner.read_end = Arc::downgrade(&read_end);
+    inner.write_end = Arc::downgrade(&write_end);
+    drop(inner);
+    (read_end, write_end)
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        !self.readable
+    }
+
+    fn read(&self, buf: &mut [u8]) -> isize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let mut inner = self.buffer.lock();
+        match inner.read(buf) {
+            // 缓冲区空：写端全部关闭则文件结束，否则让调用者稍后重试
+            0 if inner.write_end.upgrade().is_none() => 0,
+            0 => -Errno::EAGAIN,
+            n => n as _,
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> isize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let mut inner = self.buffer.lock();
+        // 读端全部关闭，写入的数据永远不会被读到
+        if inner.read_end.upgrade().is_none() {
+            return -Errno::EPIPE;
+        }
+        match inner.write(buf) {
+            0 => -Errno::EAGAIN,
+            n => n as _,
+        }
+    }
+}
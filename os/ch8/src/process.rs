@@ -0,0 +1,496 @@
+use crate::{
+    args::InitStack,
+    build_flags,
+    deadlock::DeadlockDetector,
+    elf::{self, LoadError},
+    errno::Errno,
+    fs::{File, Stdin, Stdout},
+    map_portal,
+    mmap::SharedMapping,
+    processor::BIG_STRIDE,
+    signal::SignalState,
+    stack::{RLimit, Stack, MAX_STACKS, STACKS_BOTTOM},
+    sync::{Condvar, Mutex, Semaphore},
+    user_buffer::UserBuffer,
+    Sv39, Sv39Manager, TIME_SLICE,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tg_kernel_context::{foreign::ForeignContext, LocalContext};
+use tg_kernel_vm::{
+    page_table::{MmuMeta, VAddr, VmFlags, VPN},
+    AddressSpace,
+};
+use tg_task_manage::{ProcId, ThreadId};
+use xmas_elf::ElfFile;
+
+/// 分配过的最大 pid。
+static MAX_PID: AtomicUsize = AtomicUsize::new(0);
+
+/// 分配一个新的 pid，并记录分配过的最大值。
+fn alloc_pid() -> ProcId {
+    let pid = ProcId::new();
+    MAX_PID.fetch_max(pid.get_usize(), Ordering::Relaxed);
+    pid
+}
+
+/// 分配过的最大 pid，遍历所有进程时用作上界。
+pub fn max_pid() -> usize {
+    MAX_PID.load(Ordering::Relaxed)
+}
+
+/// 线程，调度的基本单位。
+pub struct Thread {
+    /// 不可变
+    pub tid: ThreadId,
+    /// 可变
+    pub context: ForeignContext,
+    /// 进程内的线程号，`gettid` 返回的就是它，主线程为 0；同时决定用户栈的位置
+    pub local_tid: usize,
+    /// stride 调度：当前步进值
+    pub stride: usize,
+    /// stride 调度：优先级（>= 2）
+    pub priority: usize,
+    /// 用完时间片被时钟中断打断的次数
+    pub ticks: usize,
+    /// 在用户态运行的总时间（`time` 计数）
+    pub run_time: u64,
+}
+
+impl Thread {
+    fn new(context: LocalContext, satp: usize, local_tid: usize) -> Self {
+        Self {
+            tid: ThreadId::new(),
+            context: ForeignContext { context, satp },
+            local_tid,
+            stride: 0,
+            priority: 16,
+            ticks: 0,
+            run_time: 0,
+        }
+    }
+
+    /// 记录线程刚刚运行了 `elapsed` 个 `time` 计数，按实际用掉的时间片比例增加 stride。
+    ///
+    /// 每次至少增加 1，频繁让出处理器的线程也会向前推进；至多增加一个完整的 `BIG_STRIDE / priority`，
+    /// 中断来得晚了也不会多算。
+    pub fn charge(&mut self, elapsed: u64) {
+        self.run_time += elapsed;
+        let pass = (BIG_STRIDE / self.priority).max(1);
+        let used = ((pass as u64).saturating_mul(elapsed) / TIME_SLICE) as usize;
+        self.stride = self.stride.wrapping_add(used.clamp(1, pass));
+    }
+}
+
+/// 进程，同一进程的线程共享其中的资源。
+pub struct Process {
+    /// 不可变
+    pub pid: ProcId,
+    /// 进程组号，`fork` 时继承
+    pub pgid: usize,
+    /// 可变
+    pub address_space: AddressSpace<Sv39, Sv39Manager>,
+    /// 堆底
+    pub heap_bottom: usize,
+    /// 当前程序 break 位置
+    pub program_brk: usize,
+    /// 用户栈的权限，由 ELF 的 `PT_GNU_STACK` 决定，新线程的栈也用它
+    pub stack_flags: VmFlags<Sv39>,
+    /// 文件描述符表，`dup` 和 `fork` 得到的描述符共享同一个文件对象
+    pub fd_table: Vec<Option<Arc<dyn File>>>,
+    /// 信号状态：未决信号、屏蔽字和处理动作
+    pub signal: SignalState,
+    /// 主线程，它退出时整个进程退出
+    pub main_tid: ThreadId,
+    /// 进程的退出码。主线程退出或进程被信号终止后设置，其余线程再被调度到时随之退出
+    pub exit_code: Option<isize>,
+    /// 互斥锁，下标即用户态使用的编号
+    pub mutexes: Vec<Arc<Mutex>>,
+    /// 信号量，下标即用户态使用的编号
+    pub semaphores: Vec<Arc<Semaphore>>,
+    /// 条件变量，下标即用户态使用的编号
+    pub condvars: Vec<Arc<Condvar>>,
+    /// 互斥锁和信号量的死锁检测
+    pub deadlock: DeadlockDetector,
+    /// 进程内线程号到线程的映射。线程被 `waittid` 回收之前一直占用线程号和用户栈
+    threads: Vec<Option<ThreadId>>,
+    /// 用户栈，下标是线程号，与 `threads` 一样长
+    stacks: Vec<Stack>,
+    /// 栈大小限制，`fork` 和 `exec` 时保留
+    pub stack_rlimit: RLimit,
+    /// 共享映射，`fork` 的子进程映射同一组页
+    pub shared_maps: Vec<SharedMapping>,
+    /// 还没有退出的线程数
+    live_threads: usize,
+    /// 已经退出的线程用完时间片的次数之和
+    pub ticks: usize,
+    /// 已经退出的线程在用户态运行的总时间之和（`time` 计数）
+    pub run_time: u64,
+}
+
+impl Process {
+    /// 用 `elf` 替换进程的地址空间，调用 `exec` 的线程 `thread` 成为新的主线程，初始用户栈为 `stack`。
+    ///
+    /// 只有一个线程的进程才能 `exec`，否则返回 [`Errno::EBUSY`]；`elf` 无法加载时返回 [`Errno::ENOEXEC`]。
+    pub fn exec(
+        &mut self,
+        elf: ElfFile,
+        thread: &mut Thread,
+        stack: &InitStack,
+    ) -> Result<(), Errno> {
+        if self.live_threads > 1 {
+            return Err(Errno::EBUSY);
+        }
+        let (proc, main) = Process::from_elf(elf)?;
+        self.address_space = proc.address_space;
+        self.heap_bottom = proc.heap_bottom;
+        self.program_brk = proc.program_brk;
+        self.stack_flags = proc.stack_flags;
+        self.threads = vec![Some(thread.tid)];
+        self.stacks = proc.stacks;
+        self.shared_maps = proc.shared_maps;
+        self.live_threads = 1;
+        self.main_tid = thread.tid;
+        self.mutexes.clear();
+        self.semaphores.clear();
+        self.condvars.clear();
+        self.deadlock = DeadlockDetector::new();
+        self.signal.exec();
+        thread.context = main.context;
+        thread.local_tid = main.local_tid;
+        self.push_args(thread, stack);
+        Ok(())
+    }
+
+    /// 把排布好的参数写到主线程 `thread` 的用户栈上，设置它的栈指针和 `_start` 的参数 `a0`、`a1`、`a2`。
+    pub fn push_args(&self, thread: &mut Thread, stack: &InitStack) {
+        UserBuffer::new(stack.sp, stack.data.len())
+            .write(&self.address_space, &stack.data)
+            .unwrap();
+        let ctx = &mut thread.context.context;
+        *ctx.sp_mut() = stack.sp;
+        *ctx.a_mut(0) = stack.argc;
+        *ctx.a_mut(1) = stack.argv;
+        *ctx.a_mut(2) = stack.envp;
+    }
+
+    /// 复制进程，子进程只包含调用 `fork` 的线程 `thread` 的副本。
+    pub fn fork(&mut self, thread: &Thread) -> (Process, Thread) {
+        // 子进程 pid
+        let pid = alloc_pid();
+        // 复制父进程地址空间
+        let mut address_space: AddressSpace<Sv39, Sv39Manager> = AddressSpace::new();
+        // 共享映射不复制：复制时先从 areas 中拿掉，子进程再映射同一组页
+        let areas = self.address_space.areas.clone();
+        let shared_maps = &self.shared_maps;
+        self.address_space
+            .areas
+            .retain(|area| !shared_maps.iter().any(|mapping| mapping.overlaps(area)));
+        self.address_space.cloneself(&mut address_space);
+        self.address_space.areas = areas;
+        for mapping in &self.shared_maps {
+            mapping.map(&mut address_space);
+        }
+        map_portal(&address_space);
+        // 复制调用线程的上下文，其他线程的栈仍然映射着，留给子进程以后的线程复用
+        let satp = (8 << 60) | address_space.root_ppn().val();
+        let context = thread.context.context.clone();
+        let mut child = Thread::new(context, satp, thread.local_tid);
+        child.priority = thread.priority;
+        let mut threads = vec![None; self.threads.len()];
+        threads[child.local_tid] = Some(child.tid);
+        // 子进程与父进程共享打开的文件
+        let fd_table = self.fd_table.clone();
+        let process = Self {
+            pid,
+            pgid: self.pgid,
+            address_space,
+            heap_bottom: self.heap_bottom,
+            program_brk: self.program_brk,
+            stack_flags: self.stack_flags,
+            fd_table,
+            signal: self.signal.fork(),
+            main_tid: child.tid,
+            exit_code: None,
+            mutexes: Vec::new(),
+            semaphores: Vec::new(),
+            condvars: Vec::new(),
+            deadlock: DeadlockDetector::new(),
+            threads,
+            stacks: self.stacks.clone(),
+            stack_rlimit: self.stack_rlimit,
+            shared_maps: self.shared_maps.clone(),
+            live_threads: 1,
+            ticks: 0,
+            run_time: 0,
+        };
+        (process, child)
+    }
+
+    /// 加载 `elf` 创建进程和它的主线程，ELF 格式不对时返回 [`LoadError`]。
+    pub fn from_elf(elf: ElfFile) -> Result<(Self, Thread), LoadError> {
+        let mut address_space = AddressSpace::new();
+        // 程序的段不能碰到栈槽
+        let image = elf::load(&elf, &mut address_space, STACKS_BOTTOM)?;
+
+        // 堆底从 ELF 加载的最高地址的下一页开始
+        let heap_bottom = VAddr::<Sv39>::new(image.end).ceil().base().val();
+
+        // 在第 0 个栈槽映射主线程的用户栈，栈页属于地址空间，进程被回收时一起释放
+        let stack = Stack::new(&mut address_space, 0, image.stack_flags);
+        // 映射异界传送门
+        map_portal(&address_space);
+
+        let mut context = LocalContext::user(image.entry);
+        let satp = (8 << 60) | address_space.root_ppn().val();
+        *context.sp_mut() = stack.top();
+        let main = Thread::new(context, satp, 0);
+        let pid = alloc_pid();
+        let process = Self {
+            pid,
+            pgid: pid.get_usize(),
+            address_space,
+            heap_bottom,
+            program_brk: heap_bottom,
+            stack_flags: image.stack_flags,
+            fd_table: vec![
+                // stdin
+                Some(Arc::new(Stdin)),
+                // stdout
+                Some(Arc::new(Stdout)),
+                // stderr
+                Some(Arc::new(Stdout)),
+            ],
+            signal: SignalState::new(),
+            main_tid: main.tid,
+            exit_code: None,
+            mutexes: Vec::new(),
+            semaphores: Vec::new(),
+            condvars: Vec::new(),
+            deadlock: DeadlockDetector::new(),
+            threads: vec![Some(main.tid)],
+            stacks: vec![stack],
+            stack_rlimit: RLimit::STACK,
+            shared_maps: Vec::new(),
+            live_threads: 1,
+            ticks: 0,
+            run_time: 0,
+        };
+        Ok((process, main))
+    }
+
+    /// 创建一个从 `entry` 开始执行、以 `arg` 为参数的线程。
+    ///
+    /// 新线程使用最小的空闲线程号，并复用该线程号原有的用户栈；没有空闲的线程号时在下一个栈槽中映射一个新的栈。
+    /// 栈槽用完时返回 `None`。
+    pub fn new_thread(&mut self, entry: usize, arg: usize) -> Option<Thread> {
+        let local_tid = match self.threads.iter().position(Option::is_none) {
+            Some(local_tid) => local_tid,
+            None if self.threads.len() < MAX_STACKS => {
+                let local_tid = self.threads.len();
+                let stack = Stack::new(&mut self.address_space, local_tid, self.stack_flags);
+                self.stacks.push(stack);
+                self.threads.push(None);
+                local_tid
+            }
+            None => return None,
+        };
+        let mut context = LocalContext::user(entry);
+        *context.sp_mut() = self.stacks[local_tid].top();
+        *context.a_mut(0) = arg;
+        let satp = (8 << 60) | self.address_space.root_ppn().val();
+        let thread = Thread::new(context, satp, local_tid);
+        self.threads[local_tid] = Some(thread.tid);
+        self.live_threads += 1;
+        Some(thread)
+    }
+
+    /// 进程内线程号为 `local_tid` 的线程，已经被回收时返回 `None`。
+    pub fn thread(&self, local_tid: usize) -> Option<ThreadId> {
+        self.threads.get(local_tid).copied().flatten()
+    }
+
+    /// 线程 `thread` 退出，它的线程号和用户栈要等 `waittid` 回收后才能复用。
+    ///
+    /// 线程占用处理器的时间计入进程，返回进程的线程是否都已经退出。
+    pub fn exit_thread(&mut self, thread: &Thread) -> bool {
+        self.live_threads -= 1;
+        self.ticks += thread.ticks;
+        self.run_time += thread.run_time;
+        self.live_threads == 0
+    }
+
+    /// 回收已经退出的线程 `local_tid` 的线程号和用户栈。
+    pub fn release_thread(&mut self, local_tid: usize) {
+        self.threads[local_tid] = None;
+        self.deadlock.remove_thread(local_tid);
+    }
+
+    /// 进程开始退出：记下退出码，返回阻塞在本进程同步对象上的线程。
+    ///
+    /// 这些线程需要放回就绪队列，才能在被调度到时随进程退出。进程已经在退出时什么也不做。
+    pub fn exit(&mut self, exit_code: isize) -> Vec<ThreadId> {
+        if self.exit_code.is_some() {
+            return Vec::new();
+        }
+        self.exit_code = Some(exit_code);
+        let mutexes = self.mutexes.iter().flat_map(|mutex| mutex.drain());
+        let semaphores = self.semaphores.iter().flat_map(|sem| sem.drain());
+        let condvars = self.condvars.iter().flat_map(|condvar| condvar.drain());
+        mutexes.chain(semaphores).chain(condvars).collect()
+    }
+
+    /// 把 `file` 放进最小的空闲文件描述符，返回该描述符。
+    pub fn alloc_fd(&mut self, file: Arc<dyn File>) -> usize {
+        if let Some(fd) = self.fd_table.iter().position(Option::is_none) {
+            self.fd_table[fd] = Some(file);
+            fd
+        } else {
+            self.fd_table.push(Some(file));
+            self.fd_table.len() - 1
+        }
+    }
+
+    /// 复制文件描述符 `fd`，返回新的描述符。
+    pub fn dup(&mut self, fd: usize) -> Option<usize> {
+        let file = self.fd_table.get(fd)?.clone()?;
+        Some(self.alloc_fd(file))
+    }
+
+    /// 修改程序 break 位置，返回旧的 break 地址，失败返回 None
+    ///
+    /// 堆最多长到栈槽的下边界 `STACKS_BOTTOM`，也不能长进 `mmap` 的区域。
+    pub fn change_program_brk(&mut self, size: isize) -> Option<usize> {
+        let old_brk = self.program_brk;
+        let new_brk = self.program_brk as isize + size;
+        if new_brk < self.heap_bottom as isize || new_brk as usize > STACKS_BOTTOM {
+            return None;
+        }
+        let new_brk = new_brk as usize;
+
+        let old_brk_ceil = VAddr::<Sv39>::new(old_brk).ceil();
+        let new_brk_ceil = VAddr::<Sv39>::new(new_brk).ceil();
+
+        if size > 0 {
+            // 扩展堆
+            if new_brk_ceil.val() > old_brk_ceil.val() {
+                if self.overlaps(&(old_brk_ceil..new_brk_ceil)) {
+                    return None;
+                }
+                // 需要映射新页面
+                self.address_space
+                    .map(old_brk_ceil..new_brk_ceil, &[], 0, build_flags("U_WRV"));
+            }
+        } else if size < 0 {
+            // 收缩堆
+            if old_brk_ceil.val() > new_brk_ceil.val() {
+                // 需要取消映射页面
+                Sv39Manager::unmap(&mut self.address_space, new_brk_ceil..old_brk_ceil);
+            }
+        }
+
+        self.program_brk = new_brk;
+        Some(old_brk)
+    }
+
+    /// 取消 `range` 的映射，释放其中属于地址空间的页；文件共享映射的页先写回文件。
+    pub fn unmap(&mut self, range: Range<VPN<Sv39>>) {
+        self.msync(&range);
+        Sv39Manager::unmap(&mut self.address_space, range.clone());
+        let mut shared_maps = Vec::new();
+        for mapping in self.shared_maps.drain(..) {
+            shared_maps.extend(mapping.cut(&range));
+        }
+        self.shared_maps = shared_maps;
+    }
+
+    /// 把 `range` 的权限改为 `flags`，共享映射记下新权限，`fork` 的子进程按新权限映射。
+    ///
+    /// `range` 中有没有映射的页，或者碰到栈槽时返回 `false`。
+    pub fn protect(&mut self, range: Range<VPN<Sv39>>, flags: VmFlags<Sv39>) -> bool {
+        if range.end.base().val() > STACKS_BOTTOM || !self.is_mapped(&range) {
+            return false;
+        }
+        Sv39Manager::protect(&mut self.address_space, range.clone(), flags);
+        let mut shared_maps = Vec::new();
+        for mapping in self.shared_maps.drain(..) {
+            shared_maps.extend(mapping.protect(&range, flags));
+        }
+        self.shared_maps = shared_maps;
+        true
+    }
+
+    /// `range` 中的页是否都已经映射。
+    fn is_mapped(&self, range: &Range<VPN<Sv39>>) -> bool {
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let area = self
+                .address_space
+                .areas
+                .iter()
+                .find(|area| area.start <= vpn && vpn < area.end);
+            match area {
+                Some(area) => vpn = area.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// 堆占用的页，从 `heap_bottom` 到当前 break 所在的页。
+    pub fn heap(&self) -> Range<VPN<Sv39>> {
+        VAddr::<Sv39>::new(self.heap_bottom).floor()..VAddr::<Sv39>::new(self.program_brk).ceil()
+    }
+
+    /// 在堆顶和栈槽之间从高往低找 `pages` 页连续的空闲虚拟地址，返回起始页号。
+    pub fn find_free(&self, pages: usize) -> Option<VPN<Sv39>> {
+        let floor = VAddr::<Sv39>::new(self.program_brk).ceil();
+        let mut end = VPN::<Sv39>::new(STACKS_BOTTOM >> Sv39::PAGE_BITS);
+        loop {
+            let start = VPN::new(end.val().checked_sub(pages)?);
+            if start < floor {
+                return None;
+            }
+            // 与已有区域重叠时，从重叠区域中最低的起点往下接着找
+            let lowest = self
+                .address_space
+                .areas
+                .iter()
+                .filter(|area| area.start < end && start < area.end)
+                .map(|area| area.start)
+                .min();
+            match lowest {
+                Some(lowest) => end = lowest,
+                None => return Some(start),
+            }
+        }
+    }
+
+    /// `range` 是否与已有的映射重叠。
+    pub fn overlaps(&self, range: &Range<VPN<Sv39>>) -> bool {
+        self.address_space
+            .areas
+            .iter()
+            .any(|area| area.start < range.end && range.start < area.end)
+    }
+
+    /// 把 `range` 中文件共享映射的页写回文件。
+    pub fn msync(&self, range: &Range<VPN<Sv39>>) {
+        for mapping in &self.shared_maps {
+            mapping.sync(range);
+        }
+    }
+
+    /// 处理用户对 `vpn` 的缺页，返回是否处理了缺页，没有处理说明访问非法。
+    ///
+    /// 目前只处理用户栈的扩展：`vpn` 在某个线程的用户栈下方且没有超过栈大小限制时把这个栈扩展到 `vpn`。
+    pub fn handle_page_fault(&mut self, vpn: VPN<Sv39>) -> bool {
+        match Stack::index_of(vpn).and_then(|i| self.stacks.get_mut(i)) {
+            Some(stack) => stack.grow(&mut self.address_space, vpn, self.stack_rlimit.cur),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! POSIX 风格的信号，沿用第七章的实现。
+//!
+//! 信号状态 [`SignalState`] 属于进程而不是线程：屏蔽字和处理动作由进程内所有线程共享，
+//! 未决信号由下一个被调度到的线程在返回用户态之前通过 [`SignalState::handle`] 处理，
+//! 处理函数在这个线程上运行，`sigreturn` 恢复的也是它被打断的上下文。
+//!
+//! `SIGKILL` 和 `SIGSTOP` 不能被捕获、忽略或屏蔽。
+
+use alloc::vec::Vec;
+use tg_kernel_context::LocalContext;
+use tg_syscall::{SignalNo, MAX_SIG};
+
+/// 默认处理方式。
+pub const SIG_DFL: usize = 0;
+/// 忽略信号。
+pub const SIG_IGN: usize = 1;
+
+/// 用户态注册的信号处理动作，布局与 `user_lib::SignalAction` 一致。
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SignalAction {
+    /// 处理函数入口，或 [`SIG_DFL`]、[`SIG_IGN`]
+    pub handler: usize,
+    /// 处理函数执行期间额外屏蔽的信号
+    pub mask: usize,
+}
+
+/// 在返回用户态之前检查信号的结果。
+pub enum SignalResult {
+    /// 可以继续运行（可能已经跳转到处理函数）
+    Continue,
+    /// 进程被停止，暂时不能运行
+    Stopped,
+    /// 进程被信号终止，附带退出码
+    Killed(isize),
+}
+
+/// 进程的信号状态。
+#[derive(Clone)]
+pub struct SignalState {
+    /// 已收到、尚未处理的信号
+    pending: usize,
+    /// 被屏蔽的信号
+    blocked: usize,
+    /// 每个信号的处理动作
+    actions: [SignalAction; MAX_SIG + 1],
+    /// 正在执行的处理函数打断的上下文及当时的屏蔽字，支持嵌套
+    saved: Vec<(LocalContext, usize)>,
+    /// 是否被 `SIGSTOP` 一类信号停止
+    stopped: bool,
+}
+
+#[inline]
+const fn bit(signum: usize) -> usize {
+    1 << signum
+}
+
+/// 不能被屏蔽的信号。
+const UNBLOCKABLE: usize = bit(SignalNo::SIGKILL as usize) | bit(SignalNo::SIGSTOP as usize);
+
+impl SignalState {
+    /// 所有信号采用默认处理方式、没有屏蔽。
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SignalAction::default(); MAX_SIG + 1],
+            saved: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    /// `fork` 得到的子进程继承处理动作和屏蔽字，但不继承未决信号。
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            ..self.clone()
+        }
+    }
+
+    /// `exec` 之后原来的处理函数不复存在，恢复为默认动作。
+    pub fn exec(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+        self.saved.clear();
+    }
+
+    /// 是否是可以发送的信号编号。
+    pub fn is_valid(signum: usize) -> bool {
+        (1..=MAX_SIG).contains(&signum)
+    }
+
+    /// 收到信号 `signum`。
+    pub fn add(&mut self, signum: usize) {
+        self.pending |= bit(signum);
+    }
+
+    /// 替换 `signum` 的处理动作，返回原来的动作；`SIGKILL` 和 `SIGSTOP` 不能被修改。
+    pub fn set_action(
+        &mut self,
+        signum: usize,
+        action: Option<SignalAction>,
+    ) -> Option<SignalAction> {
+        if !Self::is_valid(signum)
+            || signum == SignalNo::SIGKILL as usize
+            || signum == SignalNo::SIGSTOP as usize
+        {
+            return None;
+        }
+        let old = self.actions[signum];
+        if let Some(action) = action {
+            self.actions[signum] = action;
+        }
+        Some(old)
+    }
+
+    /// 设置屏蔽字，返回原来的屏蔽字；`SIGKILL` 和 `SIGSTOP` 不能被屏蔽。
+    pub fn set_mask(&mut self, mask: usize) -> usize {
+        core::mem::replace(&mut self.blocked, mask & !UNBLOCKABLE)
+    }
+
+    /// 是否有信号等着在返回用户态时处理，阻塞中的系统调用据此提前返回。
+    pub fn has_deliverable(&self) -> bool {
+        let ready = self.pending & (!self.blocked | bit(SignalNo::SIGKILL as usize));
+        (1..=MAX_SIG).any(|signum| {
+            ready & bit(signum) != 0
+                && match self.actions[signum].handler {
+                    SIG_IGN => false,
+                    SIG_DFL => !matches!(default_action(signum), DefaultAction::Ignore),
+                    _ => true,
+                }
+        })
+    }
+
+    /// 从处理函数返回：恢复被打断的上下文和屏蔽字，返回原上下文的 `a0`。
+    pub fn sigreturn(&mut self, ctx: &mut LocalContext) -> Option<usize> {
+        let (saved, blocked) = self.saved.pop()?;
+        *ctx = saved;
+        self.blocked = blocked;
+        Some(ctx.a(0))
+    }
+
+    /// 返回用户态之前调用：按编号从小到大处理未被屏蔽的信号。
+    ///
+    /// 需要执行用户处理函数时保存 `ctx`，把 `pc` 指向处理函数、`a0` 设为信号编号。
+    pub fn handle(&mut self, ctx: &mut LocalContext) -> SignalResult {
+        const KILL: usize = SignalNo::SIGKILL as usize;
+        const CONT: usize = SignalNo::SIGCONT as usize;
+        if self.pending & bit(KILL) != 0 {
+            return SignalResult::Killed(-(KILL as isize));
+        }
+        if self.stopped {
+            if self.pending & bit(CONT) == 0 {
+                return SignalResult::Stopped;
+            }
+            // SIGCONT 总会让进程继续，是否执行处理函数交给下面的常规流程
+            self.stopped = false;
+        }
+        while let Some(signum) = self.fetch() {
+            let action = self.actions[signum];
+            match action.handler {
+                SIG_IGN => {}
+                SIG_DFL => match default_action(signum) {
+                    DefaultAction::Ignore => {}
+                    DefaultAction::Stop => {
+                        self.stopped = true;
+                        return SignalResult::Stopped;
+                    }
+                    DefaultAction::Terminate => return SignalResult::Killed(-(signum as isize)),
+                },
+                handler => {
+                    self.saved.push((ctx.clone(), self.blocked));
+                    self.blocked |= (action.mask | bit(signum)) & !UNBLOCKABLE;
+                    *ctx.pc_mut() = handler;
+                    *ctx.a_mut(0) = signum;
+                    break;
+                }
+            }
+        }
+        SignalResult::Continue
+    }
+
+    /// 取出编号最小的未被屏蔽的未决信号。
+    fn fetch(&mut self) -> Option<usize> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            return None;
+        }
+        let signum = ready.trailing_zeros() as usize;
+        self.pending &= !bit(signum);
+        Some(signum)
+    }
+}
+
+/// 信号的默认动作。
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+}
+
+fn default_action(signum: usize) -> DefaultAction {
+    const SIGTTIN: usize = 21;
+    const SIGTTOU: usize = 22;
+    const SIGURG: usize = 23;
+    const SIGWINCH: usize = 28;
+    match signum {
+        s if s == SignalNo::SIGCHLD as usize
+            || s == SignalNo::SIGCONT as usize
+            || s == SIGURG
+            || s == SIGWINCH =>
+        {
+            DefaultAction::Ignore
+        }
+        s if s == SignalNo::SIGSTOP as usize
+            || s == SignalNo::SIGTSTP as usize
+            || s == SIGTTIN
+            || s == SIGTTOU =>
+        {
+            DefaultAction::Stop
+        }
+        _ => DefaultAction::Terminate,
+    }
+}
@@ -0,0 +1,169 @@
+//! 线程同步对象：互斥锁、信号量和条件变量。
+//!
+//! 同步对象属于进程，用户态通过它们在进程中的编号访问。拿不到资源的线程进入对象的等待队列，
+//! 由调度循环移出就绪队列；释放资源的一方从等待队列中取出线程，把资源直接交给它后再放回就绪队列，
+//! 所以被唤醒的线程不需要重新竞争。
+
+use alloc::collections::VecDeque;
+use tg_task_manage::ThreadId;
+
+/// 当前线程已加入等待队列，需要移出就绪队列；被唤醒时已经获得资源，系统调用返回 0。
+pub const BLOCKED: isize = isize::MIN;
+/// 自旋锁已被占用：让出处理器，下次被调度时重新执行这个系统调用。
+pub const RETRY: isize = isize::MIN + 1;
+
+/// 互斥锁。
+///
+/// 阻塞锁被占用时，加锁的线程进入等待队列；自旋锁被占用时，加锁的线程让出处理器后重试。
+/// 两种锁都会把锁直接交给因条件变量而等待它的线程。
+pub struct Mutex {
+    blocking: bool,
+    inner: spin::Mutex<MutexInner>,
+}
+
+struct MutexInner {
+    /// 持有锁的线程
+    owner: Option<ThreadId>,
+    wait_queue: VecDeque<ThreadId>,
+}
+
+impl Mutex {
+    /// 创建一个未加锁的互斥锁，`blocking` 选择阻塞锁或自旋锁。
+    pub fn new(blocking: bool) -> Self {
+        Self {
+            blocking,
+            inner: spin::Mutex::new(MutexInner {
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// 线程 `tid` 加锁，返回 0、[`BLOCKED`] 或 [`RETRY`]。
+    pub fn lock(&self, tid: ThreadId) -> isize {
+        let mut inner = self.inner.lock();
+        if inner.owner.is_none() {
+            inner.owner = Some(tid);
+            0
+        } else if self.blocking {
+            inner.wait_queue.push_back(tid);
+            BLOCKED
+        } else {
+            RETRY
+        }
+    }
+
+    /// 替线程 `tid` 加锁，锁被占用时不论哪种锁都让 `tid` 排队等待，返回是否已经拿到锁。
+    pub fn lock_or_wait(&self, tid: ThreadId) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.owner.is_none() {
+            inner.owner = Some(tid);
+            true
+        } else {
+            inner.wait_queue.push_back(tid);
+            false
+        }
+    }
+
+    /// 线程 `tid` 解锁。有线程在等待时锁直接交给队首的线程，返回需要唤醒的线程。
+    ///
+    /// 锁不被 `tid` 持有时返回 `Err`，锁的状态不变。
+    pub fn unlock(&self, tid: ThreadId) -> Result<Option<ThreadId>, ()> {
+        let mut inner = self.inner.lock();
+        if inner.owner != Some(tid) {
+            return Err(());
+        }
+        let waking = inner.wait_queue.pop_front();
+        inner.owner = waking;
+        Ok(waking)
+    }
+
+    /// 取出所有等待的线程。
+    pub fn drain(&self) -> VecDeque<ThreadId> {
+        core::mem::take(&mut self.inner.lock().wait_queue)
+    }
+}
+
+/// 信号量。
+pub struct Semaphore {
+    inner: spin::Mutex<SemaphoreInner>,
+}
+
+struct SemaphoreInner {
+    /// 可用资源数，为负时其绝对值是等待的线程数
+    count: isize,
+    wait_queue: VecDeque<ThreadId>,
+}
+
+impl Semaphore {
+    /// 创建一个有 `res_count` 个资源的信号量。
+    pub fn new(res_count: usize) -> Self {
+        Self {
+            inner: spin::Mutex::new(SemaphoreInner {
+                count: res_count as _,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// V 操作，返回拿到资源、需要唤醒的线程。
+    pub fn up(&self) -> Option<ThreadId> {
+        let mut inner = self.inner.lock();
+        inner.count += 1;
+        if inner.count <= 0 {
+            inner.wait_queue.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// 线程 `tid` 做 P 操作，返回 0 或 [`BLOCKED`]。
+    pub fn down(&self, tid: ThreadId) -> isize {
+        let mut inner = self.inner.lock();
+        inner.count -= 1;
+        if inner.count < 0 {
+            inner.wait_queue.push_back(tid);
+            BLOCKED
+        } else {
+            0
+        }
+    }
+
+    /// 取出所有等待的线程。
+    pub fn drain(&self) -> VecDeque<ThreadId> {
+        core::mem::take(&mut self.inner.lock().wait_queue)
+    }
+}
+
+/// 条件变量。
+pub struct Condvar {
+    /// 等待的线程，以及被唤醒后需要重新获得的互斥锁的编号
+    wait_queue: spin::Mutex<VecDeque<(ThreadId, usize)>>,
+}
+
+impl Condvar {
+    /// 创建一个没有线程等待的条件变量。
+    pub fn new() -> Self {
+        Self {
+            wait_queue: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 线程 `tid` 开始等待。调用者需要先释放编号为 `mutex_id` 的互斥锁，并让线程阻塞。
+    pub fn wait(&self, tid: ThreadId, mutex_id: usize) {
+        self.wait_queue.lock().push_back((tid, mutex_id));
+    }
+
+    /// 取出一个等待的线程和它要重新获得的互斥锁的编号。
+    ///
+    /// 调用者用 [`Mutex::lock_or_wait`] 替它加锁：锁空闲时直接交给它，否则让它在锁上排队，等锁被释放时再唤醒。
+    pub fn signal(&self) -> Option<(ThreadId, usize)> {
+        self.wait_queue.lock().pop_front()
+    }
+
+    /// 取出所有等待的线程。
+    pub fn drain(&self) -> VecDeque<ThreadId> {
+        let waiting = core::mem::take(&mut *self.wait_queue.lock());
+        waiting.into_iter().map(|(tid, _)| tid).collect()
+    }
+}
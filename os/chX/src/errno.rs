@@ -0,0 +1,36 @@
+//! chX 的系统调用错误码。
+//!
+//! 编号与 Linux 一致，系统调用失败时返回错误码的相反数，写作 `-Errno::EINVAL`。
+//! 本章的系统调用与 ch4 相同，只是内核堆换成了 buddy allocator，错误码的集合也和 ch4 一样；
+//! 堆分配失败的情况由分配器处理，不会变成系统调用的错误码。
+
+use core::ops::Neg;
+
+/// 系统调用错误码，变体名沿用 Linux 的宏名。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 进程不存在
+    ESRCH = 3,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 地址无效
+    EFAULT = 14,
+    /// 已经存在
+    EEXIST = 17,
+    /// 参数无效
+    EINVAL = 22,
+}
+
+impl Neg for Errno {
+    type Output = isize;
+
+    /// 作为系统调用返回值的负数错误码。
+    #[inline]
+    fn neg(self) -> isize {
+        -(self as isize)
+    }
+}
@@ -8,6 +8,7 @@
 
 #[allow(dead_code, unused_variables, unused_imports)]
 mod allocator;
+mod errno;
 mod process;
 
 #[macro_use]
@@ -99,17 +100,6 @@ extern "C" fn rust_main() -> ! {
     // 建立内核地址空间
     let mut ks = kernel_space(layout, MEMORY, portal_ptr as _);
     let portal_idx = PROTAL_TRANSIT.index_in(Sv39::MAX_LEVEL);
-    // 加载应用程序
-    for (i, elf) in tg_linker::AppMeta::locate().iter().enumerate() {
-        let base = elf.as_ptr() as usize;
-        log::info!("detect app[{i}]: {base:#x}..{:#x}", base + elf.len());
-        if let Some(process) = Process::new(ElfFile::new(elf).unwrap()) {
-            // 映射异界传送门
-            process.address_space.root()[portal_idx] = ks.root()[portal_idx];
-            unsafe { PROCESSES.get_mut().push(process) };
-        }
-    }
-
     // 建立调度栈
     const PAGE: Layout =
         unsafe { Layout::from_size_align_unchecked(2 << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS) };
@@ -120,15 +110,29 @@ extern "C" fn rust_main() -> ! {
         PPN::new(stack as usize >> Sv39::PAGE_BITS),
         build_flags("_WRV"),
     );
+    // 内核地址空间占用的物理页，所有应用程序退出后应该只剩这些
+    let kernel_frames = Sv39Manager::frames();
+    // 加载应用程序
+    for (i, elf) in tg_linker::AppMeta::locate().iter().enumerate() {
+        let base = elf.as_ptr() as usize;
+        log::info!("detect app[{i}]: {base:#x}..{:#x}", base + elf.len());
+        if let Some(process) = Process::new(ElfFile::new(elf).unwrap()) {
+            // 映射异界传送门
+            process.address_space.root()[portal_idx] = Sv39Manager::share(ks.root()[portal_idx]);
+            unsafe { PROCESSES.get_mut().push(process) };
+        }
+    }
+
     // 建立调度线程，目的是划分异常域。调度线程上发生内核异常时会回到这个控制流处理
     let mut scheduling = LocalContext::thread(schedule as *const () as _, false);
     *scheduling.sp_mut() = 1 << 38;
+    *scheduling.a_mut(0) = kernel_frames;
     unsafe { scheduling.execute() };
     log::error!("stval = {:#x}", stval::read());
     panic!("trap from scheduling thread: {:?}", scause::read().cause());
 }
 
-extern "C" fn schedule() -> ! {
+extern "C" fn schedule(kernel_frames: usize) -> ! {
     // 初始化异界传送门
     let portal = unsafe { MultislotPortal::init_transit(PROTAL_TRANSIT.base().val(), 1) };
     // 初始化 syscall
@@ -189,9 +193,20 @@ extern "C" fn schedule() -> ! {
             }
         }
     }
+    check_frames(kernel_frames);
     tg_sbi::shutdown(false)
 }
 
+/// 所有应用程序退出后检查物理页是否全部回收。
+fn check_frames(kernel_frames: usize) {
+    let frames = Sv39Manager::frames();
+    if frames == kernel_frames {
+        log::info!("all user frames reclaimed, {frames} frames in use");
+    } else {
+        log::error!("{frames} frames in use, expected {kernel_frames}");
+    }
+}
+
 /// Rust 异常处理函数，以异常方式关机。
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -245,16 +260,36 @@ fn kernel_space(
 
 /// 各种接口库的实现。
 mod impls {
-    use crate::{build_flags, Sv39, PROCESSES};
-    use alloc::alloc::alloc_zeroed;
-    use core::{alloc::Layout, ptr::NonNull};
+    use crate::{build_flags, errno::Errno, Sv39, PROCESSES};
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc},
+        collections::BTreeMap,
+        vec::Vec,
+    };
+    use core::{
+        alloc::Layout,
+        cell::UnsafeCell,
+        ops::Range,
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
     use tg_console::log;
     use tg_kernel_vm::{
-        page_table::{MmuMeta, Pte, VAddr, VmFlags, PPN, VPN},
-        PageManager,
+        page_table::{MmuMeta, Pte, VAddr, VmFlags, VmMeta, PPN, VPN},
+        AddressSpace, PageManager,
     };
     use tg_syscall::*;
 
+    /// 所有页管理器持有的物理页数，包括页表和带 `OWNED` 标记的数据页。
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    /// 一次分配了多个页的块：起始物理页号 → (块的页数, 其中还没有释放的页数)。只有一页的不登记。
+    struct FrameBlocks(UnsafeCell<BTreeMap<usize, (usize, usize)>>);
+
+    unsafe impl Sync for FrameBlocks {}
+
+    static BLOCKS: FrameBlocks = FrameBlocks(UnsafeCell::new(BTreeMap::new()));
+
     #[repr(transparent)]
     pub struct Sv39Manager(NonNull<Pte<Sv39>>);
 
@@ -263,13 +298,87 @@ mod impls {
 
         #[inline]
         fn page_alloc<T>(count: usize) -> *mut T {
+            FRAMES.fetch_add(count, Ordering::Relaxed);
+            let ptr = unsafe { alloc_zeroed(Self::block_layout(count)) };
+            if count > 1 {
+                let ppn = ptr as usize >> Sv39::PAGE_BITS;
+                unsafe { &mut *BLOCKS.0.get() }.insert(ppn, (count, count));
+            }
+            ptr.cast()
+        }
+
+        /// 释放从 `ptr` 开始的 `count` 个页。
+        ///
+        /// 这些页可能只是 [`page_alloc`](Self::page_alloc) 一次分配的块中的一部分，
+        /// 块中的页全部释放后才按分配时的大小把整块还给堆。
+        fn page_free<T>(ptr: *mut T, count: usize) {
+            FRAMES.fetch_sub(count, Ordering::Relaxed);
+            let ppn = ptr as usize >> Sv39::PAGE_BITS;
+            let blocks = unsafe { &mut *BLOCKS.0.get() };
+            let (ptr, count) = match blocks.range_mut(..=ppn).next_back() {
+                Some((&start, (pages, live))) if ppn < start + *pages => {
+                    *live -= count;
+                    if *live > 0 {
+                        return;
+                    }
+                    let pages = *pages;
+                    blocks.remove(&start);
+                    ((start << Sv39::PAGE_BITS) as *mut u8, pages)
+                }
+                _ => (ptr.cast(), count),
+            };
+            unsafe { dealloc(ptr, Self::block_layout(count)) }
+        }
+
+        #[inline]
+        fn block_layout(count: usize) -> Layout {
             unsafe {
-                alloc_zeroed(Layout::from_size_align_unchecked(
-                    count << Sv39::PAGE_BITS,
-                    1 << Sv39::PAGE_BITS,
-                ))
+                Layout::from_size_align_unchecked(count << Sv39::PAGE_BITS, 1 << Sv39::PAGE_BITS)
             }
-            .cast()
+        }
+
+        /// 当前所有页管理器持有的物理页数。
+        pub fn frames() -> usize {
+            FRAMES.load(Ordering::Relaxed)
+        }
+
+        /// 去掉页表项的 `OWNED` 标记，用于把其他地址空间的页表项复制过来：回收页表时不会释放它指向的页。
+        pub fn share(pte: Pte<Sv39>) -> Pte<Sv39> {
+            let flags = unsafe { VmFlags::from_raw(pte.flags().val() & !Self::OWNED.val()) };
+            flags.build_pte(pte.ppn())
+        }
+
+        /// 取消 `range` 的映射，并释放其中带 `OWNED` 标记的页。
+        pub fn unmap(space: &mut AddressSpace<Sv39, Self>, range: Range<VPN<Sv39>>) {
+            let mut frames = Vec::new();
+            let mut vpn = range.start;
+            while vpn < range.end {
+                if let Some(ptr) = space.translate::<u8>(vpn.base(), Self::OWNED) {
+                    frames.push(ptr);
+                }
+                vpn += 1;
+            }
+            space.unmap(range);
+            for ptr in frames {
+                Self::page_free(ptr.as_ptr(), 1);
+            }
+        }
+
+        /// 回收页表 `table` 指向的子树：`level` 层的页表项中，带 `OWNED` 标记的中间页表递归回收，
+        /// 带 `OWNED` 标记的叶子页直接释放，最后释放页表本身。
+        unsafe fn drop_table(&mut self, table: NonNull<Pte<Sv39>>, level: usize) {
+            for i in 0..1 << Sv39::LEVEL_BITS[level] {
+                let pte = *table.as_ptr().add(i);
+                if !pte.is_valid() || !self.check_owned(pte) {
+                    continue;
+                }
+                if level > 0 && !Sv39::is_leaf(pte.flags().val()) {
+                    self.drop_table(self.p_to_v(pte.ppn()), level - 1);
+                } else {
+                    self.deallocate(pte, 1);
+                }
+            }
+            Self::page_free(table.as_ptr(), 1);
         }
     }
 
@@ -310,12 +419,26 @@ mod impls {
             NonNull::new(Self::page_alloc(len)).unwrap()
         }
 
-        fn deallocate(&mut self, _pte: Pte<Sv39>, _len: usize) -> usize {
-            todo!()
+        fn deallocate(&mut self, pte: Pte<Sv39>, len: usize) -> usize {
+            if !self.check_owned(pte) {
+                return 0;
+            }
+            Self::page_free(self.p_to_v::<u8>(pte.ppn()).as_ptr(), len);
+            len
         }
 
         fn drop_root(&mut self) {
-            todo!()
+            if self.0 == NonNull::dangling() {
+                return;
+            }
+            unsafe { self.drop_table(self.0, Sv39::MAX_LEVEL) };
+            self.0 = NonNull::dangling();
+        }
+    }
+
+    impl Drop for Sv39Manager {
+        fn drop(&mut self) {
+            self.drop_root();
         }
     }
 
@@ -350,12 +473,12 @@ mod impls {
                         count as _
                     } else {
                         log::error!("ptr not readable");
-                        -1
+                        -Errno::EFAULT
                     }
                 }
                 _ => {
                     log::error!("unsupported fd: {fd}");
-                    -1
+                    -Errno::EBADF
                 }
             }
         }
@@ -372,10 +495,10 @@ mod impls {
                 if let Some(old_brk) = process.change_program_brk(size as isize) {
                     old_brk as isize
                 } else {
-                    -1
+                    -Errno::ENOMEM
                 }
             } else {
-                -1
+                -Errno::ESRCH
             }
         }
     }
@@ -407,10 +530,10 @@ mod impls {
                         0
                     } else {
                         log::error!("ptr not readable");
-                        -1
+                        -Errno::EFAULT
                     }
                 }
-                _ => -1,
+                _ => -Errno::EINVAL,
             }
         }
     }
@@ -428,7 +551,7 @@ mod impls {
                     if let Some(ptr) = process.address_space.translate::<u8>(VAddr::new(id), READABLE) {
                         (unsafe { *ptr.as_ptr() }) as isize
                     } else {
-                        -1
+                        -Errno::EFAULT
                     }
                 }
                 // request=1: 向用户地址 id 处写入 data 的低 8 位
@@ -437,12 +560,12 @@ mod impls {
                         unsafe { *ptr.as_mut() = data as u8 };
                         0
                     } else {
-                        -1
+                        -Errno::EFAULT
                     }
                 }
                 // request=2: 返回目标 syscall 的调用次数（由调度器通过 caller.flow 传入）
                 2 => caller.flow as isize,
-                _ => -1,
+                _ => -Errno::EINVAL,
             }
         }
     }
@@ -462,15 +585,15 @@ mod impls {
 
             // addr 必须页对齐
             if addr % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
             // prot 低 3 位不能全为 0
             if prot & 0x7 == 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
             // prot 高位必须为 0
             if prot & !0x7 != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
 
             // len 向上取整到页
@@ -489,7 +612,7 @@ mod impls {
             // 检查是否与已有映射重叠
             for area in &process.address_space.areas {
                 if start_vpn < area.end && end_vpn > area.start {
-                    return -1;
+                    return -Errno::EEXIST;
                 }
             }
 
@@ -517,7 +640,7 @@ mod impls {
 
             // addr 必须页对齐
             if addr % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
 
             let len_aligned = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
@@ -539,12 +662,12 @@ mod impls {
                     vpn >= area.start && vpn < area.end
                 });
                 if !covered {
-                    return -1;
+                    return -Errno::EINVAL;
                 }
                 vpn = vpn + 1;
             }
 
-            process.address_space.unmap(start_vpn..end_vpn);
+            Sv39Manager::unmap(&mut process.address_space, start_vpn..end_vpn);
             0
         }
     }
@@ -130,7 +130,7 @@ impl Process {
             // 收缩堆
             if old_brk_ceil.val() > new_brk_ceil.val() {
                 // 需要取消映射页面
-                self.address_space.unmap(new_brk_ceil..old_brk_ceil);
+                Sv39Manager::unmap(&mut self.address_space, new_brk_ceil..old_brk_ceil);
             }
         }
 
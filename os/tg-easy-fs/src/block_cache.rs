@@ -0,0 +1,365 @@
+use crate::{BlockDevice, BLOCK_SZ};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Lazy, Mutex};
+
+/// Number of blocks kept in memory at the same time.
+pub const BLOCK_CACHE_SIZE: usize = 16;
+
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static MISSES: AtomicUsize = AtomicUsize::new(0);
+static EVICTIONS: AtomicUsize = AtomicUsize::new(0);
+static WRITEBACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Block cache counters since boot, see [`block_cache_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    /// Lookups served from memory.
+    pub hits: usize,
+    /// Lookups that had to read the device.
+    pub misses: usize,
+    /// Blocks dropped to make room for a miss.
+    pub evictions: usize,
+    /// Dirty blocks written back to the device.
+    pub writebacks: usize,
+}
+
+/// Block-sized buffer aligned so on-disk structures can be read in place.
+#[repr(C, align(8))]
+struct BlockData([u8; BLOCK_SZ]);
+
+/// In-memory copy of one disk block.
+pub struct BlockCache {
+    cache: BlockData,
+    block_id: usize,
+    block_device: Arc<dyn BlockDevice>,
+    modified: bool,
+}
+
+impl BlockCache {
+    /// Load block `block_id` from `block_device`.
+    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+        let mut cache = BlockData([0u8; BLOCK_SZ]);
+        block_device.read_block(block_id, &mut cache.0);
+        Self {
+            cache,
+            block_id,
+            block_device,
+            modified: false,
+        }
+    }
+
+    fn addr_of_offset(&self, offset: usize) -> usize {
+        &self.cache.0[offset] as *const _ as usize
+    }
+
+    /// Borrow a `T` stored at `offset` inside the block.
+    pub fn get_ref<T: Sized>(&self, offset: usize) -> &T {
+        assert!(offset + core::mem::size_of::<T>() <= BLOCK_SZ);
+        unsafe { &*(self.addr_of_offset(offset) as *const T) }
+    }
+
+    /// Mutably borrow a `T` stored at `offset` and mark the block dirty.
+    pub fn get_mut<T: Sized>(&mut self, offset: usize) -> &mut T {
+        assert!(offset + core::mem::size_of::<T>() <= BLOCK_SZ);
+        self.modified = true;
+        unsafe { &mut *(self.addr_of_offset(offset) as *mut T) }
+    }
+
+    /// Run `f` on a shared view of the `T` at `offset`.
+    pub fn read<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
+        f(self.get_ref(offset))
+    }
+
+    /// Run `f` on a mutable view of the `T` at `offset`.
+    pub fn modify<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        f(self.get_mut(offset))
+    }
+
+    /// Whether the block has been modified since it was last written back.
+    pub fn is_dirty(&self) -> bool {
+        self.modified
+    }
+
+    /// Write the block back if it has been modified.
+    pub fn sync(&mut self) {
+        if self.modified {
+            self.modified = false;
+            WRITEBACKS.fetch_add(1, Ordering::Relaxed);
+            self.block_device.write_block(self.block_id, &self.cache.0);
+        }
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        self.sync()
+    }
+}
+
+/// Identify a device by the address of its shared object.
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+/// (block id, device id)
+type CacheKey = (usize, usize);
+
+/// Cached blocks, least recently used first.
+///
+/// Dirty blocks stay in memory until they are evicted or [`block_cache_sync_all`] runs.
+struct BlockCacheManager {
+    queue: VecDeque<(CacheKey, Arc<Mutex<BlockCache>>)>,
+}
+
+impl BlockCacheManager {
+    const fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn get_block_cache(
+        &mut self,
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Arc<Mutex<BlockCache>> {
+        let key = (block_id, device_id(&block_device));
+        if let Some(idx) = self.queue.iter().position(|(k, _)| *k == key) {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            // Move the block to the most recently used end.
+            let entry = self.queue.remove(idx).unwrap();
+            let cache = Arc::clone(&entry.1);
+            self.queue.push_back(entry);
+            return cache;
+        }
+        MISSES.fetch_add(1, Ordering::Relaxed);
+        if self.queue.len() == BLOCK_CACHE_SIZE {
+            // Evict the least recently used block that nobody else is holding;
+            // dropping it writes it back if it is dirty.
+            let idx = self
+                .queue
+                .iter()
+                .position(|(_, cache)| Arc::strong_count(cache) == 1)
+                .expect("run out of block cache");
+            self.queue.remove(idx);
+            EVICTIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        let cache = Arc::new(Mutex::new(BlockCache::new(block_id, block_device)));
+        self.queue.push_back((key, Arc::clone(&cache)));
+        cache
+    }
+}
+
+static BLOCK_CACHE_MANAGER: Lazy<Mutex<BlockCacheManager>> =
+    Lazy::new(|| Mutex::new(BlockCacheManager::new()));
+
+/// Get the cached copy of block `block_id`, loading it on a miss.
+pub fn get_block_cache(
+    block_id: usize,
+    block_device: Arc<dyn BlockDevice>,
+) -> Arc<Mutex<BlockCache>> {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .get_block_cache(block_id, block_device)
+}
+
+/// Write every dirty cached block back to its device.
+///
+/// Writes are only guaranteed to reach the device after this returns, so call it
+/// before shutting down or handing the image to someone else.
+pub fn block_cache_sync_all() {
+    for (_, cache) in BLOCK_CACHE_MANAGER.lock().queue.iter() {
+        cache.lock().sync();
+    }
+}
+
+/// Snapshot of the block cache counters.
+pub fn block_cache_stats() -> BlockCacheStats {
+    BlockCacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+        writebacks: WRITEBACKS.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::vec::Vec;
+    use std::sync::{Mutex as StdMutex, MutexGuard};
+
+    /// The counters are global, so tests that check them must not run concurrently.
+    static COUNTERS: StdMutex<()> = StdMutex::new(());
+
+    fn lock_counters() -> MutexGuard<'static, ()> {
+        COUNTERS.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// In-memory device that records which blocks were read and written.
+    struct MemDevice {
+        blocks: Mutex<Vec<[u8; BLOCK_SZ]>>,
+        reads: Mutex<Vec<usize>>,
+        writes: Mutex<Vec<usize>>,
+    }
+
+    impl MemDevice {
+        fn new(blocks: usize) -> Arc<Self> {
+            Arc::new(Self {
+                blocks: Mutex::new(alloc::vec![[0; BLOCK_SZ]; blocks]),
+                reads: Mutex::new(Vec::new()),
+                writes: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn byte(&self, block_id: usize) -> u8 {
+            self.blocks.lock()[block_id][0]
+        }
+    }
+
+    impl BlockDevice for MemDevice {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            self.reads.lock().push(block_id);
+            buf.copy_from_slice(&self.blocks.lock()[block_id]);
+        }
+
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            self.writes.lock().push(block_id);
+            self.blocks.lock()[block_id].copy_from_slice(buf);
+        }
+    }
+
+    fn cached_blocks(manager: &BlockCacheManager) -> Vec<usize> {
+        manager
+            .queue
+            .iter()
+            .map(|((block_id, _), _)| *block_id)
+            .collect()
+    }
+
+    fn counters_since(before: BlockCacheStats) -> BlockCacheStats {
+        let now = block_cache_stats();
+        BlockCacheStats {
+            hits: now.hits - before.hits,
+            misses: now.misses - before.misses,
+            evictions: now.evictions - before.evictions,
+            writebacks: now.writebacks - before.writebacks,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let _guard = lock_counters();
+        let device = MemDevice::new(BLOCK_CACHE_SIZE + 2);
+        let dyn_device: Arc<dyn BlockDevice> = device.clone();
+        let mut manager = BlockCacheManager::new();
+        for block_id in 0..BLOCK_CACHE_SIZE {
+            manager.get_block_cache(block_id, dyn_device.clone());
+        }
+        // Touching block 0 makes block 1 the least recently used one.
+        manager.get_block_cache(0, dyn_device.clone());
+        manager.get_block_cache(BLOCK_CACHE_SIZE, dyn_device.clone());
+        let cached = cached_blocks(&manager);
+        assert_eq!(cached.len(), BLOCK_CACHE_SIZE);
+        assert!(!cached.contains(&1));
+        assert_eq!(cached[BLOCK_CACHE_SIZE - 2..], [0, BLOCK_CACHE_SIZE]);
+        // Only misses read the device.
+        let expected = (0..=BLOCK_CACHE_SIZE).collect::<Vec<_>>();
+        assert_eq!(*device.reads.lock(), expected);
+    }
+
+    #[test]
+    fn eviction_skips_blocks_in_use() {
+        let _guard = lock_counters();
+        let device: Arc<dyn BlockDevice> = MemDevice::new(BLOCK_CACHE_SIZE + 1);
+        let mut manager = BlockCacheManager::new();
+        let held = manager.get_block_cache(0, device.clone());
+        for block_id in 1..=BLOCK_CACHE_SIZE {
+            manager.get_block_cache(block_id, device.clone());
+        }
+        let cached = cached_blocks(&manager);
+        assert!(cached.contains(&0));
+        assert!(!cached.contains(&1));
+        drop(held);
+    }
+
+    #[test]
+    fn dirty_blocks_are_written_back_on_eviction() {
+        let _guard = lock_counters();
+        let device = MemDevice::new(BLOCK_CACHE_SIZE + 1);
+        let dyn_device: Arc<dyn BlockDevice> = device.clone();
+        let mut manager = BlockCacheManager::new();
+        manager
+            .get_block_cache(0, dyn_device.clone())
+            .lock()
+            .modify(0, |byte: &mut u8| *byte = 0xaa);
+        for block_id in 1..BLOCK_CACHE_SIZE {
+            manager.get_block_cache(block_id, dyn_device.clone());
+        }
+        // Modifying a block only changes the cached copy.
+        assert_eq!(device.byte(0), 0);
+        assert!(device.writes.lock().is_empty());
+        manager.get_block_cache(BLOCK_CACHE_SIZE, dyn_device.clone());
+        assert_eq!(device.byte(0), 0xaa);
+        assert_eq!(*device.writes.lock(), [0]);
+        // Evicting a clean block does not write it.
+        manager.get_block_cache(0, dyn_device.clone());
+        assert_eq!(*device.writes.lock(), [0]);
+    }
+
+    #[test]
+    fn counters_track_hits_misses_evictions_and_writebacks() {
+        let _guard = lock_counters();
+        let device: Arc<dyn BlockDevice> = MemDevice::new(BLOCK_CACHE_SIZE + 1);
+        let mut manager = BlockCacheManager::new();
+        let before = block_cache_stats();
+        manager
+            .get_block_cache(0, device.clone())
+            .lock()
+            .modify(0, |byte: &mut u8| *byte = 1);
+        for block_id in 1..BLOCK_CACHE_SIZE {
+            manager.get_block_cache(block_id, device.clone());
+        }
+        // Evicts the dirty block 0, then the clean block 2.
+        manager.get_block_cache(1, device.clone());
+        manager.get_block_cache(BLOCK_CACHE_SIZE, device.clone());
+        manager.get_block_cache(1, device.clone());
+        manager.get_block_cache(0, device.clone());
+        assert_eq!(
+            counters_since(before),
+            BlockCacheStats {
+                hits: 2,
+                misses: BLOCK_CACHE_SIZE + 2,
+                evictions: 2,
+                writebacks: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn sync_all_writes_dirty_blocks_once() {
+        let _guard = lock_counters();
+        let device = MemDevice::new(4);
+        let dyn_device: Arc<dyn BlockDevice> = device.clone();
+        for block_id in [1, 3] {
+            get_block_cache(block_id, dyn_device.clone())
+                .lock()
+                .modify(0, |byte: &mut u8| *byte = block_id as u8);
+        }
+        get_block_cache(2, dyn_device.clone());
+        let before = block_cache_stats();
+        block_cache_sync_all();
+        let mut writes = device.writes.lock().clone();
+        writes.sort_unstable();
+        assert_eq!(writes, [1, 3]);
+        assert_eq!((device.byte(1), device.byte(3)), (1, 3));
+        assert!(!get_block_cache(1, dyn_device.clone()).lock().is_dirty());
+        // Blocks stay cached and clean, so a second sync writes nothing.
+        block_cache_sync_all();
+        assert_eq!(device.writes.lock().len(), 2);
+        assert_eq!(counters_since(before).writebacks, 2);
+    }
+}
@@ -0,0 +1,9 @@
+use core::any::Any;
+
+/// A block device that reads and writes whole [`BLOCK_SZ`](crate::BLOCK_SZ) blocks.
+pub trait BlockDevice: Send + Sync + Any {
+    /// Read block `block_id` into `buf`.
+    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    /// Write `buf` to block `block_id`.
+    fn write_block(&self, block_id: usize, buf: &[u8]);
+}
@@ -0,0 +1,43 @@
+//! An easy-fs style file system.
+//!
+//! The on-disk image is split into five consecutive regions:
+//!
+//! ```text
+//! | super block | inode bitmap | inode area | data bitmap | data area |
+//! ```
+//!
+//! - [`SuperBlock`] records the size of every region and a magic number.
+//! - Two [`Bitmap`]s track which inodes and data blocks are in use.
+//! - Each [`DiskInode`] indexes its data through direct, single-indirect
+//!   and double-indirect block pointers and keeps a persistent link count.
+//! - The root directory is a flat list of [`DirEntry`] records.
+//!
+//! All disk accesses go through a small write-back LRU block cache, flushed
+//! with [`block_cache_sync_all`]; the device itself is abstracted by the
+//! [`BlockDevice`] trait so the same code runs in the kernel (RAM disk,
+//! virtio-blk) and on the host (an image file).
+
+#![no_std]
+#![deny(missing_docs)]
+
+extern crate alloc;
+
+mod bitmap;
+mod block_cache;
+mod block_dev;
+mod efs;
+mod layout;
+mod vfs;
+
+/// Size of a disk block in bytes.
+pub const BLOCK_SZ: usize = 512;
+
+pub use bitmap::Bitmap;
+pub use block_cache::{
+    block_cache_stats, block_cache_sync_all, get_block_cache, BlockCache, BlockCacheStats,
+    BLOCK_CACHE_SIZE,
+};
+pub use block_dev::BlockDevice;
+pub use efs::EasyFileSystem;
+pub use layout::{DirEntry, DiskInode, DiskInodeType, SuperBlock, DIRENT_SZ, NAME_LENGTH_LIMIT};
+pub use vfs::Inode;
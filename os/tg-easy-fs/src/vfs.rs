@@ -0,0 +1,263 @@
+use crate::{
+    get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, DIRENT_SZ,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::{Mutex, MutexGuard};
+
+/// An in-memory handle to an inode on disk.
+pub struct Inode {
+    inode_id: u32,
+    block_id: usize,
+    block_offset: usize,
+    fs: Arc<Mutex<EasyFileSystem>>,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl Inode {
+    /// Build a handle for the inode stored at `block_id`/`block_offset`.
+    pub fn new(
+        inode_id: u32,
+        block_id: u32,
+        block_offset: usize,
+        fs: Arc<Mutex<EasyFileSystem>>,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        Self {
+            inode_id,
+            block_id: block_id as usize,
+            block_offset,
+            fs,
+            block_device,
+        }
+    }
+
+    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
+        get_block_cache(self.block_id, Arc::clone(&self.block_device))
+            .lock()
+            .read(self.block_offset, f)
+    }
+
+    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        get_block_cache(self.block_id, Arc::clone(&self.block_device))
+            .lock()
+            .modify(self.block_offset, f)
+    }
+
+    /// Build a handle for another inode of the same file system.
+    fn sibling(&self, fs: &MutexGuard<EasyFileSystem>, inode_id: u32) -> Arc<Inode> {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Arc::new(Self::new(
+            inode_id,
+            block_id,
+            block_offset,
+            Arc::clone(&self.fs),
+            Arc::clone(&self.block_device),
+        ))
+    }
+
+    /// Position and inode id of the entry called `name` in a directory.
+    fn find_entry(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
+        assert!(disk_inode.is_dir());
+        let file_count = disk_inode.size as usize / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        for i in 0..file_count {
+            assert_eq!(
+                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
+                DIRENT_SZ,
+            );
+            if dirent.name() == name {
+                return Some((i, dirent.inode_number()));
+            }
+        }
+        None
+    }
+
+    /// Grow `disk_inode` to `new_size`, allocating blocks from `fs`.
+    fn increase_size(
+        &self,
+        new_size: u32,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        if new_size < disk_inode.size {
+            return;
+        }
+        let blocks_needed = disk_inode.blocks_num_needed(new_size);
+        let v: Vec<u32> = (0..blocks_needed).map(|_| fs.alloc_data()).collect();
+        disk_inode.increase_size(new_size, v, &self.block_device);
+    }
+
+    /// Append a directory entry to `disk_inode`.
+    fn push_entry(
+        &self,
+        dirent: &DirEntry,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = disk_inode.size as usize / DIRENT_SZ;
+        let new_size = (file_count + 1) * DIRENT_SZ;
+        self.increase_size(new_size as u32, disk_inode, fs);
+        disk_inode.write_at(
+            file_count * DIRENT_SZ,
+            dirent.as_bytes(),
+            &self.block_device,
+        );
+    }
+
+    /// Look up `name` in this directory.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            self.find_entry(name, disk_inode)
+                .map(|(_, inode_id)| self.sibling(&fs, inode_id))
+        })
+    }
+
+    /// Create an empty regular file `name` in this directory.
+    ///
+    /// Returns `None` if the name is already taken.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        if self
+            .read_disk_inode(|root_inode| self.find_entry(name, root_inode))
+            .is_some()
+        {
+            return None;
+        }
+        let new_inode_id = fs.alloc_inode();
+        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
+        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
+                new_inode.initialize(DiskInodeType::File);
+            });
+        self.modify_disk_inode(|root_inode| {
+            self.push_entry(&DirEntry::new(name, new_inode_id), root_inode, &mut fs);
+        });
+        Some(self.sibling(&fs, new_inode_id))
+    }
+
+    /// Add a hard link `new_name` to the existing entry `old_name`.
+    ///
+    /// Returns `None` if `old_name` does not exist, `new_name` already exists,
+    /// or the two names are the same.
+    pub fn link(&self, old_name: &str, new_name: &str) -> Option<()> {
+        if old_name == new_name {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        let inode_id = self.modify_disk_inode(|root_inode| {
+            if self.find_entry(new_name, root_inode).is_some() {
+                return None;
+            }
+            let (_, inode_id) = self.find_entry(old_name, root_inode)?;
+            self.push_entry(&DirEntry::new(new_name, inode_id), root_inode, &mut fs);
+            Some(inode_id)
+        })?;
+        self.sibling(&fs, inode_id)
+            .modify_disk_inode(|disk_inode| disk_inode.nlink += 1);
+        Some(())
+    }
+
+    /// Remove the entry `name`; the inode is freed when its last link goes.
+    ///
+    /// Returns `None` if `name` does not exist.
+    pub fn unlink(&self, name: &str) -> Option<()> {
+        let mut fs = self.fs.lock();
+        let inode_id = self.modify_disk_inode(|root_inode| {
+            let (pos, inode_id) = self.find_entry(name, root_inode)?;
+            // Move the last entry into the hole and shrink the directory.
+            let last = root_inode.size as usize / DIRENT_SZ - 1;
+            if pos != last {
+                let mut dirent = DirEntry::empty();
+                root_inode.read_at(last * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+                root_inode.write_at(pos * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+            }
+            root_inode.size -= DIRENT_SZ as u32;
+            Some(inode_id)
+        })?;
+        let inode = self.sibling(&fs, inode_id);
+        let remaining = inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink -= 1;
+            disk_inode.nlink
+        });
+        if remaining == 0 {
+            let blocks =
+                inode.modify_disk_inode(|disk_inode| disk_inode.clear_size(&self.block_device));
+            for block_id in blocks {
+                fs.dealloc_data(block_id);
+            }
+            fs.dealloc_inode(inode_id);
+        }
+        Some(())
+    }
+
+    /// Names of all entries in this directory.
+    pub fn ls(&self) -> Vec<String> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let file_count = disk_inode.size as usize / DIRENT_SZ;
+            let mut v: Vec<String> = Vec::new();
+            for i in 0..file_count {
+                let mut dirent = DirEntry::empty();
+                assert_eq!(
+                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
+                    DIRENT_SZ,
+                );
+                v.push(String::from(dirent.name()));
+            }
+            v
+        })
+    }
+
+    /// Read from `offset` into `buf`, returning the number of bytes read.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+    }
+
+    /// Write `buf` at `offset`, growing the file as needed.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut fs = self.fs.lock();
+        self.modify_disk_inode(|disk_inode| {
+            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            disk_inode.write_at(offset, buf, &self.block_device)
+        })
+    }
+
+    /// Truncate the file to zero length.
+    pub fn clear(&self) {
+        let mut fs = self.fs.lock();
+        self.modify_disk_inode(|disk_inode| {
+            let size = disk_inode.size;
+            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
+            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+            for data_block in data_blocks_dealloc.into_iter() {
+                fs.dealloc_data(data_block);
+            }
+        });
+    }
+
+    /// Inode number on disk.
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
+    /// Number of directory entries referring to this inode.
+    pub fn nlink(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+
+    /// File size in bytes.
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
+
+    /// Whether this inode is a directory.
+    pub fn is_dir(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+}
@@ -0,0 +1,373 @@
+//! Host side helpers for easy-fs images.
+//!
+//! The kernels mount an [`tg_easy_fs`] image instead of linking every user
+//! program into the kernel. This crate builds such images from a list of
+//! files (usually the cases of one chapter in `tg-user/cases.toml`) and can
+//! list, extract and check existing images. It backs both the `tg-mkfs`
+//! command line tool and the chapter build scripts.
+
+#![deny(missing_docs)]
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tg_easy_fs::{
+    block_cache_sync_all, get_block_cache, BlockDevice, DiskInode, EasyFileSystem, SuperBlock,
+    BLOCK_SZ, DIRENT_SZ, NAME_LENGTH_LIMIT,
+};
+
+/// Blocks of inode bitmap, enough for 4096 inodes.
+const INODE_BITMAP_BLOCKS: u32 = 1;
+/// Blocks of inode area matching [`INODE_BITMAP_BLOCKS`].
+const INODE_AREA_BLOCKS: u32 =
+    (INODE_BITMAP_BLOCKS as usize * BLOCK_SZ * 8 * core::mem::size_of::<DiskInode>() / BLOCK_SZ)
+        as u32;
+/// Minimum number of free data blocks left for files created at run time (4 MiB).
+const HEADROOM_BLOCKS: u32 = 8192;
+
+/// An image file accessed block by block.
+pub struct BlockFile(Mutex<File>);
+
+impl BlockFile {
+    /// Create (or truncate) `path` as a zero-filled image of `blocks` blocks.
+    pub fn create(path: &Path, blocks: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(blocks as u64 * BLOCK_SZ as u64)?;
+        Ok(Self(Mutex::new(file)))
+    }
+
+    /// Open an existing image for reading and writing.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self(Mutex::new(file)))
+    }
+}
+
+impl BlockDevice for BlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut file = self.0.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("error when seeking");
+        file.read_exact(buf).expect("not a complete block");
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut file = self.0.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("error when seeking");
+        file.write_all(buf).expect("error when writing");
+    }
+}
+
+/// One entry of the root directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// File name.
+    pub name: String,
+    /// Inode number.
+    pub inode: u32,
+    /// File size in bytes.
+    pub size: usize,
+    /// Hard link count.
+    pub nlink: u32,
+}
+
+#[derive(Deserialize, Default)]
+struct Cases {
+    cases: Option<Vec<String>>,
+}
+
+/// Case names listed under `[chapter]` in a `cases.toml`.
+pub fn chapter_cases(cases_toml: &Path, chapter: &str) -> io::Result<Vec<String>> {
+    let cfg = fs::read_to_string(cases_toml)?;
+    let mut cases: HashMap<String, Cases> =
+        toml::from_str(&cfg).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    match cases.remove(chapter).and_then(|c| c.cases) {
+        Some(names) if !names.is_empty() => Ok(names),
+        _ => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no cases for {chapter} in {}", cases_toml.display()),
+        )),
+    }
+}
+
+/// Number of blocks an image needs to hold files of `sizes` bytes plus
+/// `headroom` free data blocks.
+fn required_blocks(sizes: &[usize], headroom: u32) -> u32 {
+    let mut data = sizes
+        .iter()
+        .map(|size| DiskInode::total_blocks(*size as u32))
+        .sum::<u32>();
+    // root directory
+    data += DiskInode::total_blocks((sizes.len() * DIRENT_SZ) as u32);
+    data += headroom;
+    // one data bitmap block covers 4096 data blocks
+    let data_total = data + data.div_ceil(4096);
+    1 + INODE_BITMAP_BLOCKS + INODE_AREA_BLOCKS + data_total
+}
+
+/// Default image size for files of `sizes` bytes: the files plus a quarter
+/// of their size, and at least [`HEADROOM_BLOCKS`], of free space.
+pub fn image_blocks(sizes: &[usize]) -> u32 {
+    let used = required_blocks(sizes, 0);
+    required_blocks(sizes, HEADROOM_BLOCKS.max(used / 4))
+}
+
+/// Create `image` and copy every `(name, path)` pair into its root directory.
+///
+/// When `blocks` is `None` the image is sized with [`image_blocks`].
+pub fn create(image: &Path, files: &[(String, PathBuf)], blocks: Option<u32>) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mut names = BTreeSet::new();
+    for (name, _) in files {
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT {
+            return Err(invalid(format!(
+                "file name {name:?} must be 1..={NAME_LENGTH_LIMIT} bytes"
+            )));
+        }
+        if !names.insert(name.as_str()) {
+            return Err(invalid(format!("duplicate file name {name:?}")));
+        }
+    }
+    let max_files = (INODE_BITMAP_BLOCKS as usize * BLOCK_SZ * 8) - 1;
+    if files.len() > max_files {
+        return Err(invalid(format!(
+            "at most {max_files} files fit in an image"
+        )));
+    }
+    let data = files
+        .iter()
+        .map(|(_, path)| fs::read(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    let sizes = data.iter().map(Vec::len).collect::<Vec<_>>();
+    let blocks = match blocks {
+        Some(blocks) if blocks < required_blocks(&sizes, 0) => {
+            return Err(invalid(format!(
+                "{blocks} blocks is too small, the files need at least {}",
+                required_blocks(&sizes, 0)
+            )))
+        }
+        Some(blocks) => blocks,
+        None => image_blocks(&sizes),
+    };
+    let device: Arc<dyn BlockDevice> = Arc::new(BlockFile::create(image, blocks)?);
+    let efs = EasyFileSystem::create(device, blocks, INODE_BITMAP_BLOCKS);
+    let root = EasyFileSystem::root_inode(&efs);
+    for ((name, _), data) in files.iter().zip(data) {
+        let inode = root.create(name).unwrap();
+        inode.write_at(0, &data);
+    }
+    block_cache_sync_all();
+    Ok(())
+}
+
+/// Open `image` as a block device, failing if it does not hold an easy-fs
+/// super block.
+fn open_image(image: &Path) -> io::Result<Arc<dyn BlockDevice>> {
+    let device: Arc<dyn BlockDevice> = Arc::new(BlockFile::open(image)?);
+    let valid = get_block_cache(0, Arc::clone(&device))
+        .lock()
+        .read(0, |super_block: &SuperBlock| super_block.is_valid());
+    if valid {
+        Ok(device)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not an easy-fs image", image.display()),
+        ))
+    }
+}
+
+/// Entries of the root directory of `image`, in directory order.
+pub fn list(image: &Path) -> io::Result<Vec<Entry>> {
+    let efs = EasyFileSystem::open(open_image(image)?).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    Ok(root
+        .ls()
+        .into_iter()
+        .filter_map(|name| {
+            let inode = root.find(&name)?;
+            Some(Entry {
+                inode: inode.inode_id(),
+                size: inode.size(),
+                nlink: inode.nlink(),
+                name,
+            })
+        })
+        .collect())
+}
+
+/// Copy the file `name` out of `image` into `output`.
+pub fn extract(image: &Path, name: &str, output: &Path) -> io::Result<()> {
+    let efs = EasyFileSystem::open(open_image(image)?).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let inode = root.find(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{name} not found in {}", image.display()),
+        )
+    })?;
+    let mut data = vec![0u8; inode.size()];
+    let len = inode.read_at(0, &mut data);
+    fs::write(output, &data[..len])
+}
+
+/// Check the consistency of `image` and describe every problem found.
+///
+/// An empty result means the image is clean. The checks cover the region
+/// sizes in the super block, directory entries, link counts, and that the
+/// inode and data bitmaps match exactly what the inodes reference.
+pub fn fsck(image: &Path) -> io::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let device = open_image(image)?;
+    let (total, inode_area, data_area) =
+        get_block_cache(0, Arc::clone(&device))
+            .lock()
+            .read(0, |sb: &SuperBlock| {
+                let regions = 1
+                    + sb.inode_bitmap_blocks
+                    + sb.inode_area_blocks
+                    + sb.data_bitmap_blocks
+                    + sb.data_area_blocks;
+                if regions != sb.total_blocks {
+                    problems.push(format!(
+                        "regions cover {regions} blocks but the image has {}",
+                        sb.total_blocks
+                    ));
+                }
+                (sb.total_blocks, sb.inode_area_blocks, sb.data_area_blocks)
+            });
+    let file_len = fs::metadata(image)?.len();
+    if file_len < total as u64 * BLOCK_SZ as u64 {
+        problems.push(format!(
+            "image file is {file_len} bytes, shorter than {total} blocks"
+        ));
+        return Ok(problems);
+    }
+
+    let efs = EasyFileSystem::open(Arc::clone(&device)).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    if !root.is_dir() {
+        problems.push("inode 0 is not a directory".into());
+        return Ok(problems);
+    }
+    let max_inodes = (inode_area as usize * BLOCK_SZ / core::mem::size_of::<DiskInode>()) as u32;
+
+    // inode id -> number of directory entries referring to it
+    let mut refs = BTreeMap::from([(0u32, 0u32)]);
+    let mut names = BTreeSet::new();
+    for name in root.ls() {
+        if !names.insert(name.clone()) {
+            problems.push(format!("duplicate entry {name:?}"));
+            continue;
+        }
+        let inode = root.find(&name).unwrap();
+        let id = inode.inode_id();
+        if id == 0 || id >= max_inodes {
+            problems.push(format!("entry {name:?} refers to invalid inode {id}"));
+            continue;
+        }
+        if inode.is_dir() {
+            problems.push(format!("entry {name:?} is a directory"));
+        }
+        *refs.entry(id).or_default() += 1;
+    }
+
+    let data_start = efs.lock().get_data_block_id(0);
+    let mut used_blocks = BTreeSet::new();
+    let mut expected_data_bits = 0u32;
+    for (&id, &count) in &refs {
+        let (block_id, offset) = efs.lock().get_disk_inode_pos(id);
+        let (nlink, size, ids) = get_block_cache(block_id as usize, Arc::clone(&device))
+            .lock()
+            .read(offset, |disk_inode: &DiskInode| {
+                let ids = (0..disk_inode.data_blocks())
+                    .map(|i| disk_inode.get_block_id(i, &device))
+                    .collect::<Vec<_>>();
+                (disk_inode.nlink, disk_inode.size, ids)
+            });
+        if id != 0 && nlink != count {
+            problems.push(format!(
+                "inode {id} has nlink {nlink} but {count} directory entries"
+            ));
+        }
+        expected_data_bits += DiskInode::total_blocks(size);
+        for block in ids {
+            if block < data_start || block >= data_start + data_area {
+                problems.push(format!(
+                    "inode {id} uses block {block} outside the data area"
+                ));
+            } else if !used_blocks.insert(block) {
+                problems.push(format!("block {block} is used more than once"));
+            } else if !efs
+                .lock()
+                .data_bitmap
+                .is_allocated(&device, (block - data_start) as usize)
+            {
+                problems.push(format!("inode {id} uses block {block} marked free"));
+            }
+        }
+    }
+
+    let allocated_inodes = (0..max_inodes as usize)
+        .filter(|i| efs.lock().inode_bitmap.is_allocated(&device, *i))
+        .count();
+    if allocated_inodes != refs.len() {
+        problems.push(format!(
+            "{allocated_inodes} inodes are allocated but {} are reachable",
+            refs.len()
+        ));
+    }
+    let allocated_data = (0..data_area as usize)
+        .filter(|i| efs.lock().data_bitmap.is_allocated(&device, *i))
+        .count() as u32;
+    if allocated_data != expected_data_bits {
+        problems.push(format!(
+            "{allocated_data} data blocks are allocated but inodes account for {expected_data_bits}"
+        ));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_list_extract_fsck() {
+        let dir = std::env::temp_dir().join(format!("tg-mkfs-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let small = dir.join("small");
+        let large = dir.join("large");
+        fs::write(&small, b"hello").unwrap();
+        let large_data = (0..300_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(&large, &large_data).unwrap();
+        let image = dir.join("fs.img");
+        let files = [("small".into(), small), ("large".into(), large)];
+        create(&image, &files, None).unwrap();
+
+        let entries = list(&image).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            ["small", "large"]
+        );
+        assert!(entries.iter().all(|e| e.nlink == 1));
+        assert_eq!(entries[1].size, large_data.len());
+
+        let out = dir.join("out");
+        extract(&image, "large", &out).unwrap();
+        assert_eq!(fs::read(&out).unwrap(), large_data);
+        assert_eq!(fsck(&image).unwrap(), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
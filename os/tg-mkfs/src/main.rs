@@ -0,0 +1,130 @@
+//! `tg-mkfs`: create, list, extract and check easy-fs images.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+usage:
+    tg-mkfs create <image> [--blocks <n>] [--cases <cases.toml> --chapter <name> --bin-dir <dir>] [file...]
+    tg-mkfs list <image>
+    tg-mkfs extract <image> <name> [output]
+    tg-mkfs fsck <image>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => code,
+        Err(msg) => {
+            eprintln!("tg-mkfs: {msg}");
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let (cmd, rest) = args.split_first().ok_or("missing command")?;
+    match cmd.as_str() {
+        "create" => create(rest),
+        "list" => {
+            let [image] = rest else {
+                return Err("list takes exactly one image".into());
+            };
+            let entries = tg_mkfs::list(Path::new(image)).map_err(|e| e.to_string())?;
+            println!("{:>6} {:>10} {:>5}  name", "inode", "size", "nlink");
+            for entry in entries {
+                println!(
+                    "{:>6} {:>10} {:>5}  {}",
+                    entry.inode, entry.size, entry.nlink, entry.name
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        "extract" => {
+            let (image, name, output) = match rest {
+                [image, name] => (image, name, PathBuf::from(name)),
+                [image, name, output] => (image, name, PathBuf::from(output)),
+                _ => return Err("extract takes an image, a name and an optional output".into()),
+            };
+            tg_mkfs::extract(Path::new(image), name, &output).map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        "fsck" => {
+            let [image] = rest else {
+                return Err("fsck takes exactly one image".into());
+            };
+            let problems = tg_mkfs::fsck(Path::new(image)).map_err(|e| e.to_string())?;
+            if problems.is_empty() {
+                println!("{image}: clean");
+                Ok(ExitCode::SUCCESS)
+            } else {
+                for problem in &problems {
+                    println!("{image}: {problem}");
+                }
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        "-h" | "--help" | "help" => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(format!("unknown command {cmd:?}")),
+    }
+}
+
+fn create(args: &[String]) -> Result<ExitCode, String> {
+    let (image, mut rest) = args.split_first().ok_or("create needs an image path")?;
+    let mut blocks = None;
+    let mut cases = None;
+    let mut chapter = None;
+    let mut bin_dir = None;
+    let mut files = Vec::new();
+    while let Some((arg, tail)) = rest.split_first() {
+        let mut value = || {
+            let (value, tail) = tail
+                .split_first()
+                .ok_or_else(|| format!("{arg} needs a value"))?;
+            rest = tail;
+            Ok::<_, String>(value.clone())
+        };
+        match arg.as_str() {
+            "--blocks" => {
+                let n = value()?;
+                blocks = Some(
+                    n.parse::<u32>()
+                        .map_err(|_| format!("bad block count {n:?}"))?,
+                );
+            }
+            "--cases" => cases = Some(PathBuf::from(value()?)),
+            "--chapter" => chapter = Some(value()?),
+            "--bin-dir" => bin_dir = Some(PathBuf::from(value()?)),
+            _ => {
+                let path = PathBuf::from(arg);
+                let name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| format!("bad file path {arg:?}"))?
+                    .to_string();
+                files.push((name, path));
+                rest = tail;
+            }
+        }
+    }
+    match (cases, chapter, bin_dir) {
+        (Some(cases), Some(chapter), Some(bin_dir)) => {
+            let names = tg_mkfs::chapter_cases(&cases, &chapter).map_err(|e| e.to_string())?;
+            files.extend(names.into_iter().map(|name| {
+                let path = bin_dir.join(&name);
+                (name, path)
+            }));
+        }
+        (None, None, None) => {}
+        _ => return Err("--cases, --chapter and --bin-dir must be given together".into()),
+    }
+    tg_mkfs::create(Path::new(image), &files, blocks).map_err(|e| e.to_string())?;
+    println!("{image}: {} files", files.len());
+    Ok(ExitCode::SUCCESS)
+}
@@ -5,12 +5,15 @@
 extern crate user_lib;
 extern crate alloc;
 
-use user_lib::{close, open, read, OpenFlags};
+use alloc::format;
+use user_lib::{args, close, open, read, OpenFlags};
 
+/// 输出第一个参数指定的文件，默认为 `filea`。
 #[no_mangle]
 pub extern "C" fn main() -> i32 {
-    let fd = open("filea\0", OpenFlags::RDONLY);
-    if fd == -1 {
+    let name = args().nth(1).unwrap_or("filea");
+    let fd = open(&format!("{name}\0"), OpenFlags::RDONLY);
+    if fd < 0 {
         panic!("Error occured when opening file");
     }
     let fd = fd as usize;
@@ -4,9 +4,9 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::mmap;
+use user_lib::{mmap, to_result, Errno};
 
-// 理想结果：对于错误的 mmap 返回 -1
+// 理想结果：对于错误的 mmap 返回对应的错误码
 
 #[no_mangle]
 extern "C" fn main() -> i32 {
@@ -14,10 +14,25 @@ extern "C" fn main() -> i32 {
     let len: usize = 4096;
     let prot: usize = 3;
     assert_eq!(0, mmap(start, len, prot));
-    assert_eq!(mmap(start - len, len + 1, prot), -1); // 地址未对齐
-    assert_eq!(mmap(start + len + 1, len, prot), -1); // 地址未对齐
-    assert_eq!(mmap(start + len, len, 0), -1); // prot 为 0 无意义
-    assert_eq!(mmap(start + len, len, prot | 8), -1); // prot 其他位非 0
+    // 地址未对齐
+    assert_eq!(
+        to_result(mmap(start - len, len + 1, prot)),
+        Err(Errno::EINVAL)
+    );
+    // 地址未对齐
+    assert_eq!(
+        to_result(mmap(start + len + 1, len, prot)),
+        Err(Errno::EINVAL)
+    );
+    // prot 为 0 无意义
+    assert_eq!(to_result(mmap(start + len, len, 0)), Err(Errno::EINVAL));
+    // prot 其他位非 0
+    assert_eq!(
+        to_result(mmap(start + len, len, prot | 8)),
+        Err(Errno::EINVAL)
+    );
+    // 已经映射
+    assert_eq!(to_result(mmap(start, len, prot)), Err(Errno::EEXIST));
     println!("Test 04_4 test OK!");
     0
 }
@@ -4,7 +4,7 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{mmap, munmap, trace_read, trace_write};
+use user_lib::{mmap, munmap, to_result, trace_read, trace_write, Errno};
 
 #[no_mangle]
 extern "C" fn main() -> i32 {
@@ -18,9 +18,15 @@ extern "C" fn main() -> i32 {
 
     // 测试非法地址的读写
     assert_eq!(None, trace_read(isize::MAX as usize as *const _));
-    assert_eq!(-1, trace_write(isize::MAX as usize as *const _, 0));
+    assert_eq!(
+        Err(Errno::EFAULT),
+        to_result(trace_write(isize::MAX as usize as *const _, 0))
+    );
     assert_eq!(None, trace_read(0x80200000 as *const _)); // 内核地址
-    assert_eq!(-1, trace_write(0x80200000 as *const _, 0));
+    assert_eq!(
+        Err(Errno::EFAULT),
+        to_result(trace_write(0x80200000 as *const _, 0))
+    );
 
     // 测试 mmap 只读页
     let start: usize = 0x10000000;
@@ -30,13 +36,20 @@ extern "C" fn main() -> i32 {
     assert_eq!(0, mmap(start, len, prot));
 
     assert!(trace_read(start as *const u8).is_some()); // 可读
-    assert_eq!(-1, trace_write(start as *const u8, 0)); // 不可写
+                                                       // 不可写
+    assert_eq!(
+        Err(Errno::EFAULT),
+        to_result(trace_write(start as *const u8, 0))
+    );
 
     assert_eq!(0, munmap(start, len));
 
     // unmap 后不可访问
     assert_eq!(None, trace_read(start as *const u8));
-    assert_eq!(-1, trace_write(start as *const u8, 0));
+    assert_eq!(
+        Err(Errno::EFAULT),
+        to_result(trace_write(start as *const u8, 0))
+    );
 
     println!("Test trace_1 OK!");
     0
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{args, env, spawn, waitpid};
+
+const ARGV: &[&str] = &["ch5_args", "hello", "", "world with spaces"];
+const ENVP: &[&str] = &["PATH=/", "USER=rcore"];
+
+/// 带参数启动自己，子进程检查收到的参数和环境变量。
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let argv: Vec<&str> = args().collect();
+    if argv.len() > 1 {
+        assert_eq!(argv, ARGV);
+        assert_eq!(env().collect::<Vec<_>>(), ENVP);
+        return 0;
+    }
+    assert_eq!(argv, ["ch5_args"]);
+    assert_eq!(env().count(), 0);
+    let pid = spawn("ch5_args", ARGV, ENVP);
+    assert!(pid > 0, "spawn failed: {}", pid);
+    let mut exit_code: i32 = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0, "child exited with code {}", exit_code);
+    println!("Test args OK!");
+    0
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, madvise, mmap, to_result, waitpid, Errno, MADV_DONTNEED, PROT_READ, PROT_WRITE,
+};
+
+// 理想结果：MADV_DONTNEED 丢掉已经分配的页，再读得到 0；fork 共享的页只影响调用者自己
+
+const PAGE_SIZE: usize = 4096;
+
+fn fill(addr: usize, len: usize, value: u8) {
+    for i in addr..addr + len {
+        unsafe { *(i as *mut u8) = value };
+    }
+}
+
+fn check(addr: usize, len: usize, value: u8) {
+    for i in addr..addr + len {
+        assert_eq!(unsafe { *(i as *const u8) }, value);
+    }
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len = PAGE_SIZE * 3;
+    assert_eq!(mmap(start, len, PROT_READ | PROT_WRITE), 0);
+    fill(start, len, 0xaa);
+
+    // 丢掉第 1 页，其他页不变，映射还在
+    assert_eq!(madvise(start + PAGE_SIZE, PAGE_SIZE, MADV_DONTNEED), 0);
+    check(start, PAGE_SIZE, 0xaa);
+    check(start + PAGE_SIZE, PAGE_SIZE, 0);
+    check(start + PAGE_SIZE * 2, PAGE_SIZE, 0xaa);
+    fill(start + PAGE_SIZE, PAGE_SIZE, 0xbb);
+    check(start + PAGE_SIZE, PAGE_SIZE, 0xbb);
+
+    // 子进程丢掉和父进程共享的页，父进程的数据不受影响
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(madvise(start, len, MADV_DONTNEED), 0);
+        check(start, len, 0);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    check(start, PAGE_SIZE, 0xaa);
+    check(start + PAGE_SIZE, PAGE_SIZE, 0xbb);
+
+    // 其他建议只是提示
+    assert_eq!(madvise(start, len, 0), 0);
+    // 没有映射的页、未对齐的地址、不认识的建议
+    assert_eq!(
+        to_result(madvise(start + len, PAGE_SIZE, MADV_DONTNEED)),
+        Err(Errno::ENOMEM)
+    );
+    assert_eq!(
+        to_result(madvise(start + 1, PAGE_SIZE, MADV_DONTNEED)),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(to_result(madvise(start, len, 100)), Err(Errno::EINVAL));
+    println!("Test madvise OK!");
+    0
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    mmap_with, munmap, to_result, Errno, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
+
+// 理想结果：地址为 0 时内核挑选一段空闲的地址，多次映射互不重叠
+
+const PAGE_SIZE: usize = 4096;
+const RW: usize = PROT_READ | PROT_WRITE;
+const ANON: usize = MAP_PRIVATE | MAP_ANONYMOUS;
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let len = PAGE_SIZE * 3;
+    let a = mmap_with(0, len, RW, ANON, -1, 0);
+    assert!(a > 0);
+    let a = a as usize;
+    assert_eq!(a % PAGE_SIZE, 0);
+    let b = mmap_with(0, len + 1, RW, ANON, -1, 0);
+    assert!(b > 0);
+    let b = b as usize;
+    assert_eq!(b % PAGE_SIZE, 0);
+    // 两段映射不重叠（第二段向上取整到 4 页）
+    assert!(a + len <= b || b + len + PAGE_SIZE <= a);
+
+    for i in 0..len {
+        unsafe {
+            *((a + i) as *mut u8) = i as u8;
+            *((b + i) as *mut u8) = !(i as u8);
+        }
+    }
+    for i in 0..len {
+        unsafe {
+            assert_eq!(*((a + i) as *const u8), i as u8);
+            assert_eq!(*((b + i) as *const u8), !(i as u8));
+        }
+    }
+
+    // 取消映射后再挑一段，新映射的页内容为 0
+    assert_eq!(munmap(a, len), 0);
+    let c = mmap_with(0, len, RW, ANON, -1, 0);
+    assert!(c > 0);
+    assert_eq!(unsafe { *(c as *const u8) }, 0);
+    assert_eq!(munmap(c as usize, len), 0);
+    assert_eq!(munmap(b, len + PAGE_SIZE), 0);
+
+    // prot 和 flags 不合法
+    assert_eq!(
+        to_result(mmap_with(0, len, 0, ANON, -1, 0)),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        to_result(mmap_with(0, len, RW, ANON | 0x1000_0000, -1, 0)),
+        Err(Errno::EINVAL)
+    );
+    println!("Test mmap0 OK!");
+    0
+}
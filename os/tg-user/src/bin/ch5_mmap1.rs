@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    mmap, mmap_with, munmap, sbrk, to_result, Errno, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE,
+    PROT_READ, PROT_WRITE,
+};
+
+// 理想结果：MAP_FIXED 替换原有的映射，被替换的页内容清零，其他页不受影响；堆不能被替换
+
+const PAGE_SIZE: usize = 4096;
+const RW: usize = PROT_READ | PROT_WRITE;
+const FIXED: usize = MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED;
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len = PAGE_SIZE * 3;
+    assert_eq!(mmap(start, len, RW), 0);
+    for i in start..start + len {
+        unsafe { *(i as *mut u8) = 0x5a };
+    }
+
+    // 不带 MAP_FIXED 时不能覆盖已有的映射
+    assert_eq!(
+        to_result(mmap(start + PAGE_SIZE, PAGE_SIZE, RW)),
+        Err(Errno::EEXIST)
+    );
+    // 替换中间一页，还可以越过原来映射的末尾
+    assert_eq!(mmap_with(start + PAGE_SIZE, PAGE_SIZE, RW, FIXED, -1, 0), 0);
+    assert_eq!(
+        mmap_with(start + PAGE_SIZE * 2, PAGE_SIZE * 2, RW, FIXED, -1, 0),
+        0
+    );
+    for i in start..start + PAGE_SIZE * 4 {
+        let expected = if i < start + PAGE_SIZE { 0x5a } else { 0 };
+        assert_eq!(unsafe { *(i as *const u8) }, expected);
+    }
+
+    // 不能映射第 0 页
+    assert_eq!(
+        to_result(mmap_with(0, PAGE_SIZE, RW, FIXED, -1, 0)),
+        Err(Errno::EPERM)
+    );
+    // 地址未对齐
+    assert_eq!(
+        to_result(mmap_with(start + 1, PAGE_SIZE, RW, FIXED, -1, 0)),
+        Err(Errno::EINVAL)
+    );
+
+    // 堆所在的页不能被替换
+    let brk = sbrk(PAGE_SIZE as i32) as usize;
+    let heap = brk & !(PAGE_SIZE - 1);
+    assert_eq!(
+        to_result(mmap_with(heap, PAGE_SIZE, RW, FIXED, -1, 0)),
+        Err(Errno::EEXIST)
+    );
+    sbrk(-(PAGE_SIZE as i32));
+
+    // 替换后的几段映射连在一起，可以一次取消
+    assert_eq!(munmap(start, PAGE_SIZE * 4), 0);
+    assert_eq!(mmap(start, PAGE_SIZE * 4, RW), 0);
+    println!("Test mmap1 OK!");
+    0
+}
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, mmap, mprotect, to_result, waitpid, Errno, PROT_EXEC, PROT_READ, PROT_WRITE,
+};
+
+// 理想结果：mprotect 修改一段区域中部分页的权限，写只读页的进程被杀死；fork 后仍然写时复制
+
+const PAGE_SIZE: usize = 4096;
+const RW: usize = PROT_READ | PROT_WRITE;
+
+/// 在子进程中向 `addr` 写一个字节，返回子进程的退出码。
+fn write_in_child(addr: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        unsafe { (addr as *mut u8).write_volatile(1) };
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len = PAGE_SIZE * 4;
+    assert_eq!(mmap(start, len, RW), 0);
+    // 只写第 0 页，其他页还没有分配
+    unsafe { *(start as *mut u8) = 7 };
+
+    // 中间两页改为只读，区域被拆成三段
+    assert_eq!(mprotect(start + PAGE_SIZE, PAGE_SIZE * 2, PROT_READ), 0);
+    assert_eq!(unsafe { *((start + PAGE_SIZE) as *const u8) }, 0);
+    assert_ne!(write_in_child(start + PAGE_SIZE), 0);
+    assert_ne!(write_in_child(start + PAGE_SIZE * 2), 0);
+    assert_eq!(write_in_child(start), 0);
+    assert_eq!(write_in_child(start + PAGE_SIZE * 3), 0);
+
+    // 已经分配的页也可以改为只读
+    assert_eq!(mprotect(start, PAGE_SIZE, PROT_READ), 0);
+    assert_eq!(unsafe { *(start as *const u8) }, 7);
+    assert_ne!(write_in_child(start), 0);
+
+    // 改回可写。子进程和父进程共享这一页，子进程写入时复制，不影响父进程
+    assert_eq!(mprotect(start, len, RW), 0);
+    let pid = fork();
+    if pid == 0 {
+        unsafe { *(start as *mut u8) = 8 };
+        assert_eq!(unsafe { *(start as *const u8) }, 8);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(unsafe { *(start as *const u8) }, 7);
+    unsafe { *(start as *mut u8) = 9 };
+    assert_eq!(unsafe { *(start as *const u8) }, 9);
+
+    // 没有映射的页
+    assert_eq!(
+        to_result(mprotect(start + len, PAGE_SIZE, PROT_READ)),
+        Err(Errno::ENOMEM)
+    );
+    assert_eq!(
+        to_result(mprotect(start + PAGE_SIZE * 3, PAGE_SIZE * 2, PROT_READ)),
+        Err(Errno::ENOMEM)
+    );
+    // 地址未对齐、prot 不合法
+    assert_eq!(
+        to_result(mprotect(start + 1, PAGE_SIZE, PROT_READ)),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(to_result(mprotect(start, PAGE_SIZE, 0)), Err(Errno::EINVAL));
+    assert_eq!(
+        to_result(mprotect(start, PAGE_SIZE, PROT_EXEC | 8)),
+        Err(Errno::EINVAL)
+    );
+    println!("Test mprotect OK!");
+    0
+}
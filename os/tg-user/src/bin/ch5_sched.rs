@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, sched_getinfo, set_priority, wait, SchedInfo};
+
+/// 每个子进程运行的毫秒数
+const MAX_TIME: isize = 2000;
+
+/// 以优先级 `prio` 忙等 `MAX_TIME` 毫秒，返回完成的循环次数。
+fn count_during(prio: isize) -> isize {
+    let start_time = get_time();
+    let mut acc = 0;
+    set_priority(prio);
+    loop {
+        acc += 1;
+        if acc % 400 == 0 && get_time() - start_time > MAX_TIME {
+            return acc;
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    for prio in 5..=10 {
+        if fork() == 0 {
+            let count = count_during(prio);
+            let mut info = SchedInfo::default();
+            assert_eq!(sched_getinfo(&mut info), 0);
+            assert_eq!(info.priority, prio as usize);
+            println!(
+                "policy = {}, priority = {}, count = {}, dispatches = {}, ticks = {}, run_time = {}, key = {}",
+                info.policy, prio, count, info.dispatches, info.ticks, info.run_time, info.key
+            );
+            exit(0);
+        }
+    }
+    let mut exit_code: i32 = 0;
+    for _ in 5..=10 {
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+    }
+    println!("Test sched_getinfo OK!");
+    0
+}
@@ -4,15 +4,15 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::set_priority;
+use user_lib::{set_priority, to_result, Errno};
 
 #[no_mangle]
 extern "C" fn main() -> i32 {
     assert_eq!(set_priority(10), 10);
     assert_eq!(set_priority(isize::MAX), isize::MAX);
-    assert_eq!(set_priority(0), -1);
-    assert_eq!(set_priority(1), -1);
-    assert_eq!(set_priority(-10), -1);
+    assert_eq!(to_result(set_priority(0)), Err(Errno::EINVAL));
+    assert_eq!(to_result(set_priority(1)), Err(Errno::EINVAL));
+    assert_eq!(to_result(set_priority(-10)), Err(Errno::EINVAL));
     println!("Test set_priority OK!");
     0
 }
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{exit, fork, getrlimit, setrlimit, to_result, waitpid, Errno, RLimit, RLIMIT_STACK};
+
+/// 递归 `depth` 层，每层在栈上占用 1 KiB 多。
+fn recurse(depth: usize) -> usize {
+    let mut buf = [0u8; 1024];
+    buf[depth % buf.len()] = depth as u8;
+    let buf = black_box(&mut buf);
+    if depth == 0 {
+        return buf[0] as usize;
+    }
+    recurse(depth - 1) + buf[depth % buf.len()] as usize
+}
+
+/// fork 一个递归 `depth` 层的子进程，返回它的退出码。
+fn recurse_in_child(depth: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        recurse(depth);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    exit_code
+}
+
+// 理想结果：用户栈按需增长到栈大小限制，超过限制的访问落在保护页上，进程被杀死
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let mut limit = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut limit), 0);
+    assert!(limit.cur >= 32 << 10 && limit.cur <= limit.max);
+    // 只支持栈大小
+    assert_eq!(
+        to_result(getrlimit(0, &mut RLimit::default())),
+        Err(Errno::EINVAL)
+    );
+
+    // 栈开始只有两页，用到 16 KiB 时按需增长
+    assert_eq!(recurse_in_child(16), 0);
+
+    // 软限制不能超过硬限制，硬限制不能调高
+    let bad = RLimit {
+        cur: limit.max + 4096,
+        max: limit.max,
+    };
+    assert_eq!(to_result(setrlimit(RLIMIT_STACK, &bad)), Err(Errno::EINVAL));
+    let bad = RLimit {
+        cur: limit.cur,
+        max: limit.max + 4096,
+    };
+    assert_eq!(to_result(setrlimit(RLIMIT_STACK, &bad)), Err(Errno::EPERM));
+
+    // 调低软限制后，子进程继承限制，栈用超了被杀死
+    let small = RLimit {
+        cur: 16 << 10,
+        max: limit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &small), 0);
+    let mut now = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut now), 0);
+    assert_eq!(now, small);
+    assert_ne!(recurse_in_child(32), 0);
+
+    // 软限制调到硬限制，栈可以一直增长到硬限制
+    let large = RLimit {
+        cur: limit.max,
+        max: limit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &large), 0);
+    assert_eq!(recurse_in_child(limit.max / 2048), 0);
+    // 超过硬限制就碰到保护页
+    assert_ne!(recurse_in_child(limit.max / 1024 + 16), 0);
+
+    assert_eq!(setrlimit(RLIMIT_STACK, &limit), 0);
+    println!("Test stack OK!");
+    0
+}
@@ -17,8 +17,8 @@ static TESTS: &[&str] = &[
 #[no_mangle]
 extern "C" fn main() -> i32 {
     let mut pid = [0isize; 6];
-    for (i, test) in TESTS.iter().enumerate() {
-        pid[i] = spawn(*test);
+    for (i, &test) in TESTS.iter().enumerate() {
+        pid[i] = spawn(test, &[test], &[]);
     }
     set_priority(4);
     for i in 0..6 {
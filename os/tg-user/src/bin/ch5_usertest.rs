@@ -22,9 +22,15 @@ static TESTS: &[&str] = &[
     "ch4_mmap3",
     "ch4_unmap",
     "ch4_unmap2",
+    "ch5_mmap0",
+    "ch5_mmap1",
+    "ch5_mprotect",
+    "ch5_madvise",
     "ch5_spawn0",
     "ch5_spawn1",
     "ch5_setprio",
+    "ch5_args",
+    "ch5_stack",
 ];
 
 static STEST: &str = "ch5_stride";
@@ -33,10 +39,10 @@ static STEST: &str = "ch5_stride";
 
 #[no_mangle]
 extern "C" fn main() -> i32 {
-    let mut pid = [0isize; 20];
+    let mut pid = [0isize; 24];
     for (i, &test) in TESTS.iter().enumerate() {
         println!("Usertests: Running {}", test);
-        pid[i] = spawn(test);
+        pid[i] = spawn(test, &[test], &[]);
     }
     let mut xstate: i32 = Default::default();
     for (i, &test) in TESTS.iter().enumerate() {
@@ -48,7 +54,7 @@ extern "C" fn main() -> i32 {
         assert_eq!(pid[i], wait_pid);
     }
     println!("Usertests: Running {}", STEST);
-    let spid = spawn(STEST);
+    let spid = spawn(STEST, &[STEST], &[]);
     xstate = Default::default();
     let wait_pid = waitpid(spid, &mut xstate);
     assert_eq!(spid, wait_pid);
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::{
+    close, mmap, mmap_with, msync, munmap, open, read, to_result, write, Errno, OpenFlags,
+    MAP_PRIVATE, MAP_SHARED, MS_SYNC, PROT_READ, PROT_WRITE,
+};
+
+// 理想结果：私有映射读到文件内容、修改不写回；共享映射的修改在 msync 和 munmap 时写回文件
+
+const PAGE_SIZE: usize = 4096;
+const RW: usize = PROT_READ | PROT_WRITE;
+const NAME: &str = "mmapfile\0";
+/// 读写文件用的缓冲区，映射在一起，物理上也连续
+const BUF: usize = 0x2000_0000;
+const LEN: usize = PAGE_SIZE * 2;
+
+fn buf() -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(BUF as *mut u8, LEN + PAGE_SIZE) }
+}
+
+fn mapped(addr: usize, len: usize) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) }
+}
+
+fn expected(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+/// 重新打开文件读出全部内容。
+fn read_file() -> &'static [u8] {
+    let fd = open(NAME, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, buf());
+    close(fd as usize);
+    &buf()[..len as usize]
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    assert_eq!(mmap(BUF, LEN + PAGE_SIZE, RW), 0);
+    for (i, byte) in buf()[..LEN].iter_mut().enumerate() {
+        *byte = expected(i);
+    }
+    let fd = open(NAME, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, &buf()[..LEN]), LEN as isize);
+
+    // 私有映射：读到文件内容，修改只属于自己
+    let start: usize = 0x1000_0000;
+    assert_eq!(mmap_with(start, LEN, RW, MAP_PRIVATE, fd, 0), 0);
+    let data = mapped(start, LEN);
+    assert!(data.iter().enumerate().all(|(i, &b)| b == expected(i)));
+    data[..16].fill(0xff);
+    assert_eq!(munmap(start, LEN), 0);
+    let file = read_file();
+    assert_eq!(file.len(), LEN);
+    assert!(file.iter().enumerate().all(|(i, &b)| b == expected(i)));
+
+    // 共享映射文件的第二页，msync 把修改写回文件
+    assert_eq!(
+        mmap_with(start, PAGE_SIZE, RW, MAP_SHARED, fd, PAGE_SIZE),
+        0
+    );
+    let data = mapped(start, PAGE_SIZE);
+    assert!(data
+        .iter()
+        .enumerate()
+        .all(|(i, &b)| b == expected(PAGE_SIZE + i)));
+    data[..16].fill(0xee);
+    assert_eq!(msync(start, PAGE_SIZE, MS_SYNC), 0);
+    assert!(read_file()[PAGE_SIZE..PAGE_SIZE + 16]
+        .iter()
+        .all(|&b| b == 0xee));
+    // munmap 也会写回
+    data[16..32].fill(0xdd);
+    assert_eq!(munmap(start, PAGE_SIZE), 0);
+    let file = read_file();
+    assert!(file[PAGE_SIZE + 16..PAGE_SIZE + 32]
+        .iter()
+        .all(|&b| b == 0xdd));
+    assert_eq!(file[0], expected(0));
+
+    // 超出文件末尾的部分读到 0，写入也不会让文件变长
+    assert_eq!(mmap_with(start, LEN, RW, MAP_SHARED, fd, PAGE_SIZE), 0);
+    let data = mapped(start, LEN);
+    assert_eq!(data[0], 0xee);
+    assert!(data[PAGE_SIZE..].iter().all(|&b| b == 0));
+    data[PAGE_SIZE] = 1;
+    assert_eq!(munmap(start, LEN), 0);
+    assert_eq!(read_file().len(), LEN);
+
+    // 文件描述符无效、偏移未对齐、只读打开的文件不能共享地写
+    assert_eq!(
+        to_result(mmap_with(start, PAGE_SIZE, RW, MAP_PRIVATE, 100, 0)),
+        Err(Errno::EBADF)
+    );
+    assert_eq!(
+        to_result(mmap_with(start, PAGE_SIZE, RW, MAP_PRIVATE, fd, 1)),
+        Err(Errno::EINVAL)
+    );
+    let rdonly = open(NAME, OpenFlags::RDONLY);
+    assert!(rdonly > 0);
+    assert_eq!(
+        to_result(mmap_with(start, PAGE_SIZE, RW, MAP_SHARED, rdonly, 0)),
+        Err(Errno::EACCES)
+    );
+    assert_eq!(
+        mmap_with(start, PAGE_SIZE, PROT_READ, MAP_SHARED, rdonly, 0),
+        0
+    );
+    assert_eq!(mapped(start, 1)[0], expected(0));
+    assert_eq!(munmap(start, PAGE_SIZE), 0);
+    close(rdonly as usize);
+    // 没有映射的页
+    assert_eq!(
+        to_result(msync(start, PAGE_SIZE, MS_SYNC)),
+        Err(Errno::ENOMEM)
+    );
+
+    close(fd as usize);
+    println!("Test mmap file OK!");
+    0
+}
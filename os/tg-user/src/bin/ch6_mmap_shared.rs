@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, mmap, mmap_with, sched_yield, waitpid, MAP_ANONYMOUS, MAP_SHARED, PROT_READ,
+    PROT_WRITE,
+};
+
+// 理想结果：匿名共享映射在 fork 之后仍然是父子进程共享的内存，私有映射则各自一份
+
+const PAGE_SIZE: usize = 4096;
+const RW: usize = PROT_READ | PROT_WRITE;
+
+fn load(addr: usize) -> usize {
+    unsafe { (addr as *const usize).read_volatile() }
+}
+
+fn store(addr: usize, value: usize) {
+    unsafe { (addr as *mut usize).write_volatile(value) }
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    let shared: usize = 0x1000_0000;
+    let private: usize = 0x1100_0000;
+    assert_eq!(
+        mmap_with(shared, PAGE_SIZE * 2, RW, MAP_SHARED | MAP_ANONYMOUS, -1, 0),
+        0
+    );
+    assert_eq!(mmap(private, PAGE_SIZE, RW), 0);
+    store(shared, 1);
+    store(private, 1);
+
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(load(shared), 1);
+        // 等父进程在 fork 之后写入
+        while load(shared + PAGE_SIZE) == 0 {
+            sched_yield();
+        }
+        assert_eq!(load(shared + PAGE_SIZE), 2);
+        store(shared, 3);
+        store(private, 3);
+        exit(0);
+    }
+    store(shared + PAGE_SIZE, 2);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // 子进程对共享映射的写入父进程看得到，对私有映射的写入看不到
+    assert_eq!(load(shared), 3);
+    assert_eq!(load(private), 1);
+    println!("Test mmap shared OK!");
+    0
+}
@@ -24,6 +24,8 @@ static TESTS: &[&str] = &[
     "ch4_unmap2",
     "ch5_spawn0",
     "ch5_spawn1",
+    "ch5_args",
+    "ch5_stack",
     "12forktest",
     "14forktest2",
     "fork_exit",
@@ -34,15 +36,20 @@ static TESTS: &[&str] = &[
     "ch6_file1",
     "ch6_file2",
     "ch6_file3",
+    "ch6_mmap_file",
+    "ch6_mmap_shared",
+    "ch5_mmap0",
+    "ch5_mmap1",
+    "ch5_mprotect",
 ];
 
 /// 辅助测例，运行所有其他测例。
 
 #[no_mangle]
 extern "C" fn main() -> i32 {
-    for test in TESTS {
+    for &test in TESTS {
         println!("Usertests: Running {}", test);
-        let pid = spawn(*test);
+        let pid = spawn(test, &[test], &[]);
         let mut xstate: i32 = Default::default();
         let wait_pid = waitpid(pid, &mut xstate);
         assert_eq!(pid, wait_pid);
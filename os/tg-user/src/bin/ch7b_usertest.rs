@@ -34,7 +34,7 @@ extern "C" fn main() -> i32 {
         println!("Usertests: Running {}", test);
         let pid = fork();
         if pid == 0 {
-            exec(test);
+            exec(test, &[test], &[]);
             panic!("unreachable!");
         } else {
             pids[i] = pid;
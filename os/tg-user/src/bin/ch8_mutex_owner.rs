@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    enable_deadlock_detect, exit, mutex_create, mutex_lock, mutex_unlock, thread_create,
+    to_result, waittid, Errno,
+};
+
+// 理想结果：只有持有锁的线程能解锁，多余的解锁不会让死锁检测多出一个可用的锁
+
+static mut MUTEX_ID: usize = 0;
+
+fn unlock_other() -> isize {
+    let ret = to_result(mutex_unlock(unsafe { MUTEX_ID }));
+    exit(if ret == Err(Errno::EPERM) { 0 } else { -1 })
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    enable_deadlock_detect(true);
+    let mid = mutex_create(true) as usize;
+    unsafe { MUTEX_ID = mid };
+    // 解一把空闲的锁
+    assert_eq!(to_result(mutex_unlock(mid)), Err(Errno::EPERM));
+    // 解别的线程持有的锁
+    assert_eq!(mutex_lock(mid), 0);
+    let tid = thread_create(unlock_other as *const () as usize, 0);
+    assert_eq!(waittid(tid as usize), 0);
+    assert_eq!(mutex_unlock(mid), 0);
+    assert_eq!(to_result(mutex_unlock(mid)), Err(Errno::EPERM));
+    // 锁仍然只有一个，再次获取自己持有的锁会被判定为死锁
+    assert_eq!(mutex_lock(mid), 0);
+    assert_eq!(mutex_lock(mid), -0xdead);
+    assert_eq!(mutex_unlock(mid), 0);
+    println!("mutex owner test OK!");
+    0
+}
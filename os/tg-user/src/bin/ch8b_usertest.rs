@@ -23,6 +23,7 @@ const TESTS: &[&str] = &[
     "race_adder_mutex_blocking",
     "sync_sem",
     "test_condvar",
+    "ch8_mutex_owner",
     "threads",
     "threads_arg",
 ];
@@ -38,7 +39,7 @@ extern "C" fn main() -> i32 {
         println!("Usertests: Running {}", test);
         let pid = fork();
         if pid == 0 {
-            exec(test);
+            exec(test, &[test], &[]);
             panic!("unreachable!");
         } else {
             pids[i] = pid;
@@ -4,11 +4,11 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{fork, getpid, wait};
+use user_lib::{fork, getpid, to_result, wait, Errno};
 
 #[no_mangle]
 pub extern "C" fn main() -> i32 {
-    assert_eq!(wait(&mut 0i32), -1);
+    assert_eq!(to_result(wait(&mut 0i32)), Err(Errno::ECHILD));
     println!("sys_wait without child process test passed!");
     println!("parent start, pid = {}!", getpid());
     let pid = fork();
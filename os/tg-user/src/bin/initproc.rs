@@ -18,12 +18,12 @@ extern "C" fn main() -> i32 {
             "-8" => "ch8b_usertest",
             _ => "user_shell",
         };
-        exec(target);
+        exec(target, &[target], &[]);
     } else {
         loop {
             let mut exit_code: i32 = 0;
             let pid = wait(&mut exit_code);
-            if pid == -1 {
+            if pid < 0 {
                 break;
             }
         }
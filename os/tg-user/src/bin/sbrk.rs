@@ -5,7 +5,7 @@
 extern crate user_lib;
 
 use core::ptr::slice_from_raw_parts_mut;
-use user_lib::sbrk;
+use user_lib::{sbrk, to_result, Errno};
 
 #[no_mangle]
 pub extern "C" fn main() -> i32 {
@@ -35,7 +35,7 @@ pub extern "C" fn main() -> i32 {
     println!("11 page DEALLOCATED,  break point = {:x}", brk);
     println!("try DEALLOCATED more one page, should be failed.");
     let ret = sbrk(PAGE_SIZE as i32 * -1);
-    if ret != -1 {
+    if to_result(ret) != Err(Errno::ENOMEM) {
         println!("Test sbrk failed!");
         return -1;
     }
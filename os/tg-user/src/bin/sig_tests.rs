@@ -84,8 +84,15 @@ fn user_sig_test_restore() {
 }
 
 fn kernel_sig_test_ignore() {
+    // SIGSTOP 不能被屏蔽，设置后读回的屏蔽字里没有它
     sigprocmask(1 << SignalNo::SIGSTOP as usize);
-    if kill(getpid(), SignalNo::SIGSTOP) < 0 {
+    if sigprocmask(0) & (1 << SignalNo::SIGSTOP as usize) != 0 {
+        println!("SIGSTOP should not be blocked");
+        exit(-1);
+    }
+    // 被屏蔽的信号保持未决，默认动作不会终止进程
+    sigprocmask(1 << SignalNo::SIGUSR2 as usize);
+    if kill(getpid(), SignalNo::SIGUSR2) < 0 {
         println!("kill faild\n");
         exit(-1);
     }
@@ -12,7 +12,7 @@ const CR: u8 = 0x0du8;
 const DL: u8 = 0x7fu8;
 const BS: u8 = 0x08u8;
 
-use alloc::string::String;
+use alloc::{string::String, vec::Vec};
 use user_lib::{exec, fork, getchar, waitpid};
 
 #[no_mangle]
@@ -30,7 +30,9 @@ pub extern "C" fn main() -> i32 {
                     let pid = fork();
                     if pid == 0 {
                         // child process
-                        if exec(line.as_str()) == -1 {
+                        // 按空白分成程序名和参数
+                        let args: Vec<&str> = line.split_whitespace().collect();
+                        if args.is_empty() || exec(args[0], &args, &[]) < 0 {
                             println!("Error when executing!");
                             return -4;
                         }
@@ -0,0 +1,92 @@
+/// 系统调用错误码，编号和变体名与 Linux 一致。
+///
+/// 内核在系统调用失败时返回错误码的相反数，用 [`to_result`](crate::to_result) 转换成 `Result`。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(isize)]
+pub enum Errno {
+    /// 操作不允许
+    EPERM = 1,
+    /// 文件或目录不存在
+    ENOENT = 2,
+    /// 进程不存在
+    ESRCH = 3,
+    /// 系统调用被中断
+    EINTR = 4,
+    /// I/O 错误
+    EIO = 5,
+    /// 参数列表过长
+    E2BIG = 7,
+    /// 可执行文件格式错误
+    ENOEXEC = 8,
+    /// 文件描述符无效
+    EBADF = 9,
+    /// 没有子进程
+    ECHILD = 10,
+    /// 资源暂时不可用，稍后重试
+    EAGAIN = 11,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 权限不足
+    EACCES = 13,
+    /// 地址无效
+    EFAULT = 14,
+    /// 资源忙
+    EBUSY = 16,
+    /// 已经存在
+    EEXIST = 17,
+    /// 不是目录
+    ENOTDIR = 20,
+    /// 是目录
+    EISDIR = 21,
+    /// 参数无效
+    EINVAL = 22,
+    /// 打开的文件过多
+    EMFILE = 24,
+    /// 不是终端
+    ENOTTY = 25,
+    /// 管道另一端已关闭
+    EPIPE = 32,
+    /// 会导致死锁
+    EDEADLK = 35,
+    /// 系统调用未实现
+    ENOSYS = 38,
+}
+
+impl Errno {
+    /// 编号为 `code` 的错误码，不认识的编号返回 `None`。
+    pub const fn from_code(code: isize) -> Option<Self> {
+        Some(match code {
+            1 => Self::EPERM,
+            2 => Self::ENOENT,
+            3 => Self::ESRCH,
+            4 => Self::EINTR,
+            5 => Self::EIO,
+            7 => Self::E2BIG,
+            8 => Self::ENOEXEC,
+            9 => Self::EBADF,
+            10 => Self::ECHILD,
+            11 => Self::EAGAIN,
+            12 => Self::ENOMEM,
+            13 => Self::EACCES,
+            14 => Self::EFAULT,
+            16 => Self::EBUSY,
+            17 => Self::EEXIST,
+            20 => Self::ENOTDIR,
+            21 => Self::EISDIR,
+            22 => Self::EINVAL,
+            24 => Self::EMFILE,
+            25 => Self::ENOTTY,
+            32 => Self::EPIPE,
+            35 => Self::EDEADLK,
+            38 => Self::ENOSYS,
+            _ => return None,
+        })
+    }
+
+    /// 错误码的编号。
+    #[inline]
+    pub const fn code(self) -> isize {
+        self as isize
+    }
+}
@@ -1,17 +1,35 @@
 #![no_std]
 
+mod errno;
 mod heap;
 
 extern crate alloc;
 
+use alloc::vec::Vec;
+use core::{
+    ffi::CStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use tg_console::log;
 
+pub use errno::Errno;
 pub use tg_console::{print, println};
 pub use tg_syscall::*;
 
+/// 程序参数 `argv` 数组的地址，由 `_start` 记下。
+static ARGV: AtomicUsize = AtomicUsize::new(0);
+/// 环境变量 `envp` 数组的地址，由 `_start` 记下。
+static ENVP: AtomicUsize = AtomicUsize::new(0);
+
+/// 程序入口。
+///
+/// 内核按 System V 约定在用户栈上排布参数和环境变量，并把 `argc`、`argv`、`envp` 放在 `a0`、`a1`、`a2` 中。
+/// 不传参数的内核（第二到四章）把它们清零，此时 [`args`] 和 [`env`] 为空。
 #[no_mangle]
 #[link_section = ".text.entry"]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(_argc: usize, argv: usize, envp: usize) -> ! {
+    ARGV.store(argv, Ordering::Relaxed);
+    ENVP.store(envp, Ordering::Relaxed);
     tg_console::init_console(&Console);
     tg_console::set_log_level(option_env!("LOG"));
     heap::init();
@@ -37,6 +55,60 @@ fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
     unreachable!()
 }
 
+/// 以 0 结尾的字符串指针数组上的迭代器，见 [`args`] 和 [`env`]。
+pub struct Args {
+    ptr: *const *const u8,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ptr.is_null() {
+            return None;
+        }
+        // SAFETY: 内核保证数组以空指针结尾，每个元素指向以 0 结尾的字符串，它们在程序运行期间一直有效
+        let s = unsafe { *self.ptr };
+        if s.is_null() {
+            return None;
+        }
+        self.ptr = unsafe { self.ptr.add(1) };
+        Some(
+            unsafe { CStr::from_ptr(s.cast()) }
+                .to_str()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// 程序参数，第一个通常是程序名。不是 UTF-8 的参数表示为空串。
+pub fn args() -> Args {
+    Args {
+        ptr: ARGV.load(Ordering::Relaxed) as _,
+    }
+}
+
+/// 环境变量，每一项形如 `KEY=VALUE`。
+pub fn env() -> Args {
+    Args {
+        ptr: ENVP.load(Ordering::Relaxed) as _,
+    }
+}
+
+/// 把系统调用的返回值转换成 `Result`：非负数是成功的结果，负数是错误码的相反数。
+///
+/// 不在 [`Errno`] 中的负数（例如死锁检测返回的 `-0xdead`）视为 [`Errno::EINVAL`]。
+pub fn to_result(ret: isize) -> Result<usize, Errno> {
+    if ret >= 0 {
+        Ok(ret as usize)
+    } else {
+        Err(ret
+            .checked_neg()
+            .and_then(Errno::from_code)
+            .unwrap_or(Errno::EINVAL))
+    }
+}
+
 pub fn getchar() -> u8 {
     let mut c = [0u8; 1];
     read(STDIN, &mut c);
@@ -104,7 +176,7 @@ pub fn pipe_read(pipe_fd: usize, buffer: &mut [u8]) -> isize {
             return total_read as isize;
         }
         let ret = read(pipe_fd, &mut buffer[total_read..]);
-        if ret == -2 {
+        if ret == -Errno::EAGAIN.code() {
             // 暂时无数据，让出 CPU 后重试
             sched_yield();
             continue;
@@ -130,7 +202,7 @@ pub fn pipe_write(pipe_fd: usize, buffer: &[u8]) -> isize {
             return total_write as isize;
         }
         let ret = write(pipe_fd, &buffer[total_write..]);
-        if ret == -2 {
+        if ret == -Errno::EAGAIN.code() {
             // 缓冲区满，让出 CPU 后重试
             sched_yield();
             continue;
@@ -142,3 +214,251 @@ pub fn pipe_write(pipe_fd: usize, buffer: &[u8]) -> isize {
         }
     }
 }
+
+/// `sched_getinfo` 的系统调用号。
+const SYSCALL_SCHED_GETINFO: usize = 420;
+
+/// 调度策略编号，见 [`SchedInfo::policy`]。
+pub mod sched_policy {
+    /// 时间片轮转
+    pub const ROUND_ROBIN: usize = 0;
+    /// stride 调度
+    pub const STRIDE: usize = 1;
+    /// 多级反馈队列
+    pub const MLFQ: usize = 2;
+    /// 彩票调度
+    pub const LOTTERY: usize = 3;
+    /// 按虚拟运行时间调度
+    pub const CFS: usize = 4;
+}
+
+/// 当前进程的调度信息。
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct SchedInfo {
+    /// 内核使用的调度策略，取值见 [`sched_policy`]
+    pub policy: usize,
+    /// 优先级
+    pub priority: usize,
+    /// 被调度运行的次数
+    pub dispatches: usize,
+    /// 用完时间片被时钟中断打断的次数
+    pub ticks: usize,
+    /// 在用户态运行的总时间（`time` 计数）
+    pub run_time: u64,
+    /// 策略相关的调度参数：stride、虚拟运行时间、多级反馈队列的级别或彩票数，时间片轮转为 0
+    pub key: u64,
+}
+
+/// 读取当前进程的调度信息，成功返回 0。
+pub fn sched_getinfo(info: &mut SchedInfo) -> isize {
+    let ret: isize;
+    // SAFETY: 内核只写 `info` 指向的 `SchedInfo`
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") info as *mut SchedInfo as usize => ret,
+            in("a7") SYSCALL_SCHED_GETINFO,
+        );
+    }
+    ret
+}
+
+/// `getrlimit` 和 `setrlimit` 的系统调用号。
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+
+/// 栈大小的资源编号，内核目前只支持这一种资源。
+pub const RLIMIT_STACK: usize = 3;
+
+/// 资源限制，布局与 C 的 `struct rlimit` 一致。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct RLimit {
+    /// 软限制
+    pub cur: usize,
+    /// 硬限制，软限制不能超过它
+    pub max: usize,
+}
+
+/// 读取资源 `resource` 的限制，成功返回 0。
+pub fn getrlimit(resource: usize, rlim: &mut RLimit) -> isize {
+    let ret: isize;
+    // SAFETY: 内核只写 `rlim` 指向的 `RLimit`
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") resource => ret,
+            in("a1") rlim as *mut RLimit as usize,
+            in("a7") SYSCALL_GETRLIMIT,
+        );
+    }
+    ret
+}
+
+/// 设置资源 `resource` 的限制，成功返回 0。软限制超过硬限制返回 `-EINVAL`，调高硬限制返回 `-EPERM`。
+pub fn setrlimit(resource: usize, rlim: &RLimit) -> isize {
+    let ret: isize;
+    // SAFETY: 内核只读取 `rlim` 指向的 `RLimit`
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") resource => ret,
+            in("a1") rlim as *const RLimit as usize,
+            in("a7") SYSCALL_SETRLIMIT,
+        );
+    }
+    ret
+}
+
+/// 内存映射相关的系统调用号。
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_MADVISE: usize = 233;
+
+/// `mmap` 和 `mprotect` 的权限：可读、可写、可执行。
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+/// `mmap` 的标志：共享映射、私有映射、固定地址、匿名映射。
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// `msync` 的标志：异步写回、让其他映射失效、同步写回。
+pub const MS_ASYNC: usize = 1;
+pub const MS_INVALIDATE: usize = 2;
+pub const MS_SYNC: usize = 4;
+
+/// `madvise` 的建议：不再需要这段内存，已经分配的页可以丢掉。
+pub const MADV_DONTNEED: usize = 4;
+
+/// 带标志的 `mmap`。`addr` 为 0 时由内核挑选地址，成功返回映射的地址；指定地址时成功返回 0，
+/// 与 [`mmap`] 一致。`MAP_FIXED` 替换掉 `addr` 处原有的映射。
+///
+/// 不带 `MAP_ANONYMOUS` 时映射文件 `fd` 从 `offset` 开始的内容（第六章起）。
+pub fn mmap_with(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: isize,
+    offset: usize,
+) -> isize {
+    let ret: isize;
+    // SAFETY: 只改变进程的地址空间，不读写用户内存
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") addr => ret,
+            in("a1") len,
+            in("a2") prot,
+            in("a3") flags,
+            in("a4") fd,
+            in("a5") offset,
+            in("a7") SYSCALL_MMAP,
+        );
+    }
+    ret
+}
+
+/// 把 `[addr, addr + len)` 的权限改为 `prot`，成功返回 0。范围中有没有映射的页时返回 `-ENOMEM`。
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let ret: isize;
+    // SAFETY: 只改变进程的地址空间，不读写用户内存
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") addr => ret,
+            in("a1") len,
+            in("a2") prot,
+            in("a7") SYSCALL_MPROTECT,
+        );
+    }
+    ret
+}
+
+/// 把 `[addr, addr + len)` 中文件共享映射的修改写回文件，成功返回 0。范围中有没有映射的页时返回 `-ENOMEM`。
+pub fn msync(addr: usize, len: usize, flags: usize) -> isize {
+    let ret: isize;
+    // SAFETY: 内核只读取映射的页
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") addr => ret,
+            in("a1") len,
+            in("a2") flags,
+            in("a7") SYSCALL_MSYNC,
+        );
+    }
+    ret
+}
+
+/// 告诉内核怎样使用 `[addr, addr + len)`，成功返回 0。`MADV_DONTNEED` 之后再读这段内存得到 0。
+pub fn madvise(addr: usize, len: usize, advice: usize) -> isize {
+    let ret: isize;
+    // SAFETY: 只改变进程的地址空间，不读写用户内存
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") addr => ret,
+            in("a1") len,
+            in("a2") advice,
+            in("a7") SYSCALL_MADVISE,
+        );
+    }
+    ret
+}
+
+/// 按 `execve` 的约定把 `strings` 排成以 0 结尾的字符串，返回字符串缓冲区和以 0 结尾的指针数组。
+///
+/// 指针指向返回的缓冲区，两者要一起保留到系统调用返回。
+fn cstr_array(strings: &[&str]) -> (Vec<u8>, Vec<usize>) {
+    let mut buf = Vec::new();
+    let mut offsets = Vec::with_capacity(strings.len());
+    for s in strings {
+        offsets.push(buf.len());
+        buf.extend_from_slice(s.as_bytes());
+        buf.push(0);
+    }
+    let base = buf.as_ptr() as usize;
+    let mut ptrs: Vec<usize> = offsets.into_iter().map(|offset| base + offset).collect();
+    ptrs.push(0);
+    (buf, ptrs)
+}
+
+/// 带参数的 `exec` 和 `spawn` 的系统调用号。
+const SYSCALL_EXECVE_ARGS: usize = 421;
+const SYSCALL_SPAWN_ARGS: usize = 422;
+
+/// 发起带参数的 `exec` 或 `spawn`。
+fn exec_with_args(id: usize, path: &str, argv: &[&str], envp: &[&str]) -> isize {
+    let (_argv_buf, argv) = cstr_array(argv);
+    let (_envp_buf, envp) = cstr_array(envp);
+    let ret: isize;
+    // SAFETY: 内核只读取 `path` 和两个指针数组，它们在系统调用返回前一直有效
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") path.as_ptr() as usize => ret,
+            in("a1") path.len(),
+            in("a2") argv.as_ptr() as usize,
+            in("a3") envp.as_ptr() as usize,
+            in("a7") id,
+        );
+    }
+    ret
+}
+
+/// 以参数 `argv` 和环境变量 `envp` 执行应用 `path`，成功时不返回，失败返回错误码的相反数。
+pub fn exec(path: &str, argv: &[&str], envp: &[&str]) -> isize {
+    exec_with_args(SYSCALL_EXECVE_ARGS, path, argv, envp)
+}
+
+/// 以参数 `argv` 和环境变量 `envp` 运行应用 `path` 创建子进程，返回子进程的 pid 或错误码的相反数。
+pub fn spawn(path: &str, argv: &[&str], envp: &[&str]) -> isize {
+    exec_with_args(SYSCALL_SPAWN_ARGS, path, argv, envp)
+}